@@ -0,0 +1,555 @@
+/// Actors
+///
+/// `channels.rs` moves values between threads, but every thread is an ad-hoc closure. An actor
+/// gives that pattern a shape:
+/// - the actor owns its state, nobody else can touch it, so no `Mutex` is needed
+/// - the only way to talk to it is a message sent to its mailbox (an `mpsc` channel)
+/// - it handles one message at a time on its own thread
+///
+/// A request that needs an answer carries a `ReplyTo` (the sending half of a one-shot channel)
+/// inside the message, and the caller waits on the receiving half.
+///
+/// A supervisor starts actors and watches them. When an actor panics, its state is thrown away
+/// and a fresh one is built by a factory closure, while the mailbox (and every `Addr` to it)
+/// stays the same:
+/// - `OneForOne`: only the crashed actor is restarted
+/// - `AllForOne`: every child is stopped and restarted, for actors which depend on each other
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub fn run() {
+    one_vs_one();
+    multi_vs_one();
+    request_response();
+    supervised();
+}
+
+pub trait Actor: Send + 'static {
+    type Msg: Send + 'static;
+
+    fn handle(&mut self, msg: Self::Msg);
+}
+
+enum Envelope<M> {
+    Msg(M),
+    // stop the incarnation with this generation, later incarnations ignore it
+    Stop(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ActorError {
+    /// The actor is gone and its mailbox is closed.
+    Stopped,
+    /// The actor dropped the reply channel, usually because it panicked on the request.
+    NoReply,
+    Timeout,
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActorError::Stopped => write!(f, "actor stopped"),
+            ActorError::NoReply => write!(f, "actor did not reply"),
+            ActorError::Timeout => write!(f, "actor did not reply in time"),
+        }
+    }
+}
+
+/// Where the answer of a request goes.
+pub struct ReplyTo<R> {
+    tx: Sender<R>,
+}
+
+impl<R> ReplyTo<R> {
+    /// The caller may have given up waiting already, which is fine.
+    pub fn send(self, value: R) {
+        let _ = self.tx.send(value);
+    }
+}
+
+/// Handle used to send messages to an actor, cheap to clone.
+pub struct Addr<M> {
+    tx: Sender<Envelope<M>>,
+}
+
+impl<M> Clone for Addr<M> {
+    fn clone(&self) -> Addr<M> {
+        Addr { tx: self.tx.clone() }
+    }
+}
+
+impl<M: Send + 'static> Addr<M> {
+    /// Fire and forget.
+    pub fn send(&self, msg: M) -> Result<(), ActorError> {
+        self.tx.send(Envelope::Msg(msg)).map_err(|_| ActorError::Stopped)
+    }
+
+    /// Send the message built by `make` and wait for the actor to answer through the `ReplyTo`.
+    pub fn ask<R, F>(&self, make: F) -> Result<R, ActorError>
+        where F: FnOnce(ReplyTo<R>) -> M {
+        let (tx, rx) = mpsc::channel();
+        self.send(make(ReplyTo { tx }))?;
+        rx.recv().map_err(|_| ActorError::NoReply)
+    }
+
+    pub fn ask_timeout<R, F>(&self, timeout: Duration, make: F) -> Result<R, ActorError>
+        where F: FnOnce(ReplyTo<R>) -> M {
+        let (tx, rx) = mpsc::channel();
+        self.send(make(ReplyTo { tx }))?;
+        rx.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => ActorError::Timeout,
+            RecvTimeoutError::Disconnected => ActorError::NoReply,
+        })
+    }
+}
+
+/// Start an unsupervised actor on its own thread. It stops when every `Addr` is dropped,
+/// or with a panic if a message makes it panic.
+pub fn spawn<A: Actor>(mut actor: A) -> (Addr<A::Msg>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+
+    let handle = thread::spawn(move || {
+        for envelope in rx {
+            match envelope {
+                Envelope::Msg(msg) => actor.handle(msg),
+                Envelope::Stop(_) => break,
+            }
+        }
+    });
+
+    (Addr { tx }, handle)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    OneForOne,
+    AllForOne,
+}
+
+enum Event {
+    Crashed { child: usize, generation: usize },
+    Shutdown,
+}
+
+/// Type-erased supervised actor, so that children of different types fit in one `Vec`.
+trait Child: Send {
+    fn start(&mut self, index: usize, events: Sender<Event>);
+    fn stop(&mut self);
+    fn generation(&self) -> usize;
+}
+
+struct ActorChild<A: Actor> {
+    factory: Box<dyn Fn() -> A + Send>,
+    // shared by all incarnations, so queued messages survive a restart
+    mailbox: Arc<Mutex<Receiver<Envelope<A::Msg>>>>,
+    tx: Sender<Envelope<A::Msg>>,
+    handle: Option<JoinHandle<()>>,
+    generation: usize,
+}
+
+impl<A: Actor> Child for ActorChild<A> {
+    fn start(&mut self, index: usize, events: Sender<Event>) {
+        self.generation += 1;
+
+        let generation = self.generation;
+        let mailbox = Arc::clone(&self.mailbox);
+        let mut actor = (self.factory)();
+
+        self.handle = Some(thread::spawn(move || loop {
+            let envelope = mailbox.lock().unwrap().recv();
+
+            match envelope {
+                Ok(Envelope::Msg(msg)) => {
+                    // the message that caused the panic is lost, its `ReplyTo` is dropped with it
+                    if panic::catch_unwind(AssertUnwindSafe(|| actor.handle(msg))).is_err() {
+                        let _ = events.send(Event::Crashed { child: index, generation });
+                        return;
+                    }
+                }
+                Ok(Envelope::Stop(g)) if g == generation => return,
+                Ok(Envelope::Stop(_)) => {}
+                Err(_) => return,
+            }
+        }));
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if !handle.is_finished() {
+                let _ = self.tx.send(Envelope::Stop(self.generation));
+            }
+            let _ = handle.join();
+        }
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+pub struct Supervisor {
+    strategy: Strategy,
+    max_restarts: usize,
+    children: Vec<Box<dyn Child>>,
+    events_tx: Sender<Event>,
+    events_rx: Receiver<Event>,
+}
+
+/// Running supervisor, `shutdown` stops every child and returns how many restarts happened.
+pub struct SupervisorHandle {
+    events_tx: Sender<Event>,
+    handle: JoinHandle<usize>,
+}
+
+impl Supervisor {
+    pub fn new(strategy: Strategy) -> Supervisor {
+        let (events_tx, events_rx) = mpsc::channel();
+
+        Supervisor { strategy, max_restarts: 10, children: vec![], events_tx, events_rx }
+    }
+
+    /// Give up (and stop every child) after that many restarts.
+    pub fn max_restarts(mut self, max_restarts: usize) -> Supervisor {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Start a child, `factory` builds its initial state and is called again on every restart.
+    pub fn spawn<A, F>(&mut self, factory: F) -> Addr<A::Msg>
+        where A: Actor, F: Fn() -> A + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        let mut child = ActorChild {
+            factory: Box::new(factory),
+            mailbox: Arc::new(Mutex::new(rx)),
+            tx: tx.clone(),
+            handle: None,
+            generation: 0,
+        };
+
+        child.start(self.children.len(), self.events_tx.clone());
+        self.children.push(Box::new(child));
+
+        Addr { tx }
+    }
+
+    /// Watch the children on a separate thread.
+    pub fn start(self) -> SupervisorHandle {
+        let events_tx = self.events_tx.clone();
+        let handle = thread::spawn(move || self.watch());
+
+        SupervisorHandle { events_tx, handle }
+    }
+
+    fn watch(mut self) -> usize {
+        let mut restarts = 0;
+
+        for event in self.events_rx.iter() {
+            let (child, generation) = match event {
+                Event::Crashed { child, generation } => (child, generation),
+                Event::Shutdown => break,
+            };
+
+            // an incarnation already replaced by an all-for-one restart
+            if self.children[child].generation() != generation {
+                continue;
+            }
+
+            if restarts == self.max_restarts {
+                println!("supervisor: too many restarts, giving up");
+                break;
+            }
+            restarts += 1;
+
+            match self.strategy {
+                Strategy::OneForOne => {
+                    self.children[child].stop();
+                    self.children[child].start(child, self.events_tx.clone());
+                }
+                Strategy::AllForOne => {
+                    for c in self.children.iter_mut() {
+                        c.stop();
+                    }
+                    for (index, c) in self.children.iter_mut().enumerate() {
+                        c.start(index, self.events_tx.clone());
+                    }
+                }
+            }
+        }
+
+        for c in self.children.iter_mut() {
+            c.stop();
+        }
+
+        restarts
+    }
+}
+
+impl SupervisorHandle {
+    pub fn shutdown(self) -> usize {
+        let _ = self.events_tx.send(Event::Shutdown);
+        self.handle.join().unwrap()
+    }
+}
+
+// The producer / consumer demos of `channels.rs` as cooperating actors
+
+enum ConsumerMsg {
+    Got(String),
+    Received(ReplyTo<Vec<String>>),
+}
+
+#[derive(Default)]
+struct Consumer {
+    received: Vec<String>,
+}
+
+impl Actor for Consumer {
+    type Msg = ConsumerMsg;
+
+    fn handle(&mut self, msg: ConsumerMsg) {
+        match msg {
+            ConsumerMsg::Got(val) => {
+                println!("Got: {}", val);
+                self.received.push(val);
+            }
+            ConsumerMsg::Received(reply) => reply.send(self.received.clone()),
+        }
+    }
+}
+
+enum ProducerMsg {
+    Produce(Vec<String>),
+    // answered once every value before it was sent, lets the caller wait for the producer
+    Flush(ReplyTo<()>),
+}
+
+struct Producer {
+    consumer: Addr<ConsumerMsg>,
+}
+
+impl Actor for Producer {
+    type Msg = ProducerMsg;
+
+    fn handle(&mut self, msg: ProducerMsg) {
+        match msg {
+            ProducerMsg::Produce(vals) => {
+                for val in vals {
+                    self.consumer.send(ConsumerMsg::Got(val)).unwrap();
+                }
+            }
+            ProducerMsg::Flush(reply) => reply.send(()),
+        }
+    }
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(String::from).collect()
+}
+
+fn one_vs_one() {
+    let (consumer, _) = spawn(Consumer::default());
+    let (producer, _) = spawn(Producer { consumer: consumer.clone() });
+
+    producer.send(ProducerMsg::Produce(words("hi from the thread"))).unwrap();
+    producer.ask(ProducerMsg::Flush).unwrap();
+
+    println!("consumer received {:?}", consumer.ask(ConsumerMsg::Received).unwrap());
+}
+
+fn multi_vs_one() {
+    let (consumer, _) = spawn(Consumer::default());
+    let (first, _) = spawn(Producer { consumer: consumer.clone() });
+    let (second, _) = spawn(Producer { consumer: consumer.clone() });
+
+    first.send(ProducerMsg::Produce(words("hi from the thread"))).unwrap();
+    second.send(ProducerMsg::Produce(words("more messages for you"))).unwrap();
+    first.ask(ProducerMsg::Flush).unwrap();
+    second.ask(ProducerMsg::Flush).unwrap();
+
+    println!("consumer received {} messages", consumer.ask(ConsumerMsg::Received).unwrap().len());
+}
+
+enum CounterMsg {
+    Add(i64),
+    Get(ReplyTo<i64>),
+    // a bug we want the supervisor to recover from
+    Crash,
+}
+
+#[derive(Default)]
+struct Counter {
+    total: i64,
+}
+
+impl Actor for Counter {
+    type Msg = CounterMsg;
+
+    fn handle(&mut self, msg: CounterMsg) {
+        match msg {
+            CounterMsg::Add(n) => self.total += n,
+            CounterMsg::Get(reply) => reply.send(self.total),
+            CounterMsg::Crash => panic!("counter crashed"),
+        }
+    }
+}
+
+fn request_response() {
+    let (counter, _) = spawn(Counter::default());
+
+    counter.send(CounterMsg::Add(2)).unwrap();
+    counter.send(CounterMsg::Add(3)).unwrap();
+
+    println!("counter total = {:?}", counter.ask(CounterMsg::Get)); // expect Ok(5)
+}
+
+fn supervised() {
+    let mut supervisor = Supervisor::new(Strategy::OneForOne).max_restarts(3);
+    let counter = supervisor.spawn(Counter::default);
+    let supervisor = supervisor.start();
+
+    counter.send(CounterMsg::Add(5)).unwrap();
+    counter.send(CounterMsg::Crash).unwrap();
+    counter.send(CounterMsg::Add(1)).unwrap();
+
+    // the restarted counter started from a fresh state, the mailbox kept the last message
+    println!("counter total after restart = {:?}", counter.ask_timeout(Duration::from_secs(1), CounterMsg::Get)); // expect Ok(1)
+    println!("restarts = {}", supervisor.shutdown());
+
+    // with all-for-one the sibling of the crashed actor starts over as well
+    let mut supervisor = Supervisor::new(Strategy::AllForOne);
+    let crashing = supervisor.spawn(Counter::default);
+    let sibling = supervisor.spawn(Counter::default);
+    let supervisor = supervisor.start();
+
+    crashing.send(CounterMsg::Add(1)).unwrap();
+    sibling.send(CounterMsg::Add(2)).unwrap();
+    crashing.send(CounterMsg::Crash).unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    println!("after all-for-one restart = {:?}, {:?}", crashing.ask(CounterMsg::Get), sibling.ask(CounterMsg::Get)); // expect Ok(0), Ok(0)
+    println!("restarts = {}", supervisor.shutdown());
+}
+
+#[test]
+fn test_producer_consumer_actors() {
+    let (consumer, _) = spawn(Consumer::default());
+    let (first, _) = spawn(Producer { consumer: consumer.clone() });
+    let (second, _) = spawn(Producer { consumer: consumer.clone() });
+
+    first.send(ProducerMsg::Produce(words("hi from the thread"))).unwrap();
+    second.send(ProducerMsg::Produce(words("more messages for you"))).unwrap();
+    first.ask(ProducerMsg::Flush).unwrap();
+    second.ask(ProducerMsg::Flush).unwrap();
+
+    let mut received = consumer.ask(ConsumerMsg::Received).unwrap();
+    // each producer keeps its own order
+    let from_first: Vec<_> = received.iter().filter(|w| words("hi from the thread").contains(w)).cloned().collect();
+    assert_eq!(from_first, words("hi from the thread"));
+
+    received.sort();
+    let mut expected = words("hi from the thread more messages for you");
+    expected.sort();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_ask_after_stop_and_timeout() {
+    let (counter, handle) = spawn(Counter::default());
+
+    assert_eq!(counter.ask_timeout(Duration::from_secs(5), CounterMsg::Get), Ok(0));
+
+    // the reply is never sent because the actor panics on the way
+    counter.send(CounterMsg::Crash).unwrap();
+    assert!(handle.join().is_err());
+
+    assert_eq!(counter.ask(CounterMsg::Get), Err(ActorError::Stopped));
+}
+
+#[test]
+fn test_ask_timeout() {
+    struct Slow;
+
+    impl Actor for Slow {
+        type Msg = ReplyTo<()>;
+
+        fn handle(&mut self, reply: ReplyTo<()>) {
+            thread::sleep(Duration::from_millis(100));
+            reply.send(());
+        }
+    }
+
+    let (slow, _) = spawn(Slow);
+    assert_eq!(slow.ask_timeout(Duration::from_millis(10), |reply| reply), Err(ActorError::Timeout));
+}
+
+#[test]
+fn test_one_for_one_restarts_only_crashed_child() {
+    let mut supervisor = Supervisor::new(Strategy::OneForOne);
+    let crashing = supervisor.spawn(Counter::default);
+    let healthy = supervisor.spawn(Counter::default);
+    let supervisor = supervisor.start();
+
+    crashing.send(CounterMsg::Add(10)).unwrap();
+    healthy.send(CounterMsg::Add(10)).unwrap();
+
+    crashing.send(CounterMsg::Crash).unwrap();
+    crashing.send(CounterMsg::Add(1)).unwrap();
+
+    // messages queued behind the crash are handled by the fresh incarnation
+    assert_eq!(crashing.ask(CounterMsg::Get), Ok(1));
+    assert_eq!(healthy.ask(CounterMsg::Get), Ok(10));
+
+    assert_eq!(supervisor.shutdown(), 1);
+    assert_eq!(crashing.send(CounterMsg::Add(1)), Err(ActorError::Stopped));
+}
+
+#[test]
+fn test_all_for_one_restarts_every_child() {
+    let mut supervisor = Supervisor::new(Strategy::AllForOne);
+    let crashing = supervisor.spawn(Counter::default);
+    let sibling = supervisor.spawn(Counter::default);
+    let supervisor = supervisor.start();
+
+    crashing.send(CounterMsg::Add(10)).unwrap();
+    sibling.send(CounterMsg::Add(10)).unwrap();
+    assert_eq!(sibling.ask(CounterMsg::Get), Ok(10));
+
+    crashing.send(CounterMsg::Crash).unwrap();
+
+    // wait for the restart: the sibling lost its state as well
+    let mut restarted = false;
+    for _ in 0..100 {
+        if sibling.ask(CounterMsg::Get) == Ok(0) {
+            restarted = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(restarted);
+    assert_eq!(crashing.ask(CounterMsg::Get), Ok(0));
+
+    assert_eq!(supervisor.shutdown(), 1);
+}
+
+#[test]
+fn test_supervisor_gives_up_after_max_restarts() {
+    let mut supervisor = Supervisor::new(Strategy::OneForOne).max_restarts(2);
+    let counter = supervisor.spawn(Counter::default);
+    let supervisor = supervisor.start();
+
+    for _ in 0..2 {
+        counter.send(CounterMsg::Crash).unwrap();
+        // answered by the restarted incarnation
+        assert_eq!(counter.ask(CounterMsg::Get), Ok(0));
+    }
+
+    // third crash: the supervisor gives up instead of restarting
+    counter.send(CounterMsg::Crash).unwrap();
+    assert_eq!(supervisor.shutdown(), 2);
+    assert_eq!(counter.ask(CounterMsg::Get), Err(ActorError::Stopped));
+}
@@ -1,3 +1,7 @@
+// the lessons below are kept as the book writes them
+#![allow(unused_mut, static_mut_refs, clippy::just_underscores_and_digits, clippy::ptr_offset_with_cast,
+         clippy::missing_safety_doc, clippy::useless_vec)]
+
 /// NOTE: four powers of unsafe
 /// - Dereference a raw pointer
 /// - Call an unsafe function or method
@@ -64,7 +68,7 @@ use std::slice;
 /// unsafe code be wrapped inside safe Rust func so that caller of this function don't care about
 /// the unsafe implementation!
 
-fn split_at_mut(slice: &mut [i32], mid: usize) -> (&mut [i32], &mut [i32]) {
+pub fn split_at_mut<T>(slice: &mut [T], mid: usize) -> (&mut [T], &mut [T]) {
     /// The function slice::from_raw_parts_mut is unsafe because it takes a raw pointer and
     /// must trust that this pointer is valid. The offset method on raw pointers is also unsafe,
     /// because it must trust that the offset location is also a valid pointer.
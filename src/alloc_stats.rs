@@ -0,0 +1,295 @@
+/// Counting global allocator
+///
+/// The lessons keep saying "`Box`/`String`/`Vec` put it on the heap". This wraps the system
+/// allocator to count how often that really happens:
+///
+/// - every `alloc` / `dealloc` / `realloc` goes to `System` as usual, and bumps a few counters
+/// - global counters (atomics) see every thread, `main` uses them for the per-topic summary
+/// - per-thread counters let a test `measure` its own code while other tests run in parallel
+///
+/// It is opt-in: the allocator is only installed in test builds and with
+/// `cargo run --features alloc-stats -- <topic>`, a normal run uses the plain system allocator.
+///
+/// NOTE: the allocator must never allocate itself, so the thread-local is a `const` initialised
+/// `Cell` of plain numbers (no lazy init, no destructor to register).
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Counters over some period: a whole run, a topic, or one `measure` call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub reallocations: usize,
+    pub bytes_allocated: usize,
+    pub bytes_freed: usize,
+    /// Highest number of bytes in use at once, above the level at the start of the period.
+    pub peak_bytes: usize,
+}
+
+impl Stats {
+    /// Bytes allocated during the period and not freed yet.
+    pub fn live_bytes(&self) -> isize {
+        self.bytes_allocated as isize - self.bytes_freed as isize
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} allocs, {} frees, {} reallocs, {} bytes allocated, peak {} bytes, {} bytes still live",
+               self.allocations, self.deallocations, self.reallocations,
+               self.bytes_allocated, self.peak_bytes, self.live_bytes())
+    }
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static BYTES_FREED: AtomicUsize = AtomicUsize::new(0);
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Same counters for one thread. `current` can go below 0 when the thread frees memory
+/// another thread allocated.
+#[derive(Clone, Copy)]
+struct Local {
+    allocations: usize,
+    deallocations: usize,
+    reallocations: usize,
+    bytes_allocated: usize,
+    bytes_freed: usize,
+    current: isize,
+    peak: isize,
+}
+
+thread_local! {
+    static LOCAL: Cell<Local> = const {
+        Cell::new(Local {
+            allocations: 0,
+            deallocations: 0,
+            reallocations: 0,
+            bytes_allocated: 0,
+            bytes_freed: 0,
+            current: 0,
+            peak: 0,
+        })
+    };
+}
+
+/// Records one event; `allocated` / `freed` are the bytes which changed hands.
+fn record(allocs: usize, deallocs: usize, reallocs: usize, allocated: usize, freed: usize) {
+    // only decides what gets printed, no other memory depends on these: Relaxed
+    ALLOCATIONS.fetch_add(allocs, Ordering::Relaxed);
+    DEALLOCATIONS.fetch_add(deallocs, Ordering::Relaxed);
+    REALLOCATIONS.fetch_add(reallocs, Ordering::Relaxed);
+    BYTES_ALLOCATED.fetch_add(allocated, Ordering::Relaxed);
+    BYTES_FREED.fetch_add(freed, Ordering::Relaxed);
+    let current = CURRENT.fetch_add(allocated, Ordering::Relaxed) + allocated;
+    CURRENT.fetch_sub(freed, Ordering::Relaxed);
+    PEAK.fetch_max(current, Ordering::Relaxed);
+
+    // fails only while the thread is being torn down, those events stay global only
+    let _ = LOCAL.try_with(|local| {
+        let mut l = local.get();
+        l.allocations += allocs;
+        l.deallocations += deallocs;
+        l.reallocations += reallocs;
+        l.bytes_allocated += allocated;
+        l.bytes_freed += freed;
+        l.current += allocated as isize - freed as isize;
+        l.peak = l.peak.max(l.current);
+        local.set(l);
+    });
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record(1, 0, 0, layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(1, 0, 0, layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        record(0, 1, 0, 0, layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            record(0, 0, 1, new_size, layout.size());
+        }
+        new
+    }
+}
+
+/// Counters of the whole process since it started.
+pub fn global() -> Stats {
+    Stats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+        bytes_allocated: BYTES_ALLOCATED.load(Ordering::Relaxed),
+        bytes_freed: BYTES_FREED.load(Ordering::Relaxed),
+        peak_bytes: PEAK.load(Ordering::Relaxed),
+    }
+}
+
+/// Start of a period measured over all threads, see `Period::finish`.
+pub struct Period {
+    start: Stats,
+    current: usize,
+}
+
+impl Period {
+    /// Restarts the global peak from what is in use now.
+    pub fn start() -> Period {
+        let current = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(current, Ordering::Relaxed);
+        Period { start: global(), current }
+    }
+
+    pub fn finish(self) -> Stats {
+        let end = global();
+        Stats {
+            allocations: end.allocations - self.start.allocations,
+            deallocations: end.deallocations - self.start.deallocations,
+            reallocations: end.reallocations - self.start.reallocations,
+            bytes_allocated: end.bytes_allocated - self.start.bytes_allocated,
+            bytes_freed: end.bytes_freed - self.start.bytes_freed,
+            peak_bytes: end.peak_bytes.saturating_sub(self.current),
+        }
+    }
+}
+
+/// Runs `f` and counts what the current thread allocated meanwhile. Other threads, including
+/// ones `f` spawns, are not counted.
+#[cfg(test)]
+pub fn measure<R, F: FnOnce() -> R>(f: F) -> (R, Stats) {
+    let before = LOCAL.with(Cell::get);
+    // the peak of this period starts from the current level
+    LOCAL.with(|local| local.set(Local { peak: before.current, ..before }));
+
+    let result = f();
+
+    let after = LOCAL.with(Cell::get);
+    // keep the thread's overall peak right for an enclosing `measure`
+    LOCAL.with(|local| local.set(Local { peak: after.peak.max(before.peak), ..after }));
+    let stats = Stats {
+        allocations: after.allocations - before.allocations,
+        deallocations: after.deallocations - before.deallocations,
+        reallocations: after.reallocations - before.reallocations,
+        bytes_allocated: after.bytes_allocated - before.bytes_allocated,
+        bytes_freed: after.bytes_freed - before.bytes_freed,
+        peak_bytes: (after.peak - before.current).max(0) as usize,
+    };
+    (result, stats)
+}
+
+/// Runs `f` and panics if it allocated more than `max` times on this thread.
+#[cfg(test)]
+pub fn assert_allocations_at_most<R, F: FnOnce() -> R>(max: usize, f: F) -> R {
+    let (result, stats) = measure(f);
+    assert!(stats.allocations <= max, "expected at most {} allocations, got {}", max, stats);
+    result
+}
+
+/// One line for `main` after a topic ran.
+#[cfg(feature = "alloc-stats")]
+pub fn report(topic: &str, period: Period) {
+    println!("[alloc-stats] {}: {}", topic, period.finish());
+}
+
+#[test]
+fn test_measure_counts_heap_only() {
+    let (sum, stats) = measure(|| {
+        let array = [1u64; 64];
+        array.iter().sum::<u64>()
+    });
+    assert_eq!(sum, 64);
+    assert_eq!(stats, Stats::default());
+
+    let (_, stats) = measure(|| {
+        let boxed = Box::new([0u8; 100]);
+        drop(boxed);
+    });
+    assert_eq!((stats.allocations, stats.deallocations), (1, 1));
+    assert_eq!((stats.bytes_allocated, stats.bytes_freed, stats.peak_bytes), (100, 100, 100));
+    assert_eq!(stats.live_bytes(), 0);
+}
+
+#[test]
+fn test_growth_is_counted_as_realloc() {
+    let (s, stats) = measure(|| {
+        let mut s = String::new();
+        for _ in 0..100 {
+            s.push('x');
+        }
+        s
+    });
+    assert_eq!(stats.allocations, 1);
+    assert!(stats.reallocations >= 1);
+    assert_eq!(stats.live_bytes(), s.capacity() as isize);
+
+    let (_, stats) = measure(|| String::with_capacity(100).push_str(&"x".repeat(100)));
+    assert_eq!(stats.reallocations, 0);
+}
+
+#[test]
+fn test_peak_and_nested_measure() {
+    let (_, outer) = measure(|| {
+        let big = vec![0u8; 4096];
+        drop(big);
+        let (_, inner) = measure(|| vec![0u8; 16]);
+        assert_eq!(inner.peak_bytes, 16);
+    });
+    assert_eq!(outer.peak_bytes, 4096);
+    assert_eq!(outer.allocations, 2);
+    assert_eq!(outer.live_bytes(), 0);
+}
+
+#[test]
+fn test_other_threads_are_not_counted() {
+    let handle = std::thread::spawn(|| (0..1000).map(|i| vec![i; 8]).collect::<Vec<_>>().len());
+    let (_, stats) = measure(|| std::hint::black_box([0u8; 32]));
+    assert_eq!(stats.allocations, 0);
+    assert_eq!(handle.join().unwrap(), 1000);
+    assert!(global().allocations >= 1000);
+}
+
+#[test]
+fn test_period_counts_all_threads() {
+    let period = Period::start();
+    let handle = std::thread::spawn(|| (0..100).map(Box::new).collect::<Vec<_>>());
+    assert_eq!(handle.join().unwrap().len(), 100);
+    let stats = period.finish();
+    // other tests allocate at the same time, so only a lower bound holds
+    assert!(stats.allocations >= 101, "{}", stats);
+}
+
+#[test]
+#[should_panic(expected = "expected at most 2 allocations")]
+fn test_assert_allocations_at_most() {
+    let v = assert_allocations_at_most(1, || Vec::<u8>::with_capacity(10));
+    assert_eq!(v.capacity(), 10);
+    assert_allocations_at_most(2, || (0..3).map(|_| Box::new(0)).collect::<Vec<_>>());
+}
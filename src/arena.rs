@@ -0,0 +1,405 @@
+/// Index-based arena
+///
+/// `rc_tree` links nodes with `Rc`/`Weak`: one heap allocation per node, a count update for every
+/// handle, and a `RefCell` check on every access. An arena keeps all nodes in one `Vec` and links
+/// them by index instead:
+///
+/// - a `NodeId` is `(index, generation)`, a plain `Copy` value, no counts, no borrow flags
+/// - removing a node frees its slot for reuse and bumps the slot's generation
+/// - an old `NodeId` still carries the old generation, so it finds nothing instead of silently
+///   pointing at whatever reused the slot (the arena version of a dangling pointer)
+///
+/// Cycles are no problem either: parent and child ids point at each other but own nothing,
+/// dropping the arena drops every node at once.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    // indexes of empty slots, reused before the Vec grows
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena { slots: vec![], free: vec![], len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Arena<T> {
+        Arena { slots: Vec::with_capacity(capacity), free: vec![], len: 0 }
+    }
+
+    pub fn insert(&mut self, value: T) -> NodeId {
+        self.len += 1;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                NodeId { index, generation: slot.generation }
+            }
+            None => {
+                let index = self.slots.len() as u32;
+                self.slots.push(Slot { generation: 0, value: Some(value) });
+                NodeId { index, generation: 0 }
+            }
+        }
+    }
+
+    /// Takes the value out; `id` and every copy of it are invalid from now on.
+    pub fn remove(&mut self, id: NodeId) -> Option<T> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&T> {
+        self.slots.get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut T> {
+        self.slots.get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Live values with their ids, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| (NodeId { index: index as u32, generation: slot.generation }, value))
+        })
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Arena<T> {
+        Arena::new()
+    }
+}
+
+struct TreeNode<T> {
+    value: T,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+/// The `rc_tree::Tree` operations on an arena. Node ids from another tree, or of removed nodes,
+/// are simply not found.
+pub struct ArenaTree<T> {
+    nodes: Arena<TreeNode<T>>,
+    root: NodeId,
+}
+
+impl<T> ArenaTree<T> {
+    pub fn new(value: T) -> ArenaTree<T> {
+        ArenaTree::with_capacity(value, 1)
+    }
+
+    pub fn with_capacity(value: T, capacity: usize) -> ArenaTree<T> {
+        let mut nodes = Arena::with_capacity(capacity);
+        let root = nodes.insert(TreeNode { value, parent: None, children: vec![] });
+        ArenaTree { nodes, root }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.contains(id)
+    }
+
+    pub fn value(&self, id: NodeId) -> Option<&T> {
+        self.nodes.get(id).map(|node| &node.value)
+    }
+
+    pub fn value_mut(&mut self, id: NodeId) -> Option<&mut T> {
+        self.nodes.get_mut(id).map(|node| &mut node.value)
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes.get(id).and_then(|node| node.parent)
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.nodes.get(id).map_or(&[], |node| &node.children)
+    }
+
+    /// Appends a new last child, `None` if `parent` is not in the tree.
+    pub fn add_child(&mut self, parent: NodeId, value: T) -> Option<NodeId> {
+        if !self.nodes.contains(parent) {
+            return None;
+        }
+        let child = self.nodes.insert(TreeNode { value, parent: Some(parent), children: vec![] });
+        self.nodes.get_mut(parent)?.children.push(child);
+        Some(child)
+    }
+
+    /// Removes `id` and all its descendants, returns their values in pre-order.
+    /// The root can't be removed.
+    pub fn remove_subtree(&mut self, id: NodeId) -> Vec<T> {
+        let parent = match self.parent(id) {
+            Some(parent) => parent,
+            None => return vec![],
+        };
+        if let Some(node) = self.nodes.get_mut(parent) {
+            node.children.retain(|&child| child != id);
+        }
+
+        let order: Vec<NodeId> = self.pre_order(id).collect();
+        order.into_iter()
+            .filter_map(|id| self.nodes.remove(id))
+            .map(|node| node.value)
+            .collect()
+    }
+
+    /// Number of edges up to the root, `None` for an unknown id.
+    pub fn depth(&self, id: NodeId) -> Option<usize> {
+        self.nodes.get(id)?;
+        let mut depth = 0;
+        let mut current = id;
+        while let Some(parent) = self.parent(current) {
+            depth += 1;
+            current = parent;
+        }
+        Some(depth)
+    }
+
+    /// Node, then each child subtree from first to last. Empty for an unknown id.
+    pub fn pre_order(&self, from: NodeId) -> PreOrder<'_, T> {
+        let stack = if self.contains(from) { vec![from] } else { vec![] };
+        PreOrder { tree: self, stack }
+    }
+
+    /// Breadth first from `from`.
+    pub fn level_order(&self, from: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut queue: VecDeque<NodeId> = self.pre_order(from).take(1).collect();
+        std::iter::from_fn(move || {
+            let id = queue.pop_front()?;
+            queue.extend(self.children(id));
+            Some(id)
+        })
+    }
+
+    pub fn find<P>(&self, mut predicate: P) -> Option<NodeId>
+        where P: FnMut(&T) -> bool {
+        self.pre_order(self.root).find(|&id| self.value(id).is_some_and(&mut predicate))
+    }
+}
+
+pub struct PreOrder<'a, T> {
+    tree: &'a ArenaTree<T>,
+    stack: Vec<NodeId>,
+}
+
+impl<T> Iterator for PreOrder<'_, T> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.stack.pop()?;
+        self.stack.extend(self.tree.children(id).iter().rev());
+        Some(id)
+    }
+}
+
+/// Same indented layout as `rc_tree::Tree`.
+impl<T: fmt::Display> fmt::Display for ArenaTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stack = vec![(self.root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            writeln!(f, "{:indent$}{}", "", self.nodes.get(id).unwrap().value, indent = 2 * depth)?;
+            stack.extend(self.children(id).iter().rev().map(|&child| (child, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+/// Builds the same `n`-node tree (every node gets up to `fan_out` children, breadth first)
+/// with `rc_tree` and with the arena and times build, traversal and drop.
+pub fn run() {
+    use crate::rc_tree::Tree;
+
+    let n = 200_000;
+    let fan_out = 4;
+    println!("{} nodes, up to {} children each", n, fan_out);
+
+    let now = Instant::now();
+    let tree = Tree::new(0u64);
+    let mut parents = VecDeque::from(vec![std::rc::Rc::clone(tree.root())]);
+    let mut count = 1;
+    while count < n {
+        let parent = parents.pop_front().unwrap();
+        for _ in 0..fan_out.min(n - count) {
+            parents.push_back(parent.add_child(count as u64));
+            count += 1;
+        }
+    }
+    drop(parents);
+    let build = now.elapsed();
+    let now = Instant::now();
+    let sum: u64 = tree.pre_order().map(|node| *node.value()).sum();
+    let traverse = now.elapsed();
+    let now = Instant::now();
+    drop(tree);
+    println!("Rc<Node> tree : build {:?} | traverse {:?} (sum {}) | drop {:?}", build, traverse, sum, now.elapsed());
+
+    let now = Instant::now();
+    let mut tree = ArenaTree::with_capacity(0u64, n);
+    let mut parents = VecDeque::from(vec![tree.root()]);
+    let mut count = 1;
+    while count < n {
+        let parent = parents.pop_front().unwrap();
+        for _ in 0..fan_out.min(n - count) {
+            parents.push_back(tree.add_child(parent, count as u64).unwrap());
+            count += 1;
+        }
+    }
+    let build = now.elapsed();
+    let now = Instant::now();
+    let sum: u64 = tree.pre_order(tree.root()).filter_map(|id| tree.value(id)).sum();
+    let traverse = now.elapsed();
+    let now = Instant::now();
+    drop(tree);
+    println!("Arena tree    : build {:?} | traverse {:?} (sum {}) | drop {:?}", build, traverse, sum, now.elapsed());
+
+    // removal leaves no dangling handle: the old id just finds nothing
+    let mut tree = ArenaTree::new("root");
+    let branch = tree.add_child(tree.root(), "branch").unwrap();
+    let leaf = tree.add_child(branch, "leaf").unwrap();
+    println!("{}", tree);
+    println!("removed {:?}", tree.remove_subtree(branch));
+    let reused = tree.add_child(tree.root(), "new").unwrap();
+    *tree.value_mut(reused).unwrap() = "reused";
+    println!("old leaf = {:?} | new node = {:?} at depth {:?} | level order = {:?} | found = {}",
+             tree.value(leaf), tree.value(reused), tree.depth(reused),
+             tree.level_order(tree.root()).filter_map(|id| tree.value(id)).collect::<Vec<_>>(),
+             tree.find(|v| *v == "reused") == Some(reused));
+    println!("{} nodes left", tree.len());
+
+    // a graph is just values with id lists, the cycle a <-> b owns nothing and can't leak
+    let mut graph: Arena<(&str, Vec<NodeId>)> = Arena::new();
+    let a = graph.insert(("a", vec![]));
+    let b = graph.insert(("b", vec![a]));
+    graph.get_mut(a).unwrap().1.push(b);
+    for (_, (name, edges)) in graph.iter() {
+        let targets: Vec<_> = edges.iter().filter_map(|&id| graph.get(id)).map(|(name, _)| *name).collect();
+        println!("{} -> {:?}", name, targets);
+    }
+    graph.remove(b);
+    println!("after removing b: {} node(s), a's edge to b finds {:?}, empty = {}",
+             graph.len(), graph.get(graph.get(a).unwrap().1[0]).map(|(name, _)| *name), graph.is_empty());
+}
+
+#[test]
+fn test_generation_invalidates_old_ids() {
+    let mut arena = Arena::new();
+    let a = arena.insert("a");
+    let b = arena.insert("b");
+    assert_eq!(arena.len(), 2);
+    assert_eq!(arena.get(a), Some(&"a"));
+
+    assert_eq!(arena.remove(a), Some("a"));
+    assert_eq!(arena.remove(a), None);
+    assert!(!arena.contains(a));
+
+    // the slot of `a` is reused, the old id still finds nothing
+    let c = arena.insert("c");
+    assert_eq!(c.index, a.index);
+    assert_ne!(c, a);
+    assert_eq!(arena.get(a), None);
+    assert_eq!(arena.get_mut(a), None);
+    assert_eq!(arena.get(c), Some(&"c"));
+
+    *arena.get_mut(b).unwrap() = "B";
+    assert_eq!(arena.iter().map(|(_, v)| *v).collect::<Vec<_>>(), vec!["c", "B"]);
+    assert!(!arena.is_empty());
+}
+
+#[cfg(test)]
+fn sample() -> (ArenaTree<&'static str>, NodeId, NodeId) {
+    let mut tree = ArenaTree::new("root");
+    let root = tree.root();
+    let a = tree.add_child(root, "a").unwrap();
+    tree.add_child(a, "a1").unwrap();
+    tree.add_child(a, "a2").unwrap();
+    let b = tree.add_child(root, "b").unwrap();
+    tree.add_child(b, "b1").unwrap();
+    (tree, a, b)
+}
+
+#[test]
+fn test_tree_traversal_matches_rc_tree() {
+    let (tree, a, _) = sample();
+    let values = |ids: Vec<NodeId>| ids.into_iter().map(|id| *tree.value(id).unwrap()).collect::<Vec<_>>();
+
+    assert_eq!(values(tree.pre_order(tree.root()).collect()), vec!["root", "a", "a1", "a2", "b", "b1"]);
+    assert_eq!(values(tree.level_order(tree.root()).collect()), vec!["root", "a", "b", "a1", "a2", "b1"]);
+    assert_eq!(tree.to_string(), "root\n  a\n    a1\n    a2\n  b\n    b1\n");
+
+    let a2 = tree.find(|v| *v == "a2").unwrap();
+    assert_eq!(tree.depth(a2), Some(2));
+    assert_eq!(tree.parent(a2), Some(a));
+    assert_eq!(values(tree.children(a).to_vec()), vec!["a1", "a2"]);
+}
+
+#[test]
+fn test_remove_subtree_leaves_no_dangling_ids() {
+    let (mut tree, a, b) = sample();
+    let a1 = tree.children(a)[0];
+
+    assert_eq!(tree.remove_subtree(a), vec!["a", "a1", "a2"]);
+    assert_eq!(tree.len(), 3);
+    assert!(!tree.contains(a) && !tree.contains(a1));
+    assert_eq!(tree.value(a1), None);
+    assert_eq!(tree.depth(a), None);
+    assert_eq!(tree.children(a), &[] as &[NodeId]);
+    assert_eq!(tree.pre_order(a).count(), 0);
+    assert_eq!(tree.add_child(a, "orphan"), None);
+    assert_eq!(tree.children(tree.root()), &[b]);
+
+    // new nodes reuse the slots, old ids stay dead
+    let x = tree.add_child(b, "x").unwrap();
+    assert!(!tree.contains(a1));
+    assert_eq!(tree.to_string(), "root\n  b\n    b1\n    x\n");
+    assert_eq!(tree.depth(x), Some(2));
+
+    assert!(tree.remove_subtree(tree.root()).is_empty());
+    assert_eq!(tree.len(), 4);
+}
@@ -7,6 +7,9 @@
 /// if that data type do not implement COPY trait yet, make sure you borrow value across
 /// re-assign operation
 
+use std::rc::Rc;
+use crate::tracked::{record, Tracked};
+
 /// NOTE 3: custom data type (by struct keyword) which contains known size type is
 /// known size type and variable of this type will be pushed to stack :)))
 /// note that if that type not implement COPY trait, still need borrow explicitly :))
@@ -16,6 +19,7 @@ struct MyType {
     y: i32
 }
 
+#[allow(clippy::inherent_to_string)] // the lesson's own method, not `Display`
 impl MyType {
     fn to_string(&self) -> String {
         format!("x = {} y = {}", self.x, self.y)
@@ -118,6 +122,23 @@ pub fn run() {
     // if we try to do something like below statement, it will occurred error
     // due to rm_s has no ownership to string value
     // println!("value pointed by rm_s = {}", rm_s);
+
+    // `tracked::Tracked` writes down every move and drop, so the rules above can be watched
+    let ((), journal) = record(|| {
+        let owner = Tracked::new("owner", String::from("sample"));
+        let copy = owner.clone(); // deep copy: a second value which is dropped on its own
+        sample_take_tracked(owner); // moved: dropped at the end of the function, not here
+        let shared = copy.into_rc(); // moved into the Rc, nothing dropped
+        let other = Rc::clone(&shared); // one more owner, still one value
+        println!("{:?} has {} owners", other, Rc::strong_count(&shared));
+        // `other` then `shared` go out of scope here, the value is dropped with the last one
+    });
+    print!("{}", journal);
+    println!("drop order = {:?} | leaked = {:?}", journal.drop_order(), journal.leaked());
+}
+
+fn sample_take_tracked(value: Tracked<String>) {
+    println!("tracked inside the function = {:?}", value);
 }
 
 fn sample_take_ownership(string: String) {
@@ -132,3 +153,17 @@ fn sample_take_ownership_and_return(string: String) -> String {
 fn sample_take_reference_instead_of_value(string_ref: &String) {
     println!("string inside the function = {}", string_ref);
 }
+
+#[test]
+fn test_move_drops_in_callee_and_borrow_does_not() {
+    let ((), journal) = record(|| {
+        let moved = Tracked::new("moved", String::from("a"));
+        let borrowed = Tracked::new("borrowed", String::from("b"));
+        sample_take_tracked(moved);
+        sample_take_reference_instead_of_value(&borrowed);
+        assert_eq!(borrowed.label(), "borrowed");
+    });
+    // `moved` died inside the function, `borrowed` only when its owner went out of scope
+    assert_eq!(journal.drop_order(), vec!["moved", "borrowed"]);
+    journal.assert_no_leaks();
+}
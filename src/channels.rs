@@ -113,4 +113,6 @@ fn one_vs_one_multi_with_rc() {
     rx.iter().for_each(|mess| {
         println!("Got: {}", mess);
     });
-}
\ No newline at end of file
+}
+
+// NOTE: `actor.rs` rewrites the producer / consumer demos above as cooperating actors
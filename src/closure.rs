@@ -1,6 +1,10 @@
+use std::cell::Cell;
+use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
-use std::collections::HashMap;
+
+use crate::memo::Memo;
+use crate::shared_memo::SharedMemo;
 
 pub fn run() {
     let simulated_user_specified_value = 10;
@@ -10,6 +14,32 @@ pub fn run() {
         simulated_user_specified_value,
         simulated_random_number
     );
+    generate_workout_memo_threads(
+        simulated_user_specified_value,
+        simulated_random_number
+    );
+
+    // bounded memo: the two most recently used words stay, older ones are computed again
+    let mut lengths = Memo::with_capacity(2, |word: &str| word.chars().count())
+        .with_ttl(Duration::from_secs(60));
+    for word in &["push-ups", "sit-ups", "push-ups", "run", "sit-ups"] {
+        println!("{} -> {}", word, lengths.value(*word));
+    }
+    println!("cached {:?} ({} of them), {}", lengths.keys(), lengths.len(), lengths.stats());
+    println!("forget run: {:?}, still has push-ups: {}", lengths.remove(&"run"), lengths.contains(&"push-ups"));
+    lengths.clear();
+    println!("after clear: empty = {}", lengths.is_empty());
+
+    // any `memo::Clock` can drive the expiry, here a day counter: a plan is good for two days
+    let day = Rc::new(Cell::new(0));
+    let today = Rc::clone(&day);
+    let mut plans = Memo::new(|intensity: u32| intensity + 2)
+        .with_ttl(Duration::from_secs(2 * 86_400))
+        .with_clock(move || Duration::from_secs(today.get() * 86_400));
+    for _ in 0..4 {
+        println!("day {}: intensity {}, {}", day.get(), plans.value(10), plans.stats());
+        day.set(day.get() + 1);
+    }
 }
 
 fn simulated_expensive_calculation(intensity: u32) -> u32 {
@@ -21,6 +51,7 @@ fn simulated_expensive_calculation(intensity: u32) -> u32 {
 
 /// Normal method: normal version which call function in case we need it results
 /// Prob: we must wait for expensive cal more than one ( which actually need just once )
+/// NOTE: `workout` grows this into a real planner: seeded random days, rules from a file, history
 fn generate_workout(intensity: u32, random_number: u32) {
     if intensity < 25 {
         println!(
@@ -106,32 +137,12 @@ fn generate_workout_two(intensity: u32, random_number: u32) {
 
 /// Memoization method ( or lazy evaluation method )
 /// create cache struct where we store the value after first call to expensive func
-struct Memo<T> where T: Fn(u32) -> u32 {
-    calculation: T,
-    values: HashMap<u32, u32>
-}
-
-impl<T> Memo<T> where T: Fn(u32) -> u32 {
-    fn new(calculation: T) -> Memo<T> {
-        Memo {
-            calculation,
-            values: HashMap::new()
-        }
-    }
-
-    fn value(&mut self, arg: u32) -> u32 {
-        if let Some(v) = self.values.get(&arg) {
-            *v
-        } else {
-            let v = (self.calculation)(arg);
-            self.values.insert(arg, v);
-            v
-        }
-    }
-}
-
+///
+/// NOTE: the first version was `Memo<T> where T: Fn(u32) -> u32` over a `HashMap<u32, u32>`,
+/// it only fits this one closure and never forgets a value. `memo::Memo` is the same idea for
+/// any key and value type, with a capacity (least recently used goes first), expiry and stats.
 fn generate_workout_memo(intensity: u32, random_number: u32) {
-    let mut expensive_result = Memo::new(|num| {
+    let mut expensive_result = Memo::new(|num: u32| {
         println!("calculating slowly...");
         thread::sleep(Duration::from_secs(2));
         num
@@ -159,7 +170,7 @@ fn generate_workout_memo(intensity: u32, random_number: u32) {
 }
 
 #[test]
-#[allow(dead_code)]
+#[allow(dead_code, unused_variables)]
 fn call_memo() {
     let mut c = Memo::new(|a| a);
 
@@ -206,3 +217,27 @@ fn call_memo() {
 ///
 /// Trick: Most of the time when specifying one of the Fn trait bounds,
 /// you can start with `Fn` and the compiler will tell you if you need `FnMut` or `FnOnce` based on what happens in the closure body.
+///
+/// The plan of `generate_workout_memo` with threads: push-ups and sit-ups are worked out by two
+/// threads at once, the run by one. `shared_memo::SharedMemo` takes `&self`: the first thread
+/// computes, the other waits for its result instead of calculating slowly again
+fn generate_workout_memo_threads(intensity: u32, random_number: u32) {
+    let expensive_result = SharedMemo::new(|num: u32| {
+        println!("calculating slowly...");
+        thread::sleep(Duration::from_secs(2));
+        num
+    });
+
+    thread::scope(|s| {
+        let expensive_result = &expensive_result;
+        if intensity < 25 {
+            s.spawn(move || println!("Today, do {} push-ups!", expensive_result.value(intensity).unwrap()));
+            s.spawn(move || println!("Next, do {} sit-ups!", expensive_result.value(intensity).unwrap()));
+        } else if random_number == 3 {
+            println!("Take a break today! Remember to stay hydrated!");
+        } else {
+            s.spawn(move || println!("Today, run for {} minutes!", expensive_result.value(intensity).unwrap()));
+        }
+    });
+    println!("{}", expensive_result.stats());
+}
@@ -0,0 +1,290 @@
+/// Persistent cons list
+///
+/// `smart_pointer.rs` builds `Cons(i32, Box<List>)` and `Cons(i32, Rc<List>)` by hand. This is the
+/// same idea made generic and usable: a list is never modified, `cons` returns a new list whose
+/// tail is the old one. Because the tail sits behind an `Rc`, the old list is shared, not copied:
+///
+///     a = (5 10)        a ──► [5] ──► [10]
+///     b = a.cons(3)     b ──► [3] ──┘
+///     c = a.cons(4)     c ──► [4] ──┘
+///
+/// Dropping a list only frees the nodes nobody else points to. The derived (recursive) drop would
+/// recurse once per node and overflow the stack on long lists, so `Drop` walks the list in a loop.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+pub struct List<T> {
+    head: Option<Rc<Node<T>>>,
+    len: usize,
+}
+
+struct Node<T> {
+    value: T,
+    next: Option<Rc<Node<T>>>,
+}
+
+impl<T> List<T> {
+    /// The empty list (`Nil`).
+    pub fn new() -> List<T> {
+        List { head: None, len: 0 }
+    }
+
+    /// New list with `value` in front of `self`, `self` is shared and not copied.
+    pub fn cons(&self, value: T) -> List<T> {
+        List {
+            head: Some(Rc::new(Node { value, next: self.head.clone() })),
+            len: self.len + 1,
+        }
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    /// Everything but the head, shared with `self`. `None` for the empty list.
+    pub fn tail(&self) -> Option<List<T>> {
+        self.head.as_ref().map(|node| List { head: node.next.clone(), len: self.len - 1 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { next: self.head.as_deref(), len: self.len }
+    }
+
+    /// New list of `f(x)` in the same order, nothing is shared with `self`.
+    pub fn map<U, F>(&self, f: F) -> List<U>
+        where F: FnMut(&T) -> U {
+        self.iter().map(f).collect()
+    }
+
+    /// Combines the elements from head to last: `f(f(f(init, a), b), c)`.
+    pub fn fold<A, F>(&self, init: A, f: F) -> A
+        where F: FnMut(A, &T) -> A {
+        self.iter().fold(init, f)
+    }
+
+    /// New list in reverse order, each element is cloned into a fresh node.
+    pub fn reverse(&self) -> List<T>
+        where T: Clone {
+        self.fold(List::new(), |acc, value| acc.cons(value.clone()))
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> List<T> {
+        List::new()
+    }
+}
+
+/// Cloning a list is cheap: it only bumps the count of the first node.
+impl<T> Clone for List<T> {
+    fn clone(&self) -> List<T> {
+        List { head: self.head.clone(), len: self.len }
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                // last owner of this node: detach its tail before the node goes away,
+                // so dropping the node itself never recurses
+                Ok(mut node) => next = node.next.take(),
+                // the rest of the list is still shared with another list, leave it alone
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    next: Option<&'a Node<T>>,
+    len: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next.map(|node| {
+            self.next = node.next.as_deref();
+            self.len -= 1;
+            &node.value
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Keeps the iteration order: `vec![1, 2, 3].into_iter().collect()` is `(1 2 3)`.
+impl<T> FromIterator<T> for List<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> List<T> {
+        // a cons list grows at the front, so build it from the last element
+        let items: Vec<T> = iter.into_iter().collect();
+        items.into_iter().rev().fold(List::new(), |list, value| list.cons(value))
+    }
+}
+
+impl<T: PartialEq> PartialEq for List<T> {
+    fn eq(&self, other: &List<T>) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for List<T> {}
+
+/// Lisp style: `(1 2 3)`, `()` for the empty list.
+impl<T: fmt::Display> fmt::Display for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+impl<T> List<T> {
+    /// Strong count of the first node, 0 for the empty list.
+    fn head_count(&self) -> usize {
+        self.head.as_ref().map_or(0, Rc::strong_count)
+    }
+}
+
+#[test]
+fn test_cons_head_tail() {
+    let nil: List<i32> = List::new();
+    assert!(nil.is_empty());
+    assert_eq!(nil.head(), None);
+    assert!(nil.tail().is_none());
+
+    let list = nil.cons(3).cons(2).cons(1);
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.head(), Some(&1));
+
+    let tail = list.tail().unwrap();
+    assert_eq!(tail.len(), 2);
+    assert_eq!(tail.head(), Some(&2));
+    assert_eq!(tail.tail().unwrap().tail().unwrap(), nil);
+
+    // the original is untouched
+    assert_eq!(list.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_structural_sharing() {
+    let a: List<i32> = vec![5, 10].into_iter().collect();
+    assert_eq!(a.head_count(), 1);
+
+    let b = a.cons(3);
+    assert_eq!(a.head_count(), 2);
+    {
+        let c = a.cons(4);
+        assert_eq!(a.head_count(), 3);
+        assert_eq!(c.to_string(), "(4 5 10)");
+    }
+    assert_eq!(a.head_count(), 2);
+    assert_eq!(b.to_string(), "(3 5 10)");
+
+    // the tail of b is the very same node as a
+    assert_eq!(b.tail().unwrap().head().unwrap() as *const i32, a.head().unwrap() as *const i32);
+
+    drop(a);
+    assert_eq!(b.tail().unwrap().head_count(), 2); // b's link + the tail() we just made
+    assert_eq!(b.to_string(), "(3 5 10)");
+}
+
+#[test]
+fn test_map_fold_reverse() {
+    let list: List<i32> = (1..=4).collect();
+
+    assert_eq!(list.map(|x| x * 10), (10..=40).step_by(10).collect());
+    assert_eq!(list.map(|x| x.to_string()).to_string(), "(1 2 3 4)");
+    assert_eq!(list.fold(0, |acc, x| acc + x), 10);
+    assert_eq!(list.fold(String::new(), |acc, x| format!("{}{}", acc, x)), "1234");
+    assert_eq!(list.reverse().to_string(), "(4 3 2 1)");
+    assert_eq!(list.reverse().reverse(), list);
+    assert_eq!(List::<i32>::new().reverse(), List::new());
+}
+
+#[test]
+fn test_display_and_debug() {
+    assert_eq!(List::<i32>::new().to_string(), "()");
+    assert_eq!(List::new().cons("a").to_string(), "(a)");
+
+    let list: List<&str> = vec!["a", "b"].into_iter().collect();
+    assert_eq!(format!("{:?}", list), r#"["a", "b"]"#);
+    assert_eq!(list.iter().len(), 2);
+}
+
+#[test]
+fn test_drop_long_list_does_not_overflow() {
+    let list: List<u32> = (0..1_000_000).collect();
+    assert_eq!(list.len(), 1_000_000);
+
+    // a shared suffix survives when the longer list goes away
+    let suffix = list.tail().unwrap();
+    drop(list);
+    assert_eq!(suffix.len(), 999_999);
+    assert_eq!(suffix.head(), Some(&1));
+    drop(suffix);
+}
+
+#[test]
+fn test_drop_frees_every_node_once() {
+    let value = Rc::new(());
+    {
+        let a: List<Rc<()>> = (0..100).map(|_| Rc::clone(&value)).collect();
+        let b = a.cons(Rc::clone(&value));
+        assert_eq!(Rc::strong_count(&value), 102);
+        drop(a);
+        assert_eq!(Rc::strong_count(&value), 102); // still reachable through b
+        drop(b);
+    }
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+fn test_cons_allocates_one_node_and_clone_none() {
+    use crate::alloc_stats::measure;
+
+    let list: List<u64> = (0..10).collect();
+    let (longer, stats) = measure(|| list.cons(99));
+    assert_eq!(stats.allocations, 1);
+    let (copy, stats) = measure(|| (list.clone(), list.tail()));
+    assert_eq!(stats.allocations, 0);
+    drop((longer, copy));
+}
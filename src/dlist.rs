@@ -0,0 +1,604 @@
+/// Doubly-linked list with `Rc` / `Weak`
+///
+/// `smart_pointer::multi_mutate_list` shares nodes with `Rc<RefCell<_>>` and `tree_weak` shows
+/// that links pointing both ways must not both be strong. A doubly-linked list needs both ideas:
+///
+///     head ══► [a] ══► [b] ══► [c]        ══► Rc (owns)
+///              [a] ◄── [b] ◄── [c] ◄── tail   ──► Weak
+///
+/// - forward links own the next node, so every node has exactly one strong count
+/// - back links and `tail` are `Weak`, so there is no cycle and dropping `head` frees everything
+/// - nodes sit in a `RefCell` because inserting in the middle rewires both neighbours
+///
+/// Values can't be borrowed out of a node past its `RefCell` guard, so iterators hand out clones
+/// (`iter`) or the values themselves (`into_iter`), and `CursorMut::current` returns a `RefMut`.
+
+use std::cell::{RefCell, RefMut};
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::rc::{Rc, Weak};
+
+type Link<T> = Option<Rc<RefCell<Node<T>>>>;
+type BackLink<T> = Weak<RefCell<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    next: Link<T>,
+    prev: BackLink<T>,
+}
+
+pub struct DList<T> {
+    head: Link<T>,
+    tail: BackLink<T>,
+    len: usize,
+}
+
+impl<T> DList<T> {
+    pub fn new() -> DList<T> {
+        DList { head: None, tail: Weak::new(), len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let node = Rc::new(RefCell::new(Node { value, next: self.head.take(), prev: Weak::new() }));
+        match &node.borrow().next {
+            Some(old) => old.borrow_mut().prev = Rc::downgrade(&node),
+            None => self.tail = Rc::downgrade(&node),
+        }
+        self.head = Some(node);
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        let node = Rc::new(RefCell::new(Node { value, next: None, prev: self.tail.clone() }));
+        self.tail = Rc::downgrade(&node);
+        match node.borrow().prev.upgrade() {
+            Some(old) => old.borrow_mut().next = Some(Rc::clone(&node)),
+            None => self.head = Some(Rc::clone(&node)),
+        }
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        let node = self.head.take()?;
+        self.head = node.borrow_mut().next.take();
+        match &self.head {
+            Some(next) => next.borrow_mut().prev = Weak::new(),
+            None => self.tail = Weak::new(),
+        }
+        self.len -= 1;
+        Some(into_value(node))
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        let prev = self.tail.upgrade()?.borrow().prev.upgrade();
+        // take the one strong link to the last node, from its predecessor or from `head`
+        let node = match &prev {
+            Some(prev) => prev.borrow_mut().next.take(),
+            None => self.head.take(),
+        }?;
+        self.tail = prev.as_ref().map_or_else(Weak::new, Rc::downgrade);
+        self.len -= 1;
+        Some(into_value(node))
+    }
+
+    /// Clones of the values, from either end.
+    pub fn iter(&self) -> Iter<'_, T>
+        where T: Clone {
+        Iter { front: self.head.clone(), back: self.tail.upgrade(), len: self.len, _list: PhantomData }
+    }
+
+    /// Cursor on the first element (on the "ghost" position if the list is empty).
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.head.clone();
+        let index = current.as_ref().map(|_| 0);
+        CursorMut { list: self, current, index }
+    }
+
+    /// Cursor on the last element (on the "ghost" position if the list is empty).
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.tail.upgrade();
+        let index = current.as_ref().map(|_| self.len - 1);
+        CursorMut { list: self, current, index }
+    }
+
+    #[cfg(test)]
+    fn nodes(&self) -> Vec<BackLink<T>> {
+        let mut nodes = vec![];
+        let mut next = self.head.clone();
+        while let Some(node) = next {
+            nodes.push(Rc::downgrade(&node));
+            next = node.borrow().next.clone();
+        }
+        nodes
+    }
+}
+
+/// Value of a node which is not linked anymore.
+fn into_value<T>(node: Rc<RefCell<Node<T>>>) -> T {
+    match Rc::try_unwrap(node) {
+        Ok(cell) => cell.into_inner().value,
+        Err(_) => panic!("unlinked node is still shared"),
+    }
+}
+
+impl<T> Default for DList<T> {
+    fn default() -> DList<T> {
+        DList::new()
+    }
+}
+
+/// Iterative, like `cons_list::List`: the derived drop would recurse once per node.
+impl<T> Drop for DList<T> {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            next = node.borrow_mut().next.take();
+        }
+    }
+}
+
+impl<T> FromIterator<T> for DList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> DList<T> {
+        let mut list = DList::new();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for DList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T> {
+    front: Link<T>,
+    back: Link<T>,
+    // stops both ends from walking past each other
+    len: usize,
+    // the list must not change (and pop a node we hold) while we walk it
+    _list: PhantomData<&'a DList<T>>,
+}
+
+impl<T: Clone> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = self.front.take()?;
+        let node = node.borrow();
+        self.front = node.next.clone();
+        self.len -= 1;
+        Some(node.value.clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T: Clone> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = self.back.take()?;
+        let node = node.borrow();
+        self.back = node.prev.upgrade();
+        self.len -= 1;
+        Some(node.value.clone())
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for Iter<'_, T> {}
+
+/// Empty, but having a `Drop` keeps the list borrowed until the iterator (and the nodes it
+/// holds) is gone, not only until its last use.
+impl<T> Drop for Iter<'_, T> {
+    fn drop(&mut self) {}
+}
+
+pub struct IntoIter<T>(DList<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for DList<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+/// Points at one element, or at the "ghost" position between the last and the first element
+/// (like `std::collections::linked_list::CursorMut`). Moving past either end lands on the ghost,
+/// moving again wraps around.
+pub struct CursorMut<'a, T> {
+    list: &'a mut DList<T>,
+    current: Link<T>,
+    index: Option<usize>,
+}
+
+/// Same as for `Iter`: no popping a node while a cursor still holds it.
+impl<T> Drop for CursorMut<'_, T> {
+    fn drop(&mut self) {}
+}
+
+impl<T> CursorMut<'_, T> {
+    /// Position of the current element, `None` on the ghost.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    pub fn current(&mut self) -> Option<RefMut<'_, T>> {
+        self.current.as_ref().map(|node| RefMut::map(node.borrow_mut(), |node| &mut node.value))
+    }
+
+    pub fn move_next(&mut self) {
+        match self.current.take() {
+            Some(node) => {
+                self.current = node.borrow().next.clone();
+                self.index = self.current.as_ref().and_then(|_| self.index.map(|i| i + 1));
+            }
+            None => {
+                self.current = self.list.head.clone();
+                self.index = self.current.as_ref().map(|_| 0);
+            }
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        match self.current.take() {
+            Some(node) => {
+                self.current = node.borrow().prev.upgrade();
+                self.index = self.current.as_ref().and_then(|_| self.index.map(|i| i - 1));
+            }
+            None => {
+                self.current = self.list.tail.upgrade();
+                self.index = self.current.as_ref().map(|_| self.list.len - 1);
+            }
+        }
+    }
+
+    /// Inserts after the current element, at the front when on the ghost.
+    pub fn insert_after(&mut self, value: T) {
+        let current = match &self.current {
+            Some(current) => current,
+            None => return self.list.push_front(value),
+        };
+        let node = Rc::new(RefCell::new(Node {
+            value,
+            next: current.borrow_mut().next.take(),
+            prev: Rc::downgrade(current),
+        }));
+        match &node.borrow().next {
+            Some(next) => next.borrow_mut().prev = Rc::downgrade(&node),
+            None => self.list.tail = Rc::downgrade(&node),
+        }
+        current.borrow_mut().next = Some(node);
+        self.list.len += 1;
+    }
+
+    /// Inserts before the current element, at the back when on the ghost.
+    pub fn insert_before(&mut self, value: T) {
+        let current = match &self.current {
+            Some(current) => current,
+            None => return self.list.push_back(value),
+        };
+        let prev = current.borrow().prev.upgrade();
+        match prev {
+            None => self.list.push_front(value),
+            Some(prev) => {
+                let node = Rc::new(RefCell::new(Node {
+                    value,
+                    next: prev.borrow_mut().next.take(),
+                    prev: Rc::downgrade(&prev),
+                }));
+                current.borrow_mut().prev = Rc::downgrade(&node);
+                prev.borrow_mut().next = Some(node);
+                self.list.len += 1;
+            }
+        }
+        self.index = self.index.map(|i| i + 1);
+    }
+
+    /// Unlinks the current element and moves to the next one (the ghost after the last).
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node = self.current.take()?;
+        let next = node.borrow_mut().next.take();
+        let prev = node.borrow().prev.upgrade();
+
+        // drop the strong link to `node` held by its predecessor (or by `head`)
+        match &prev {
+            Some(prev) => prev.borrow_mut().next = next.clone(),
+            None => self.list.head = next.clone(),
+        }
+        let back = prev.as_ref().map_or_else(Weak::new, Rc::downgrade);
+        match &next {
+            Some(next) => next.borrow_mut().prev = back,
+            None => self.list.tail = back,
+        }
+
+        self.list.len -= 1;
+        self.index = next.as_ref().and(self.index);
+        self.current = next;
+        Some(into_value(node))
+    }
+
+    /// Moves everything after the current element into a new list. On the ghost, takes all.
+    pub fn split_after(&mut self) -> DList<T> {
+        let (current, index) = match (&self.current, self.index) {
+            (Some(current), Some(index)) => (current, index),
+            _ => return std::mem::take(self.list),
+        };
+        let head = match current.borrow_mut().next.take() {
+            Some(head) => head,
+            None => return DList::new(),
+        };
+        head.borrow_mut().prev = Weak::new();
+
+        let split = DList {
+            head: Some(head),
+            tail: std::mem::replace(&mut self.list.tail, Rc::downgrade(current)),
+            len: self.list.len - index - 1,
+        };
+        self.list.len = index + 1;
+        split
+    }
+
+    /// Moves everything before the current element into a new list. On the ghost, takes all.
+    pub fn split_before(&mut self) -> DList<T> {
+        let (current, index) = match (&self.current, self.index) {
+            (Some(current), Some(index)) => (current, index),
+            _ => return std::mem::take(self.list),
+        };
+        let prev = match current.borrow().prev.upgrade() {
+            Some(prev) => prev,
+            None => return DList::new(),
+        };
+        // the strong link from `prev` to `current` becomes the new head
+        let new_head = prev.borrow_mut().next.take();
+        current.borrow_mut().prev = Weak::new();
+
+        let split = DList {
+            head: std::mem::replace(&mut self.list.head, new_head),
+            tail: Rc::downgrade(&prev),
+            len: index,
+        };
+        self.list.len -= index;
+        self.index = Some(0);
+        split
+    }
+}
+
+pub fn run() {
+    let mut list: DList<i32> = (1..=5).collect();
+    list.push_front(0);
+    list.push_back(6);
+    println!("list = {:?} | len = {} | reversed = {:?}", list, list.len(), list.iter().rev().collect::<Vec<_>>());
+    println!("pop_front = {:?} | pop_back = {:?}", list.pop_front(), list.pop_back());
+
+    let (head, tail) = {
+        let mut cursor = list.cursor_front_mut();
+        cursor.move_next();
+        cursor.move_next();
+        println!("cursor at {:?} = {:?}", cursor.index(), cursor.current().map(|v| *v));
+        if let Some(mut value) = cursor.current() {
+            *value *= 100;
+        }
+        cursor.insert_before(25);
+        cursor.insert_after(35);
+        println!("removed {:?}, now at {:?}", cursor.remove_current(), cursor.index());
+        let tail = cursor.split_after();
+        (cursor.split_before(), tail)
+    };
+    println!("split into {:?} | {:?} | {:?}", head, list, tail);
+
+    let mut back: DList<i32> = tail.into_iter().rev().collect();
+    println!("rev tail = {:?} | empty = {}", back, DList::<i32>::new().is_empty());
+    let mut cursor = back.cursor_back_mut();
+    cursor.move_prev();
+    cursor.move_prev();
+    println!("back cursor after two move_prev = {:?}", cursor.index());
+}
+
+#[cfg(test)]
+fn values(list: &DList<i32>) -> Vec<i32> {
+    list.iter().collect()
+}
+
+/// Every node: one strong link from its predecessor (or `head`), one weak from its successor
+/// (or `tail`). Counts are taken through an upgrade, hence the `- 1`.
+#[cfg(test)]
+fn assert_links(list: &DList<i32>) {
+    let nodes = list.nodes();
+    assert_eq!(nodes.len(), list.len());
+    for node in &nodes {
+        let node = node.upgrade().unwrap();
+        assert_eq!((Rc::strong_count(&node) - 1, Rc::weak_count(&node)), (1, 2)); // + our Weak
+    }
+    let back: Vec<i32> = list.iter().rev().collect();
+    let mut forward = values(list);
+    forward.reverse();
+    assert_eq!(back, forward);
+}
+
+#[test]
+fn test_push_pop_both_ends() {
+    let mut list = DList::new();
+    assert_eq!(list.pop_front(), None);
+    assert_eq!(list.pop_back(), None);
+
+    list.push_back(2);
+    list.push_back(3);
+    list.push_front(1);
+    assert_eq!(values(&list), vec![1, 2, 3]);
+    assert_links(&list);
+
+    assert_eq!(list.pop_back(), Some(3));
+    assert_eq!(list.pop_front(), Some(1));
+    assert_eq!(list.pop_back(), Some(2));
+    assert!(list.is_empty());
+    assert!(list.head.is_none() && list.tail.upgrade().is_none());
+
+    list.push_front(9);
+    assert_eq!(list.pop_back(), Some(9));
+    assert_eq!(list.len(), 0);
+}
+
+#[test]
+fn test_double_ended_iterators() {
+    let list: DList<i32> = (1..=5).collect();
+    {
+        let mut iter = list.iter();
+        assert_eq!(iter.len(), 5);
+        assert_eq!(iter.next(), Some(1));
+        assert_eq!(iter.next_back(), Some(5));
+        assert_eq!(iter.next_back(), Some(4));
+        assert_eq!(iter.next(), Some(2));
+        assert_eq!(iter.next(), Some(3));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    assert_eq!(list.iter().rev().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1]);
+    let mut into = list.into_iter();
+    assert_eq!((into.next(), into.next_back(), into.len()), (Some(1), Some(5), 3));
+    assert_eq!(into.rev().collect::<Vec<_>>(), vec![4, 3, 2]);
+}
+
+#[test]
+fn test_cursor_move_and_edit() {
+    let mut list: DList<i32> = (1..=3).collect();
+    {
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(cursor.index(), Some(0));
+        cursor.move_prev();
+        assert_eq!(cursor.index(), None); // ghost
+        cursor.move_prev();
+        assert_eq!((cursor.index(), cursor.current().map(|v| *v)), (Some(2), Some(3)));
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(cursor.current().map(|v| *v), Some(1));
+
+        cursor.insert_before(0); // new head
+        assert_eq!(cursor.index(), Some(1));
+        cursor.move_next();
+        *cursor.current().unwrap() = 20;
+        cursor.insert_after(25);
+        cursor.insert_before(15);
+        assert_eq!(cursor.index(), Some(3));
+    }
+    assert_eq!(values(&list), vec![0, 1, 15, 20, 25, 3]);
+    assert_links(&list);
+
+    {
+        let mut cursor = list.cursor_back_mut();
+        cursor.insert_after(4); // new tail
+        assert_eq!(cursor.remove_current(), Some(3));
+        assert_eq!((cursor.index(), cursor.current().map(|v| *v)), (Some(5), Some(4)));
+        assert_eq!(cursor.remove_current(), Some(4));
+        assert_eq!(cursor.index(), None);
+        assert_eq!(cursor.remove_current(), None);
+        cursor.insert_after(-1); // on the ghost: front
+        cursor.insert_before(99); // on the ghost: back
+    }
+    assert_eq!(values(&list), vec![-1, 0, 1, 15, 20, 25, 99]);
+    assert_links(&list);
+
+    {
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(cursor.remove_current(), Some(-1));
+        assert_eq!(cursor.index(), Some(0));
+    }
+    assert_eq!(values(&list), vec![0, 1, 15, 20, 25, 99]);
+    assert_links(&list);
+}
+
+#[test]
+fn test_cursor_split() {
+    let mut list: DList<i32> = (0..6).collect();
+    let (before, after) = {
+        let mut cursor = list.cursor_front_mut();
+        cursor.move_next();
+        cursor.move_next();
+        let after = cursor.split_after();
+        let before = cursor.split_before();
+        assert_eq!(cursor.index(), Some(0));
+        (before, after)
+    };
+    assert_eq!((values(&before), values(&list), values(&after)), (vec![0, 1], vec![2], vec![3, 4, 5]));
+    assert_eq!((before.len(), list.len(), after.len()), (2, 1, 3));
+    for part in [&before, &list, &after].iter() {
+        assert_links(part);
+    }
+
+    // at the ends there is nothing to split off, on the ghost everything goes
+    let mut list: DList<i32> = (0..3).collect();
+    let all = {
+        let mut cursor = list.cursor_back_mut();
+        assert!(cursor.split_after().is_empty());
+        cursor.move_next();
+        cursor.split_before()
+    };
+    assert_eq!(values(&all), vec![0, 1, 2]);
+    assert!(list.is_empty());
+}
+
+#[test]
+fn test_no_leaks() {
+    let list: DList<i32> = (0..100).collect();
+    let nodes = list.nodes();
+    drop(list);
+    assert!(nodes.iter().all(|node| node.upgrade().is_none()));
+
+    let mut list: DList<i32> = (0..10).collect();
+    let nodes = list.nodes();
+    let tail = {
+        let mut cursor = list.cursor_front_mut();
+        cursor.move_next();
+        cursor.remove_current();
+        cursor.split_after()
+    };
+    assert_eq!(nodes.iter().filter(|node| node.upgrade().is_some()).count(), 9);
+    drop(list);
+    drop(tail);
+    assert!(nodes.iter().all(|node| node.upgrade().is_none()));
+
+    // long lists are dropped without recursion
+    let long: DList<u32> = (0..1_000_000).collect();
+    assert_eq!(long.len(), 1_000_000);
+    drop(long);
+}
@@ -0,0 +1,504 @@
+/// Own iterator adaptors
+///
+/// `iterator.rs` only chains the std adaptors. These are written by hand, the same way
+/// `Map` or `Filter` are: a struct holding the inner iterator plus whatever state the adaptor
+/// needs, and an `Iterator` impl. `IterExt` makes them methods on every iterator:
+///
+/// - `windows_by(size, step)`  windows of `size` items, each `step` items after the previous one
+/// - `chunks(n)`               `n` items at a time, the last chunk may be shorter
+/// - `interleave(other)`       one from each in turn, then the rest of the longer one
+/// - `dedup_by_key(f)`         drops items whose key equals the key of the item before
+/// - `group_by(f)`             runs of items with the same key, as `(key, items)`
+/// - `scan_while(init, f, p)`  running state `f(&state, item)` for as long as `p(&state)` holds
+/// - `step_by_fn(f)`           after each item skip `f(&item)` items
+/// - `cartesian_product(other)` every `(a, b)` pair, `other` is cloned for each `a`
+/// - `tee()`                   two iterators over the same items, a buffer holds what only
+///   one of them has seen yet
+///
+/// Every adaptor keeps `size_hint` honest: the real number of items left is always within it,
+/// `test_size_hints_hold` walks each one and checks that at every step.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::iter::Fuse;
+use std::rc::Rc;
+
+pub trait IterExt: Iterator + Sized {
+    fn windows_by(self, size: usize, step: usize) -> WindowsBy<Self> where Self::Item: Clone {
+        assert!(size > 0 && step > 0, "windows need a size and a step of at least 1");
+        WindowsBy { iter: self.fuse(), size, step, window: VecDeque::with_capacity(size), started: false }
+    }
+
+    fn chunks(self, size: usize) -> Chunks<Self> {
+        assert!(size > 0, "chunks need a size of at least 1");
+        Chunks { iter: self.fuse(), size }
+    }
+
+    fn interleave<J>(self, other: J) -> Interleave<Self, J::IntoIter>
+        where J: IntoIterator<Item = Self::Item> {
+        Interleave { a: self.fuse(), b: other.into_iter().fuse(), b_next: false }
+    }
+
+    fn dedup_by_key<K, F>(self, key: F) -> DedupByKey<Self, K, F>
+        where K: PartialEq, F: FnMut(&Self::Item) -> K {
+        DedupByKey { iter: self, key, last: None }
+    }
+
+    fn group_by<K, F>(self, key: F) -> GroupBy<Self, K, F>
+        where K: PartialEq, F: FnMut(&Self::Item) -> K {
+        GroupBy { iter: self.fuse(), key, pending: None }
+    }
+
+    fn scan_while<S, F, P>(self, init: S, f: F, predicate: P) -> ScanWhile<Self, S, F, P>
+        where S: Clone, F: FnMut(&S, Self::Item) -> S, P: FnMut(&S) -> bool {
+        ScanWhile { iter: self, state: init, f, predicate, done: false }
+    }
+
+    fn step_by_fn<F>(self, skip: F) -> StepByFn<Self, F> where F: FnMut(&Self::Item) -> usize {
+        StepByFn { iter: self, skip }
+    }
+
+    fn cartesian_product<J>(self, other: J) -> CartesianProduct<Self, J::IntoIter>
+        where Self::Item: Clone, J: IntoIterator, J::IntoIter: Clone {
+        let other = other.into_iter();
+        CartesianProduct { a: self, current: None, b: other.clone(), b_start: other }
+    }
+
+    fn tee(self) -> (Tee<Self>, Tee<Self>) where Self::Item: Clone {
+        let shared = Rc::new(RefCell::new(TeeShared { iter: self.fuse(), buffer: VecDeque::new(), ahead: false }));
+        (Tee { shared: Rc::clone(&shared), side: false }, Tee { shared, side: true })
+    }
+}
+
+impl<I: Iterator> IterExt for I {}
+
+fn add_hints(a: (usize, Option<usize>), b: (usize, Option<usize>)) -> (usize, Option<usize>) {
+    let hi = match (a.1, b.1) {
+        (Some(x), Some(y)) => x.checked_add(y),
+        _ => None,
+    };
+    (a.0.saturating_add(b.0), hi)
+}
+
+#[derive(Clone)]
+pub struct WindowsBy<I: Iterator> {
+    iter: Fuse<I>,
+    size: usize,
+    step: usize,
+    window: VecDeque<I::Item>,
+    started: bool,
+}
+
+impl<I: Iterator> WindowsBy<I> {
+    /// Windows in `available` items, `started` ones drop `step` first.
+    fn windows(&self, available: usize) -> usize {
+        let available = if self.started { available.saturating_sub(self.step) } else { available };
+        if available < self.size { 0 } else { (available - self.size) / self.step + 1 }
+    }
+}
+
+impl<I: Iterator> Iterator for WindowsBy<I> where I::Item: Clone {
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Vec<I::Item>> {
+        if self.started {
+            for _ in 0..self.step {
+                if self.window.pop_front().is_none() {
+                    self.iter.next()?;
+                }
+            }
+        }
+        self.started = true;
+        while self.window.len() < self.size {
+            self.window.push_back(self.iter.next()?);
+        }
+        Some(self.window.iter().cloned().collect())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = add_hints(self.iter.size_hint(), (self.window.len(), Some(self.window.len())));
+        (self.windows(lo), hi.map(|hi| self.windows(hi)))
+    }
+}
+
+#[derive(Clone)]
+pub struct Chunks<I: Iterator> {
+    iter: Fuse<I>,
+    size: usize,
+}
+
+impl<I: Iterator> Iterator for Chunks<I> {
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Vec<I::Item>> {
+        let chunk: Vec<I::Item> = self.iter.by_ref().take(self.size).collect();
+        if chunk.is_empty() { None } else { Some(chunk) }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        let chunks = |n: usize| n / self.size + !n.is_multiple_of(self.size) as usize;
+        (chunks(lo), hi.map(chunks))
+    }
+}
+
+#[derive(Clone)]
+pub struct Interleave<I, J> {
+    a: Fuse<I>,
+    b: Fuse<J>,
+    b_next: bool,
+}
+
+impl<I, J> Iterator for Interleave<I, J>
+    where I: Iterator, J: Iterator<Item = I::Item> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.b_next = !self.b_next;
+        if self.b_next {
+            self.a.next().or_else(|| self.b.next())
+        } else {
+            self.b.next().or_else(|| self.a.next())
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        add_hints(self.a.size_hint(), self.b.size_hint())
+    }
+}
+
+#[derive(Clone)]
+pub struct DedupByKey<I, K, F> {
+    iter: I,
+    key: F,
+    last: Option<K>,
+}
+
+impl<I, K, F> Iterator for DedupByKey<I, K, F>
+    where I: Iterator, K: PartialEq, F: FnMut(&I::Item) -> K {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        loop {
+            let item = self.iter.next()?;
+            let key = (self.key)(&item);
+            if self.last.as_ref() != Some(&key) {
+                self.last = Some(key);
+                return Some(item);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        // after the first item, all the rest may be duplicates
+        let lo = if self.last.is_none() && lo > 0 { 1 } else { 0 };
+        (lo, hi)
+    }
+}
+
+#[derive(Clone)]
+pub struct GroupBy<I: Iterator, K, F> {
+    iter: Fuse<I>,
+    key: F,
+    /// first item of the next group, read while looking for the end of the last one
+    pending: Option<(K, I::Item)>,
+}
+
+impl<I, K, F> Iterator for GroupBy<I, K, F>
+    where I: Iterator, K: PartialEq, F: FnMut(&I::Item) -> K {
+    type Item = (K, Vec<I::Item>);
+
+    fn next(&mut self) -> Option<(K, Vec<I::Item>)> {
+        let (key, first) = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                let item = self.iter.next()?;
+                ((self.key)(&item), item)
+            }
+        };
+        let mut group = vec![first];
+        for item in self.iter.by_ref() {
+            let next_key = (self.key)(&item);
+            if next_key != key {
+                self.pending = Some((next_key, item));
+                break;
+            }
+            group.push(item);
+        }
+        Some((key, group))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.pending.is_some() as usize;
+        let (lo, hi) = add_hints(self.iter.size_hint(), (pending, Some(pending)));
+        (lo.min(1), hi)
+    }
+}
+
+#[derive(Clone)]
+pub struct ScanWhile<I, S, F, P> {
+    iter: I,
+    state: S,
+    f: F,
+    predicate: P,
+    done: bool,
+}
+
+impl<I, S, F, P> Iterator for ScanWhile<I, S, F, P>
+    where I: Iterator, S: Clone, F: FnMut(&S, I::Item) -> S, P: FnMut(&S) -> bool {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        if self.done {
+            return None;
+        }
+        let item = self.iter.next()?;
+        let state = (self.f)(&self.state, item);
+        if !(self.predicate)(&state) {
+            self.done = true;
+            return None;
+        }
+        self.state = state.clone();
+        Some(state)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            (0, Some(0))
+        } else {
+            (0, self.iter.size_hint().1)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StepByFn<I, F> {
+    iter: I,
+    skip: F,
+}
+
+impl<I, F> Iterator for StepByFn<I, F>
+    where I: Iterator, F: FnMut(&I::Item) -> usize {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let item = self.iter.next()?;
+        let skip = (self.skip)(&item);
+        if skip > 0 {
+            self.iter.nth(skip - 1);
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        (lo.min(1), hi)
+    }
+}
+
+#[derive(Clone)]
+pub struct CartesianProduct<I: Iterator, J> {
+    a: I,
+    current: Option<I::Item>,
+    b: J,
+    /// `b` as it was at the start, cloned for every new `a`
+    b_start: J,
+}
+
+impl<I, J> Iterator for CartesianProduct<I, J>
+    where I: Iterator, I::Item: Clone, J: Iterator + Clone {
+    type Item = (I::Item, J::Item);
+
+    fn next(&mut self) -> Option<(I::Item, J::Item)> {
+        loop {
+            if self.current.is_none() {
+                self.current = Some(self.a.next()?);
+                self.b = self.b_start.clone();
+            }
+            match self.b.next() {
+                Some(b) => return self.current.clone().map(|a| (a, b)),
+                None => self.current = None,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let current = if self.current.is_some() { self.b.size_hint() } else { (0, Some(0)) };
+        let (a_lo, a_hi) = self.a.size_hint();
+        let (b_lo, b_hi) = self.b_start.size_hint();
+        let rest = (a_lo.saturating_mul(b_lo), a_hi.zip(b_hi).and_then(|(a, b)| a.checked_mul(b)));
+        add_hints(current, rest)
+    }
+}
+
+struct TeeShared<I: Iterator> {
+    iter: Fuse<I>,
+    /// items one side has seen and the other not yet
+    buffer: VecDeque<I::Item>,
+    /// the side which is ahead, the buffer is for the other one
+    ahead: bool,
+}
+
+pub struct Tee<I: Iterator> {
+    shared: Rc<RefCell<TeeShared<I>>>,
+    side: bool,
+}
+
+impl<I: Iterator> Iterator for Tee<I> where I::Item: Clone {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        // the other side was dropped: nobody will ever read what we would buffer for it
+        let alone = Rc::strong_count(&self.shared) == 1;
+        let mut shared = self.shared.borrow_mut();
+        if shared.ahead != self.side {
+            if let Some(item) = shared.buffer.pop_front() {
+                return Some(item);
+            }
+        }
+        let item = shared.iter.next()?;
+        if alone {
+            shared.buffer.clear();
+        } else {
+            shared.ahead = self.side;
+            shared.buffer.push_back(item.clone());
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let shared = self.shared.borrow();
+        let buffered = if shared.ahead != self.side { shared.buffer.len() } else { 0 };
+        add_hints(shared.iter.size_hint(), (buffered, Some(buffered)))
+    }
+}
+
+/// Walks `iter` to the end and checks at each step that the items left are within `size_hint`.
+#[cfg(test)]
+fn assert_size_hints<I: Iterator + Clone>(iter: I) {
+    let mut iter = iter;
+    loop {
+        let left = iter.clone().count();
+        let (lo, hi) = iter.size_hint();
+        assert!(lo <= left && hi.is_none_or(|hi| left <= hi), "{} items left, size_hint ({}, {:?})", left, lo, hi);
+        if iter.next().is_none() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_windows_and_chunks() {
+    let windows: Vec<_> = (1..=5).windows_by(3, 1).collect();
+    assert_eq!(windows, vec![vec![1, 2, 3], vec![2, 3, 4], vec![3, 4, 5]]);
+    let windows: Vec<_> = (1..=8).windows_by(2, 3).collect();
+    assert_eq!(windows, vec![vec![1, 2], vec![4, 5], vec![7, 8]]);
+    assert_eq!((1..3).windows_by(3, 1).count(), 0);
+    assert_eq!((1..=7).windows_by(2, 3).size_hint(), (2, Some(2)));
+
+    let chunks: Vec<_> = "abcdefg".chars().chunks(3).collect();
+    assert_eq!(chunks, vec![vec!['a', 'b', 'c'], vec!['d', 'e', 'f'], vec!['g']]);
+    assert_eq!((0..9).chunks(3).size_hint(), (3, Some(3)));
+    assert_eq!((0..0).chunks(3).next(), None);
+}
+
+#[test]
+fn test_interleave_and_cartesian_product() {
+    let mixed: Vec<_> = vec![1, 3, 5, 7, 9].into_iter().interleave(vec![2, 4]).collect();
+    assert_eq!(mixed, vec![1, 2, 3, 4, 5, 7, 9]);
+    assert_eq!((0..3).interleave(10..15).size_hint(), (8, Some(8)));
+
+    let pairs: Vec<_> = (1..=2).cartesian_product("ab".chars()).collect();
+    assert_eq!(pairs, vec![(1, 'a'), (1, 'b'), (2, 'a'), (2, 'b')]);
+    assert_eq!((0..3).cartesian_product(0..0).count(), 0);
+    let mut product = (0..3).cartesian_product(0..4);
+    product.next();
+    assert_eq!(product.size_hint(), (11, Some(11)));
+}
+
+#[test]
+fn test_dedup_and_group_by() {
+    let words = ["apple", "avocado", "banana", "blueberry", "cherry", "apricot"];
+    let deduped: Vec<_> = words.iter().dedup_by_key(|w| w.chars().next()).collect();
+    assert_eq!(deduped, vec![&"apple", &"banana", &"cherry", &"apricot"]);
+
+    let groups: Vec<_> = words.iter().group_by(|w| w.len()).map(|(len, g)| (len, g.len())).collect();
+    assert_eq!(groups, vec![(5, 1), (7, 1), (6, 1), (9, 1), (6, 1), (7, 1)]);
+    let groups: Vec<_> = vec![1, 1, 2, 3, 3, 3].into_iter().group_by(|n| *n).collect();
+    assert_eq!(groups, vec![(1, vec![1, 1]), (2, vec![2]), (3, vec![3, 3, 3])]);
+    assert_eq!((0..0).group_by(|n| *n).next(), None);
+}
+
+#[test]
+fn test_scan_while_and_step_by_fn() {
+    let totals: Vec<_> = (1..).scan_while(0, |total, n| total + n, |total| *total <= 10).collect();
+    assert_eq!(totals, vec![1, 3, 6, 10]);
+    let mut scan = (1..5).scan_while(0, |total, n| total + n, |total| *total < 4);
+    assert_eq!(scan.size_hint(), (0, Some(4)));
+    assert_eq!(scan.by_ref().count(), 2);
+    assert_eq!(scan.size_hint(), (0, Some(0)));
+
+    // jump as far as the value says
+    let jumps: Vec<_> = vec![1, 2, 0, 9, 1, 7, 5].into_iter().step_by_fn(|&n| n).collect();
+    assert_eq!(jumps, vec![1, 0, 9]);
+    let evens: Vec<_> = (0..10).step_by_fn(|_| 1).collect();
+    assert_eq!(evens, vec![0, 2, 4, 6, 8]);
+}
+
+#[test]
+fn test_tee() {
+    let (mut a, mut b) = (1..=4).map(|n| n * 10).tee();
+    assert_eq!(a.next(), Some(10));
+    assert_eq!(a.next(), Some(20));
+    assert_eq!(b.size_hint(), (4, Some(4)));
+    assert_eq!(a.size_hint(), (2, Some(2)));
+    assert_eq!(b.next(), Some(10));
+    // b overtakes a
+    assert_eq!(b.by_ref().collect::<Vec<_>>(), vec![20, 30, 40]);
+    assert_eq!(a.size_hint(), (2, Some(2)));
+    assert_eq!(a.collect::<Vec<_>>(), vec![30, 40]);
+    assert_eq!(b.next(), None);
+}
+
+#[test]
+fn test_tee_with_one_side_dropped() {
+    let buffered = |tee: &Tee<std::ops::Range<u32>>| tee.shared.borrow().buffer.len();
+
+    let (mut a, _) = (0..100_000).tee();
+    assert_eq!(a.by_ref().take(99_000).count(), 99_000);
+    assert_eq!(buffered(&a), 0);
+    assert_eq!(a.size_hint(), (1000, Some(1000)));
+
+    // dropped later: what was kept for it goes with the next item
+    let (mut a, b) = (0..10).tee();
+    a.by_ref().take(5).for_each(drop);
+    assert_eq!(buffered(&a), 5);
+    drop(b);
+    assert_eq!(a.next(), Some(5));
+    assert_eq!(buffered(&a), 0);
+
+    // the side left behind still gets what the dropped one had read ahead
+    let (a, mut b) = (0..6).tee();
+    b.by_ref().take(3).for_each(drop);
+    drop(b);
+    assert_eq!(a.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_size_hints_hold() {
+    let data = vec![3, 3, 1, 4, 1, 5, 5, 5, 2, 6, 0, 3];
+    let items = data.iter();
+    // `Tee` shares its state, so it can't be cloned: checked in `test_tee`
+    assert_size_hints(items.clone().windows_by(3, 2));
+    assert_size_hints(items.clone().windows_by(4, 1));
+    assert_size_hints(items.clone().chunks(5));
+    assert_size_hints(items.clone().interleave(&[7, 8, 9]));
+    assert_size_hints(items.clone().dedup_by_key(|n| **n));
+    assert_size_hints(items.clone().group_by(|n| **n % 2));
+    assert_size_hints(items.clone().scan_while(0, |total, n| total + n, |total| *total < 20));
+    assert_size_hints(items.clone().step_by_fn(|n| **n));
+    assert_size_hints(items.clone().cartesian_product(0..3));
+    assert_size_hints((0..5).filter(|n| n % 2 == 0).chunks(2));
+    assert_size_hints((0..7).filter(|n| n % 3 == 0).windows_by(2, 1));
+}
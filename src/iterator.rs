@@ -1,3 +1,12 @@
+// the lessons iterate over a `Vec` on purpose, an array would hide what `iter()` borrows
+#![allow(clippy::useless_vec)]
+
+use std::convert::TryFrom;
+use crate::iter_ext::IterExt;
+use crate::par_iter::Producer;
+#[cfg(test)]
+use crate::par_iter::ParSlice;
+
 pub fn run() {
     let v1 = vec![1, 2, 3];
     let v1_iter = v1.iter();
@@ -5,6 +14,22 @@ pub fn run() {
     for val in v1_iter {
         println!("Val: {}", val);
     }
+
+    // own adaptors from `iter_ext`, on a stepping `Counter`
+    let evens = Counter::range(0u32, 20, 2);
+    println!("evens: {:?} ({} of them)", evens.clone().collect::<Vec<_>>(), evens.len());
+    println!("windows_by(3, 2): {:?}", evens.clone().windows_by(3, 2).collect::<Vec<_>>());
+    println!("chunks(4): {:?}", evens.clone().chunks(4).collect::<Vec<_>>());
+    println!("interleave: {:?}", evens.clone().interleave(Counter::range(1, 6, 2)).collect::<Vec<_>>());
+    println!("dedup_by_key(n / 5): {:?}", evens.clone().dedup_by_key(|n| n / 5).collect::<Vec<_>>());
+    println!("group_by(n / 5): {:?}", evens.clone().group_by(|n| n / 5).collect::<Vec<_>>());
+    println!("scan_while(sum < 50): {:?}",
+             evens.clone().scan_while(0, |sum, n| sum + n, |sum| *sum < 50).collect::<Vec<_>>());
+    println!("step_by_fn(skip n / 4): {:?}", evens.clone().step_by_fn(|n| (n / 4) as usize).collect::<Vec<_>>());
+    println!("cartesian_product: {:?}",
+             Counter::range(0, 2, 1).cartesian_product("xy".chars()).collect::<Vec<_>>());
+    let (small, big) = evens.tee();
+    println!("tee: {:?} | {:?}", small.take(3).collect::<Vec<_>>(), big.skip(7).collect::<Vec<_>>());
 }
 
 #[test]
@@ -34,6 +59,21 @@ fn iterator_sum() {
     // v1_iter is unusable here since sum() take ownership of that iterator
 }
 
+/// `par_iter` splits the vector into chunks, sums every chunk on its own thread, then adds up
+/// the chunk sums
+#[test]
+fn par_iterator_sum() {
+    let v1 = vec![1, 2, 3];
+
+    let total: i32 = v1.par_iter().par_sum();
+
+    assert_eq!(total, 6);
+
+    let v2: Vec<i32> = (-500..1000).collect();
+    let sequential: i32 = v2.iter().sum();
+    assert_eq!(v2.par_iter().with_chunk_size(7).par_sum::<i32>(), sequential);
+}
+
 /// NOTE: 2 types of iterator trait associated method:
 /// - iterator adaptors (eg. map, filter,...)
 /// - consuming adaptors (eg. sum,...) - which call next() inside them to perform their tasks
@@ -56,7 +96,7 @@ fn test_consume() {
     assert_eq!(v2, vec![2, 3, 4]);
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 struct Shoe {
     size: u32,
     style: String,
@@ -67,12 +107,20 @@ struct Shoe {
 /// Hint: the trait `std::iter::FromIterator<&iterator::Shoe>` is not implemented     *
 /// for `std::vec::Vec<iterator::Shoe>`                                               *
 ///************************************************************************************
+/// NOTE: `shoes` loads shoes from a CSV file and runs queries on them, `size 10` is this function
 fn shoes_in_my_size(shoes: Vec<Shoe>, shoe_size: u32) -> Vec<Shoe> {
     shoes.into_iter()
         .filter(|s| s.size == shoe_size)
         .collect()
 }
 
+/// Same, but the shoes are checked in parallel; still in the order they came in
+fn par_shoes_in_my_size(shoes: Vec<Shoe>, shoe_size: u32) -> Vec<Shoe> {
+    shoes.into_par_iter()
+        .par_filter(|s| s.size == shoe_size)
+        .collect_vec()
+}
+
 #[test]
 fn filters_by_size() {
     let shoes = vec![
@@ -92,30 +140,109 @@ fn filters_by_size() {
     );
 }
 
+#[test]
+fn par_filters_by_size() {
+    let styles = ["sneaker", "sandal", "boot", "loafer"];
+    let shoes: Vec<Shoe> = (0..10_000)
+        .map(|i| Shoe { size: 5 + i % 9, style: format!("{}-{}", styles[i as usize % 4], i) })
+        .collect();
+
+    let in_my_size = par_shoes_in_my_size(shoes.clone(), 10);
+
+    assert_eq!(in_my_size.len(), 1111);
+    assert_eq!(in_my_size, shoes_in_my_size(shoes, 10));
+}
+
 /// Your own Iterator
-struct Counter {
-    count: u32
+///
+/// `Counter` started as "1 to 5". It is a stepping range now: `Counter::range(start, end, step)`
+/// counts from `start` up to (not including) `end` in steps of `step`, for any integer type.
+/// `Counter::new()` is still the book's 1 to 5.
+pub trait Stepping: Copy + PartialOrd + Default {
+    /// `self + step`, `None` when it overflows.
+    fn forward(self, step: Self) -> Option<Self>;
+    /// `self * factor`, `None` when it overflows.
+    fn scale(self, factor: Self) -> Option<Self>;
+    /// How many of `start, start + step, ..` are below `end`, `None` when that does not fit
+    /// a `usize`.
+    fn steps(start: Self, end: Self, step: Self) -> Option<usize>;
+}
+
+macro_rules! stepping {
+    ($($t:ty => $unsigned:ty),*) => {
+        $(
+            impl Stepping for $t {
+                fn forward(self, step: $t) -> Option<$t> {
+                    self.checked_add(step)
+                }
+
+                fn scale(self, factor: $t) -> Option<$t> {
+                    self.checked_mul(factor)
+                }
+
+                fn steps(start: $t, end: $t, step: $t) -> Option<usize> {
+                    if start >= end {
+                        return Some(0);
+                    }
+                    // the distance always fits the unsigned type of the same size
+                    let distance = end.wrapping_sub(start) as $unsigned;
+                    let steps = (distance - 1) / step as $unsigned + 1;
+                    usize::try_from(steps).ok()
+                }
+            }
+        )*
+    };
+}
+
+stepping!(u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128, usize => usize,
+          i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+#[derive(Clone, Debug)]
+pub struct Counter<T> {
+    next: Option<T>,
+    end: T,
+    step: T,
 }
 
-impl Counter {
-    fn new() -> Counter {
-        Counter { count: 0 }
+impl Counter<u32> {
+    fn new() -> Counter<u32> {
+        Counter::range(1, 6, 1)
     }
 }
 
-impl Iterator for Counter {
-    type Item = u32;
+impl<T: Stepping> Counter<T> {
+    pub fn range(start: T, end: T, step: T) -> Counter<T> {
+        assert!(step > T::default(), "a counter needs a positive step");
+        Counter { next: Some(start), end, step }
+    }
+}
+
+impl<T: Stepping> Iterator for Counter<T> {
+    type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.count += 1;
-        if self.count < 6 {
-            Some(self.count)
-        } else {
-            None
+        let count = self.next.filter(|count| *count < self.end)?;
+        self.next = count.forward(self.step);
+        Some(count)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.next.map_or(Some(0), |next| T::steps(next, self.end, self.step)) {
+            Some(left) => (left, Some(left)),
+            None => (usize::MAX, None),
         }
     }
 }
 
+// like `Range`, only where the count always fits a `usize`
+macro_rules! exact_size {
+    ($($t:ty),*) => {
+        $(impl ExactSizeIterator for Counter<$t> {})*
+    };
+}
+
+exact_size!(u8, u16, u32, usize, i8, i16, i32, isize);
+
 #[test]
 fn test_counter() {
     let mut counter = Counter::new();
@@ -137,3 +264,32 @@ fn fuck_up() {
 
     assert_eq!(18, sum);
 }
+
+#[test]
+fn test_counter_steps() {
+    assert_eq!(Counter::range(0, 10, 3).collect::<Vec<u8>>(), vec![0, 3, 6, 9]);
+    assert_eq!(Counter::range(-100i8, 100, 50).collect::<Vec<_>>(), vec![-100, -50, 0, 50]);
+    assert_eq!(Counter::range(-100i8, 100, 50).len(), 4);
+    assert_eq!(Counter::range(5u64, 5, 1).size_hint(), (0, Some(0)));
+    // stops instead of overflowing
+    assert_eq!(Counter::range(250u8, 255, 4).collect::<Vec<_>>(), vec![250, 254]);
+    assert_eq!(Counter::range(0u128, u128::MAX, u128::MAX / 2).size_hint(), (3, Some(3)));
+    // more items than a usize can count
+    assert_eq!(Counter::range(0u128, u128::MAX, 1).size_hint(), (usize::MAX, None));
+    assert_eq!(Counter::range(i128::MIN, 0, 1).size_hint(), (usize::MAX, None));
+
+    let mut counter = Counter::range(1usize, 8, 2);
+    counter.next();
+    assert_eq!(counter.size_hint(), (3, Some(3)));
+    assert_eq!(counter.sum::<usize>(), 3 + 5 + 7);
+}
+
+/// `iter_ext::IterExt` adds own adaptors next to the std ones
+#[test]
+fn test_counter_with_own_adaptors() {
+    let windows: Vec<u32> = Counter::range(0, 10, 2).windows_by(2, 1).map(|w| w[0] * w[1]).collect();
+    assert_eq!(windows, vec![0, 8, 24, 48]);
+    let grid: Vec<_> = Counter::new().chunks(2).cartesian_product(Counter::range(0, 2, 1)).collect();
+    assert_eq!(grid.len(), 6);
+    assert_eq!(grid[5], (vec![5], 1));
+}
@@ -0,0 +1,258 @@
+/// Lending iterator
+///
+/// `Iterator::next(&mut self) -> Option<Self::Item>` can not hand out something borrowed from
+/// the iterator itself: `Item` is one type for the whole loop, it has no way to name the
+/// lifetime of each `&mut self`. So `BufRead::lines` has to give every line its own `String`.
+///
+/// With a generic associated type the item can borrow from the call to `next`:
+///
+/// ```text
+/// type Item<'a> where Self: 'a;
+/// fn next(&mut self) -> Option<Self::Item<'_>>;
+/// ```
+///
+/// The price: the previous item must be dropped before asking for the next one, so there is
+/// no `collect` and no `for` loop. `LineReader` reads every line into the same `String` and
+/// lends it out as `&str`, after the first few lines (the buffer growing to the longest one)
+/// it does not allocate any more. Use `map_owned` to get back to a normal `Iterator` of owned
+/// values when some of them have to be kept.
+///
+/// NOTE: the closures of the adaptors take an item of any `'a`, and `Item<'a>` needs
+/// `Self: 'a`, so today's compiler asks for `Self: 'static` there. `next` works with any reader;
+/// for the adaptors the reader has to own its data: `BufReader<File>`, `io::stdin().lock()`,
+/// `Cursor<Vec<u8>>` or a `&'static [u8]`, not a `&[u8]` borrowed from a local.
+
+use std::io::{self, BufRead, Cursor};
+use std::time::Instant;
+
+pub trait LendingIterator {
+    type Item<'a> where Self: 'a;
+
+    fn next(&mut self) -> Option<Self::Item<'_>>;
+
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+        where Self: Sized, P: FnMut(&Self::Item<'_>) -> bool {
+        Filter { iter: self, predicate }
+    }
+
+    /// A normal `Iterator` again: `f` turns each lent item into something owned.
+    fn map_owned<T, F>(self, f: F) -> MapOwned<Self, F>
+        where Self: Sized, F: FnMut(Self::Item<'_>) -> T {
+        MapOwned { iter: self, f }
+    }
+
+    fn for_each<F>(mut self, mut f: F)
+        where Self: Sized, F: FnMut(Self::Item<'_>) {
+        while let Some(item) = self.next() {
+            f(item);
+        }
+    }
+
+    fn count(mut self) -> usize where Self: Sized {
+        let mut count = 0;
+        while self.next().is_some() {
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Lines of `reader` without their `\n` / `\r\n`, each one borrowed from the same buffer.
+///
+/// A read error ends the lines, `error` tells whether that is why they ended.
+pub struct LineReader<R> {
+    reader: R,
+    buffer: String,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader { reader, buffer: String::new(), error: None }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<R: BufRead> LendingIterator for LineReader<R> {
+    type Item<'a> = &'a str where R: 'a;
+
+    fn next(&mut self) -> Option<&str> {
+        self.buffer.clear();
+        match self.reader.read_line(&mut self.buffer) {
+            Ok(0) => None,
+            Ok(_) => {
+                let line = self.buffer.strip_suffix('\n').unwrap_or(&self.buffer);
+                Some(line.strip_suffix('\r').unwrap_or(line))
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+pub struct Filter<I, P> {
+    iter: I,
+    predicate: P,
+}
+
+impl<I, P> LendingIterator for Filter<I, P>
+    where I: LendingIterator, P: FnMut(&I::Item<'_>) -> bool {
+    type Item<'a> = I::Item<'a> where Self: 'a;
+
+    fn next(&mut self) -> Option<I::Item<'_>> {
+        let iter: *mut I = &mut self.iter;
+        loop {
+            // SAFETY: the borrow checker ties every `item` to the whole `&mut self` as soon as
+            // one of them is returned, even the ones that are dropped to try the next. Those are
+            // gone before `next` is called again, so there is only ever one live borrow of `iter`.
+            // (The plain loop is fine for Polonius, the next generation borrow checker.)
+            let item = unsafe { (*iter).next() }?;
+            if (self.predicate)(&item) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+pub struct MapOwned<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<I, F, T> Iterator for MapOwned<I, F>
+    where I: LendingIterator, F: FnMut(I::Item<'_>) -> T {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.next().map(&mut self.f)
+    }
+}
+
+/// `line <n> <word>`, `<n> % 7` words are `ERROR`.
+fn sample_log(lines: usize) -> Vec<u8> {
+    let mut log = Vec::new();
+    for n in 0..lines {
+        let level = if n % 7 == 0 { "ERROR" } else { "info" };
+        log.extend_from_slice(format!("line {} {} something happened\n", n, level).as_bytes());
+    }
+    log
+}
+
+/// Both count the `ERROR` lines and add up their lengths.
+fn scan_with_lines(log: &[u8]) -> (usize, usize) {
+    let (mut count, mut bytes) = (0, 0);
+    for line in log.lines().map_while(Result::ok).filter(|line| line.contains("ERROR")) {
+        count += 1;
+        bytes += line.len();
+    }
+    (count, bytes)
+}
+
+fn scan_with_line_reader<R: BufRead + 'static>(log: R) -> (usize, usize) {
+    let (mut count, mut bytes) = (0, 0);
+    LineReader::new(log).filter(|line| line.contains("ERROR")).for_each(|line| {
+        count += 1;
+        bytes += line.len();
+    });
+    (count, bytes)
+}
+
+pub fn run() {
+    let log = sample_log(1_000_000);
+    println!("{} bytes of log", log.len());
+
+    let now = Instant::now();
+    let lines = scan_with_lines(&log);
+    println!("BufRead::lines      : {:?} in {:?}, a String per line", lines, now.elapsed());
+
+    let owned = Cursor::new(log.clone());
+    let now = Instant::now();
+    let reader = scan_with_line_reader(owned);
+    println!("LineReader (lending): {:?} in {:?}, one buffer", reader, now.elapsed());
+    assert_eq!(lines, reader);
+
+    println!("lines               : {}", LineReader::new(Cursor::new(log.clone())).count());
+    let first: Vec<String> = LineReader::new(Cursor::new(log))
+        .filter(|line| line.ends_with("ERROR something happened"))
+        .map_owned(str::to_string)
+        .take(3)
+        .collect();
+    println!("kept with map_owned : {:?}", first);
+
+    let mut broken = LineReader::new(&b"fine\nnot utf-8 \xff\nnever read\n"[..]);
+    while let Some(line) = broken.next() {
+        println!("read {:?}", line);
+    }
+    println!("stopped because     : {:?}", broken.error().map(io::Error::kind));
+    // `cargo run --features alloc-stats -- lending` shows the allocations of the whole topic
+}
+
+#[test]
+fn test_line_reader() {
+    let text = "one\ntwo\r\n\nlast without newline";
+    let mut reader = LineReader::new(text.as_bytes());
+    assert_eq!(reader.next(), Some("one"));
+    assert_eq!(reader.next(), Some("two"));
+    assert_eq!(reader.next(), Some(""));
+    assert_eq!(reader.next(), Some("last without newline"));
+    assert_eq!(reader.next(), None);
+    assert!(reader.error().is_none());
+
+    assert_eq!(LineReader::new(&b""[..]).count(), 0);
+    assert_eq!(LineReader::new(text.as_bytes()).count(), text.lines().count());
+
+    let mut broken = LineReader::new(&b"fine\n\xff\xfe\n"[..]);
+    assert_eq!(broken.next(), Some("fine"));
+    assert_eq!(broken.next(), None);
+    assert_eq!(broken.error().map(io::Error::kind), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_adaptors() {
+    let text = "apple\nbanana\navocado\ncherry\napricot\n";
+
+    let a_words: Vec<String> = LineReader::new(text.as_bytes())
+        .filter(|line| line.starts_with('a'))
+        .map_owned(|line| line.to_uppercase())
+        .collect();
+    assert_eq!(a_words, vec!["APPLE", "AVOCADO", "APRICOT"]);
+
+    let lengths: Vec<usize> = LineReader::new(text.as_bytes()).map_owned(str::len).collect();
+    assert_eq!(lengths, vec![5, 6, 7, 6, 7]);
+
+    // filters stack, each one borrows the same buffer
+    let count = LineReader::new(text.as_bytes())
+        .filter(|line| line.len() > 5)
+        .filter(|line| line.contains('c'))
+        .count();
+    assert_eq!(count, 3);
+    assert_eq!(LineReader::new(text.as_bytes()).filter(|_| false).count(), 0);
+}
+
+#[test]
+fn test_million_lines_constant_allocations() {
+    use crate::alloc_stats::measure;
+
+    let log = sample_log(1_000_000);
+
+    let owned = Cursor::new(log.clone());
+    let (reader, stats) = measure(move || scan_with_line_reader(owned));
+    // the buffer starts empty and doubles up to the longest line, nothing per line
+    assert!(stats.allocations + stats.reallocations <= 8, "{}", stats);
+    assert_eq!(reader, (142_858, 142_858 * 30 + (0..1_000_000).step_by(7).map(|n: usize| n.to_string().len()).sum::<usize>()));
+
+    let (lines, stats) = measure(|| scan_with_lines(&log));
+    assert_eq!(lines, reader);
+    assert!(stats.allocations >= 1_000_000, "{}", stats);
+
+    // the same number of allocations for ten lines
+    let (short, long) = (Cursor::new(sample_log(10)), Cursor::new(log));
+    let (_, small) = measure(move || scan_with_line_reader(short));
+    let (_, big) = measure(move || scan_with_line_reader(long));
+    assert!(big.allocations + big.reallocations <= small.allocations + small.reallocations + 2, "{} vs {}", big, small);
+}
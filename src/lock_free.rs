@@ -0,0 +1,622 @@
+/// Lock-free data structures with `AtomicPtr`
+///
+/// `advance_unsafe.rs` shows raw pointers on one thread. Here several threads share raw
+/// pointers to heap nodes and change them with `compare_exchange` (CAS) instead of a lock:
+/// "replace `head` by `new` only if it is still `old`, otherwise tell me what it is now".
+/// A thread which loses the race simply retries with the fresh value.
+///
+/// The hard part is freeing memory. After a thread unlinks a node, another thread may still
+/// hold a pointer it loaded a moment before and be about to read `node.next`. Freeing the node
+/// right away would be a use-after-free, and reusing its address would break CAS (ABA problem).
+///
+/// Hazard pointers solve it:
+/// - before dereferencing a shared node, a thread publishes its address in a hazard slot
+/// - an unlinked node is not freed but `retired`
+/// - retired nodes are freed later, only if no hazard slot holds their address
+///
+/// Memory orderings:
+/// - `Release` when publishing a node (push) pairs with `Acquire` when loading it, so the
+///   node's fields written before the push are visible to whoever reads the pointer
+/// - publishing a hazard, then checking that the pointer is still reachable, vs unlinking a node,
+///   then reading the hazard slots, is the store-then-load pattern which only `SeqCst` orders:
+///   either the reclaimer sees the hazard, or the reader sees that the node is gone
+/// - counters that only decide when to scan use `Relaxed`
+
+use std::cell::UnsafeCell;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+pub fn run() {
+    let threads = 8;
+    let per_thread = 100_000;
+
+    let now = Instant::now();
+    let stack = Arc::new(Stack::new());
+    stress(threads, per_thread, &stack, |s, v| s.push(v), |s| s.pop());
+    println!("Treiber stack         : {:?}", now.elapsed());
+    assert!(stack.is_empty());
+
+    let now = Instant::now();
+    let queue = Arc::new(Queue::new());
+    stress(threads, per_thread, &queue, |q, v| q.push(v), |q| q.pop());
+    println!("Michael-Scott queue   : {:?}", now.elapsed());
+    assert!(queue.is_empty());
+
+    // lock-free is not automatically faster: an uncontended mutex is cheap and one allocation
+    // per push plus hazard bookkeeping is not, the difference shows with many busy cores
+    let now = Instant::now();
+    let vec = Arc::new(Mutex::new(Vec::new()));
+    stress(threads, per_thread, &vec, |v, x| v.lock().unwrap().push(x), |v| v.lock().unwrap().pop());
+    println!("Arc<Mutex<Vec<T>>>    : {:?}", now.elapsed());
+}
+
+/// Every thread pushes `per_thread` values and pops as many, returns everything popped.
+fn stress<S, Push, Pop>(threads: usize, per_thread: usize, shared: &Arc<S>, push: Push, pop: Pop) -> Vec<usize>
+    where S: Send + Sync + 'static,
+          Push: Fn(&S, usize) + Send + Sync + Copy + 'static,
+          Pop: Fn(&S) -> Option<usize> + Send + Sync + Copy + 'static {
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let shared = Arc::clone(shared);
+            thread::spawn(move || {
+                let mut popped = Vec::with_capacity(per_thread);
+                for i in 0..per_thread {
+                    push(&shared, t * per_thread + i);
+                    // never empty here: this thread pushed at least one value not popped yet
+                    popped.push(pop(&shared).expect("a value was just pushed"));
+                }
+                popped
+            })
+        })
+        .collect();
+
+    handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+}
+
+/// Retired node waiting until no hazard slot points at it.
+struct Retired {
+    ptr: *mut u8,
+    free: unsafe fn(*mut u8),
+    next: *mut Retired,
+}
+
+struct HazardSlot {
+    ptr: AtomicPtr<u8>,
+    in_use: AtomicBool,
+    // slots are only pushed in front of the list and freed with the domain, `next` never changes
+    next: *mut HazardSlot,
+}
+
+/// Hazard slots and retired nodes of one data structure.
+struct Domain {
+    slots: AtomicPtr<HazardSlot>,
+    slot_count: AtomicUsize,
+    retired: AtomicPtr<Retired>,
+    retired_count: AtomicUsize,
+}
+
+/// A claimed hazard slot, released when dropped.
+struct Hazard<'a> {
+    slot: &'a HazardSlot,
+}
+
+impl<'a> Hazard<'a> {
+    /// Load `src` and publish it as hazardous, retry until `src` did not change in between so the
+    /// node cannot have been retired before it was protected.
+    fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let mut p = src.load(Ordering::Acquire);
+        loop {
+            self.slot.ptr.store(p as *mut u8, Ordering::SeqCst);
+            let again = src.load(Ordering::SeqCst);
+            if again == p {
+                return p;
+            }
+            p = again;
+        }
+    }
+}
+
+impl<'a> Drop for Hazard<'a> {
+    fn drop(&mut self) {
+        self.slot.ptr.store(ptr::null_mut(), Ordering::Release);
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+impl Domain {
+    fn new() -> Domain {
+        Domain {
+            slots: AtomicPtr::new(ptr::null_mut()),
+            slot_count: AtomicUsize::new(0),
+            retired: AtomicPtr::new(ptr::null_mut()),
+            retired_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reuse a free slot, or add a new one when every slot is taken.
+    fn hazard(&self) -> Hazard<'_> {
+        let mut p = self.slots.load(Ordering::Acquire);
+        while !p.is_null() {
+            let slot = unsafe { &*p };
+            if slot.in_use.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return Hazard { slot };
+            }
+            p = slot.next;
+        }
+
+        let slot = Box::into_raw(Box::new(HazardSlot {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            in_use: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+
+        let mut head = self.slots.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*slot).next = head };
+            match self.slots.compare_exchange_weak(head, slot, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(actual) => head = actual,
+            }
+        }
+        self.slot_count.fetch_add(1, Ordering::Relaxed);
+
+        Hazard { slot: unsafe { &*slot } }
+    }
+
+    /// Hand an unlinked node over to the domain, `free` is called once nobody protects it.
+    ///
+    /// The retired list is itself a small Treiber stack, so retiring never blocks.
+    fn retire(&self, ptr: *mut u8, free: unsafe fn(*mut u8)) {
+        // counted before it is pushed, so a concurrent scan never frees more than was counted
+        let count = self.retired_count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let node = Box::into_raw(Box::new(Retired { ptr, free, next: ptr::null_mut() }));
+        self.push_retired(node, node);
+
+        // scanning costs O(slots), do it only once enough garbage piled up
+        if count >= 2 * self.slot_count.load(Ordering::Relaxed) + 64 {
+            self.scan();
+        }
+    }
+
+    /// Push the chain `first ..= last` in front of the retired list.
+    fn push_retired(&self, first: *mut Retired, last: *mut Retired) {
+        let mut head = self.retired.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*last).next = head };
+            match self.retired.compare_exchange_weak(head, first, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Take the whole retired list, free what is not protected and put the rest back.
+    fn scan(&self) {
+        let mut list = self.retired.swap(ptr::null_mut(), Ordering::Acquire);
+
+        let mut hazards = vec![];
+        let mut p = self.slots.load(Ordering::Acquire);
+        while !p.is_null() {
+            let slot = unsafe { &*p };
+            let hazard = slot.ptr.load(Ordering::SeqCst);
+            if !hazard.is_null() {
+                hazards.push(hazard);
+            }
+            p = slot.next;
+        }
+
+        let (mut keep_first, mut keep_last): (*mut Retired, *mut Retired) = (ptr::null_mut(), ptr::null_mut());
+        let mut freed = 0;
+
+        while !list.is_null() {
+            let node = unsafe { Box::from_raw(list) };
+            list = node.next;
+
+            if hazards.contains(&node.ptr) {
+                let node = Box::into_raw(node);
+                unsafe { (*node).next = keep_first };
+                if keep_last.is_null() {
+                    keep_last = node;
+                }
+                keep_first = node;
+            } else {
+                unsafe { (node.free)(node.ptr) };
+                freed += 1;
+            }
+        }
+
+        self.retired_count.fetch_sub(freed, Ordering::Relaxed);
+        if !keep_first.is_null() {
+            self.push_retired(keep_first, keep_last);
+        }
+    }
+}
+
+/// The owner of the domain is being dropped, so no other thread can hold a hazard any more.
+impl Drop for Domain {
+    fn drop(&mut self) {
+        let mut list = *self.retired.get_mut();
+        while !list.is_null() {
+            let node = unsafe { Box::from_raw(list) };
+            unsafe { (node.free)(node.ptr) };
+            list = node.next;
+        }
+
+        let mut slot = *self.slots.get_mut();
+        while !slot.is_null() {
+            let node = unsafe { Box::from_raw(slot) };
+            slot = node.next;
+        }
+    }
+}
+
+unsafe fn free_box<N>(ptr: *mut u8) {
+    drop(Box::from_raw(ptr as *mut N));
+}
+
+struct StackNode<T> {
+    // moved out by the thread which pops the node, so it must not be dropped with the node
+    value: ManuallyDrop<T>,
+    next: *mut StackNode<T>,
+}
+
+/// Treiber stack: a singly linked list whose `head` is swapped with CAS.
+pub struct Stack<T> {
+    head: AtomicPtr<StackNode<T>>,
+    domain: Domain,
+}
+
+// values move between threads through the stack, the raw pointers themselves are never aliased
+// mutably thanks to CAS + hazard pointers
+unsafe impl<T: Send> Send for Stack<T> {}
+unsafe impl<T: Send> Sync for Stack<T> {}
+
+impl<T> Stack<T> {
+    pub fn new() -> Stack<T> {
+        Stack { head: AtomicPtr::new(ptr::null_mut()), domain: Domain::new() }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(StackNode { value: ManuallyDrop::new(value), next: ptr::null_mut() }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // the node is still private, a plain write is fine
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let hazard = self.domain.hazard();
+
+        loop {
+            let head = hazard.protect(&self.head);
+            if head.is_null() {
+                return None;
+            }
+
+            // safe to read: `head` is protected, and `next` never changes once the node is pushed
+            let next = unsafe { (*head).next };
+
+            if self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                // only the thread winning the CAS gets here, so the value is moved out once
+                let value = unsafe { ManuallyDrop::take(&mut (*head).value) };
+                drop(hazard);
+                self.domain.retire(head as *mut u8, free_box::<StackNode<T>>);
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Stack<T> {
+        Stack::new()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node) };
+            unsafe { ManuallyDrop::drop(&mut boxed.value) };
+            node = boxed.next;
+        }
+    }
+}
+
+struct QueueNode<T> {
+    // uninitialized in the dummy node, i.e. the node `head` points at
+    value: UnsafeCell<MaybeUninit<T>>,
+    next: AtomicPtr<QueueNode<T>>,
+}
+
+impl<T> QueueNode<T> {
+    fn new(value: MaybeUninit<T>) -> *mut QueueNode<T> {
+        Box::into_raw(Box::new(QueueNode { value: UnsafeCell::new(value), next: AtomicPtr::new(ptr::null_mut()) }))
+    }
+}
+
+/// Michael-Scott queue: a linked list with a dummy node in front.
+///
+/// - `push` links the new node after the last one with CAS on `last.next`, then moves `tail`
+/// - `pop` moves `head` to the next node with CAS, that node becomes the new dummy
+/// - `tail` may lag one node behind, any thread seeing that helps by moving it forward
+pub struct Queue<T> {
+    head: AtomicPtr<QueueNode<T>>,
+    tail: AtomicPtr<QueueNode<T>>,
+    domain: Domain,
+}
+
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Queue<T> {
+        let dummy = QueueNode::new(MaybeUninit::uninit());
+        Queue { head: AtomicPtr::new(dummy), tail: AtomicPtr::new(dummy), domain: Domain::new() }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = QueueNode::new(MaybeUninit::new(value));
+        let hazard = self.domain.hazard();
+
+        loop {
+            let tail = hazard.protect(&self.tail);
+            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+
+            if !next.is_null() {
+                // `tail` is lagging behind, help moving it before trying again
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue;
+            }
+
+            let linked = unsafe { &(*tail).next }
+                .compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed);
+
+            if linked.is_ok() {
+                let _ = self.tail.compare_exchange(tail, node, Ordering::Release, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let head_hazard = self.domain.hazard();
+        let next_hazard = self.domain.hazard();
+
+        loop {
+            let head = head_hazard.protect(&self.head);
+            let next = next_hazard.protect(unsafe { &(*head).next });
+
+            // while `head` is still the head, `next` cannot have been dequeued and retired
+            if self.head.load(Ordering::SeqCst) != head {
+                continue;
+            }
+
+            if next.is_null() {
+                return None;
+            }
+
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                // never let `head` pass `tail`, move `tail` first
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue;
+            }
+
+            if self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                // `next` is the new dummy, its value is moved out by the winner only
+                let value = unsafe { (*(*next).value.get()).assume_init_read() };
+                drop(head_hazard);
+                drop(next_hazard);
+                self.domain.retire(head as *mut u8, free_box::<QueueNode<T>>);
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // the dummy may be popped and retired meanwhile, protect it before reading `next`
+        let hazard = self.domain.hazard();
+        let head = hazard.protect(&self.head);
+        unsafe { (*head).next.load(Ordering::Acquire).is_null() }
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Queue<T> {
+        Queue::new()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        // the dummy holds no value
+        let dummy = unsafe { Box::from_raw(*self.head.get_mut()) };
+        let mut node = dummy.next.load(Ordering::Relaxed);
+
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            unsafe { (*boxed.value.get()).assume_init_drop() };
+            node = boxed.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts drops so tests can check that nothing leaks and nothing is dropped twice.
+#[cfg(test)]
+struct Tracked {
+    value: usize,
+    drops: Arc<AtomicUsize>,
+}
+
+#[cfg(test)]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_stack_is_lifo() {
+    let stack = Stack::new();
+    assert!(stack.is_empty());
+    assert_eq!(stack.pop(), None);
+
+    stack.push(1);
+    stack.push(2);
+    stack.push(3);
+
+    assert_eq!(stack.pop(), Some(3));
+    assert_eq!(stack.pop(), Some(2));
+    assert_eq!(stack.pop(), Some(1));
+    assert_eq!(stack.pop(), None);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn test_queue_is_fifo() {
+    let queue = Queue::new();
+    assert!(queue.is_empty());
+    assert_eq!(queue.pop(), None);
+
+    queue.push(1);
+    queue.push(2);
+    queue.push(3);
+
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.pop(), Some(2));
+    queue.push(4);
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), Some(4));
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_every_value_dropped_once() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let tracked = |value| Tracked { value, drops: Arc::clone(&drops) };
+
+    {
+        let stack = Stack::new();
+        let queue = Queue::new();
+        for i in 0..100 {
+            stack.push(tracked(i));
+            queue.push(tracked(i));
+        }
+        // popped values are dropped here, the rest with the structures
+        for _ in 0..40 {
+            assert!(stack.pop().is_some());
+            assert!(queue.pop().is_some());
+        }
+    }
+
+    assert_eq!(drops.load(Ordering::SeqCst), 200);
+}
+
+#[cfg(test)]
+fn assert_same_values(mut popped: Vec<usize>, threads: usize, per_thread: usize) {
+    popped.sort();
+    assert_eq!(popped, (0..threads * per_thread).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_stack_stress_matches_mutex_vec() {
+    let (threads, per_thread) = (8, 20_000);
+
+    let stack = Arc::new(Stack::new());
+    let popped = stress(threads, per_thread, &stack, |s, v| s.push(v), |s| s.pop());
+    assert_same_values(popped, threads, per_thread);
+    assert!(stack.is_empty());
+
+    let vec = Arc::new(Mutex::new(Vec::new()));
+    let popped = stress(threads, per_thread, &vec, |v, x| v.lock().unwrap().push(x), |v| v.lock().unwrap().pop());
+    assert_same_values(popped, threads, per_thread);
+}
+
+#[test]
+fn test_queue_stress_matches_mutex_vec() {
+    let (threads, per_thread) = (8, 20_000);
+
+    let queue = Arc::new(Queue::new());
+    let popped = stress(threads, per_thread, &queue, |q, v| q.push(v), |q| q.pop());
+    assert_same_values(popped, threads, per_thread);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_queue_keeps_per_producer_order() {
+    let (producers, per_producer) = (4, 10_000);
+    let queue = Arc::new(Queue::new());
+
+    let handles: Vec<_> = (0..producers)
+        .map(|p| {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || (0..per_producer).for_each(|i| queue.push((p, i))))
+        })
+        .collect();
+
+    let mut last = vec![None; producers];
+    let mut received = 0;
+    while received < producers * per_producer {
+        if let Some((p, i)) = queue.pop() {
+            // FIFO: values of one producer come out in the order they were pushed
+            assert!(last[p].is_none_or(|prev| prev < i));
+            last[p] = Some(i);
+            received += 1;
+        }
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_no_leak_under_contention() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let (threads, per_thread) = (4, 5_000);
+
+    {
+        let stack = Arc::new(Stack::new());
+        let queue = Arc::new(Queue::new());
+
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let (stack, queue, drops) = (Arc::clone(&stack), Arc::clone(&queue), Arc::clone(&drops));
+                thread::spawn(move || {
+                    for i in 0..per_thread {
+                        stack.push(Tracked { value: i, drops: Arc::clone(&drops) });
+                        queue.push(Tracked { value: i, drops: Arc::clone(&drops) });
+                        if i % 2 == 0 {
+                            assert!(stack.pop().map(|t| t.value).is_some());
+                            assert!(queue.pop().map(|t| t.value).is_some());
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    assert_eq!(drops.load(Ordering::SeqCst), 2 * threads * per_thread);
+}
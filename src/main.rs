@@ -1,3 +1,6 @@
+// lessons use `///` blocks as section notes between items, not only as item docs
+#![allow(unused_doc_comments, clippy::empty_line_after_doc_comments, clippy::doc_lazy_continuation)]
+
 //mod print;
 //mod vars;
 //mod types;
@@ -12,7 +15,7 @@
 //mod structs;
 //mod enums;
 //mod cli;
-//mod borrow_move;
+mod borrow_move;
 //mod options;
 //mod module;
 //mod hashmap;
@@ -20,9 +23,12 @@
 //mod generic;
 //mod traits;
 //mod lifetime;
-//mod closure;
-//mod iterator;
-//mod smart_pointer;
+#[allow(dead_code)] // the workout variants are steps of the lesson, only one of them runs
+mod closure;
+#[allow(dead_code)] // most items only exist to be exercised by the lesson tests
+mod iterator;
+#[allow(dead_code)] // most items only exist to be exercised by the lesson tests
+mod smart_pointer;
 //mod concurrent;
 //mod channels;
 mod mutexs;
@@ -31,13 +37,76 @@ mod mutexs;
 //mod state_pattern_1;
 //mod matches;
 //mod pattern;
-//mod advance_unsafe;
+#[allow(dead_code)] // most items only exist to be exercised by the lesson tests
+mod advance_unsafe;
 //mod advance_trait;
 //mod advance_type;
 //mod advance_func;
 //mod advance_marco;
+mod par_slice;
+mod work_stealing;
+mod sync_primitives;
+mod lock_free;
+mod actor;
+mod cons_list;
+mod rc_cycles;
+mod rc_tree;
+mod my_rc;
+mod my_ref_cell;
+mod arena;
+mod dlist;
+mod tracked;
+mod quota;
+mod memo;
+mod shared_memo;
+mod memoize;
+mod workout;
+mod iter_ext;
+mod sequences;
+mod par_iter;
+mod shoes;
+mod lending;
+mod parse_error;
+#[cfg(any(test, feature = "alloc-stats"))]
+mod alloc_stats;
+#[cfg(test)]
+mod model_check;
 
 fn main() {
+    // `cargo run -- <topic>` runs one of the enabled topics, no topic runs the current lesson
+    let topic = std::env::args().nth(1);
+    // `cargo run --features alloc-stats -- <topic>` also prints how much the topic allocated
+    #[cfg(feature = "alloc-stats")]
+    let period = alloc_stats::Period::start();
+
+    match topic.as_deref() {
+        Some("mutexs") | None => mutexs::run(),
+        Some("advance_unsafe") => advance_unsafe::run(),
+        Some("par_slice") => par_slice::run(),
+        Some("work_stealing") => work_stealing::run(),
+        Some("sync_primitives") => sync_primitives::run(),
+        Some("lock_free") => lock_free::run(),
+        Some("actor") => actor::run(),
+        Some("borrow_move") => borrow_move::run(),
+        Some("smart_pointer") => smart_pointer::run(),
+        Some("closure") => closure::run(),
+        Some("iterator") => iterator::run(),
+        Some("arena") => arena::run(),
+        Some("dlist") => dlist::run(),
+        Some("quota") => quota::run(&std::env::args().skip(2).collect::<Vec<_>>()),
+        Some("shared_memo") => shared_memo::run(),
+        Some("memoize") => memoize::run(),
+        Some("workout") => workout::run(&std::env::args().skip(2).collect::<Vec<_>>()),
+        Some("sequences") => sequences::run(),
+        Some("par_iter") => par_iter::run(),
+        Some("shoes") => shoes::run(&std::env::args().skip(2).collect::<Vec<_>>()),
+        Some("lending") => lending::run(),
+        Some(other) => eprintln!("Unknown topic: {}", other),
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    alloc_stats::report(topic.as_deref().unwrap_or("mutexs"), period);
+
 //    print::run();
 //    vars::run();
 //    types:: run();
@@ -65,7 +134,6 @@ fn main() {
 //    smart_pointer::run();
 //    concurrent::run();
 //    channels::run();
-    mutexs::run();
 //    polymorphism::run();
 //    state_pattern::run();
 //    state_pattern_1::run();
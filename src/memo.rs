@@ -0,0 +1,448 @@
+/// Generic memoization cache
+///
+/// `closure::Memo` started as `HashMap<u32, u32>` next to a `Fn(u32) -> u32`: every value it
+/// ever computed stays forever. This one works for any `K: Hash + Eq + Clone` / `V: Clone` and
+/// can be bounded:
+///
+/// - `with_capacity(n)` keeps at most n values, the least recently used one is evicted first
+/// - `with_ttl(d)` makes values expire d after they were computed, `insert` can give one entry
+///   its own TTL
+/// - time comes from a `Clock`: `SystemClock` unless `with_clock` plugs in another one, like a
+///   closure returning the time; tests use a `ManualClock` instead of sleeping
+/// - `stats()` counts hits, misses, evictions and expirations
+///
+/// The recency order is a doubly linked list threaded through a `Vec` by index (like the free
+/// list in `arena`), the `HashMap` only maps a key to its slot, so every operation is O(1).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+pub trait Clock {
+    /// Time since some fixed start, only differences matter.
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// `|| Duration::from_secs(day * 86_400)` is a clock too.
+impl<F: Fn() -> Duration> Clock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// Only moves when told to. Clones share the time, so a test keeps one and gives one away.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    nanos: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(std::sync::atomic::Ordering::SeqCst))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: usize,
+    pub misses: usize,
+    /// Values dropped to make room.
+    pub evictions: usize,
+    /// Values found expired, they count as a miss too.
+    pub expirations: usize,
+}
+
+impl Stats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hits, {} misses ({:.0}% hit rate), {} evicted, {} expired",
+               self.hits, self.misses, self.hit_rate() * 100.0, self.evictions, self.expirations)
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    expires: Option<Duration>,
+    /// towards the most recently used end
+    prev: usize,
+    /// towards the least recently used end
+    next: usize,
+}
+
+pub struct Memo<K, V, F, C = SystemClock> where F: Fn(K) -> V {
+    calculation: F,
+    capacity: Option<usize>,
+    ttl: Option<Duration>,
+    clock: C,
+    index: HashMap<K, usize>,
+    slots: Vec<Option<Slot<K, V>>>,
+    free: Vec<usize>,
+    /// most recently used
+    head: usize,
+    /// least recently used, evicted first
+    tail: usize,
+    stats: Stats,
+}
+
+impl<K, V, F> Memo<K, V, F>
+    where K: Hash + Eq + Clone, V: Clone, F: Fn(K) -> V {
+    /// Unbounded and never expiring, like the original.
+    pub fn new(calculation: F) -> Memo<K, V, F> {
+        Memo {
+            calculation,
+            capacity: None,
+            ttl: None,
+            clock: SystemClock::default(),
+            index: HashMap::new(),
+            slots: vec![],
+            free: vec![],
+            head: NIL,
+            tail: NIL,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize, calculation: F) -> Memo<K, V, F> {
+        assert!(capacity > 0, "a memo needs room for at least one value");
+        Memo { capacity: Some(capacity), ..Memo::new(calculation) }
+    }
+}
+
+impl<K, V, F, C> Memo<K, V, F, C>
+    where K: Hash + Eq + Clone, V: Clone, F: Fn(K) -> V, C: Clock {
+    /// Every value computed from now on expires `ttl` after it was computed.
+    pub fn with_ttl(self, ttl: Duration) -> Memo<K, V, F, C> {
+        Memo { ttl: Some(ttl), ..self }
+    }
+
+    /// Same memo, different time source.
+    pub fn with_clock<C2: Clock>(self, clock: C2) -> Memo<K, V, F, C2> {
+        Memo {
+            calculation: self.calculation,
+            capacity: self.capacity,
+            ttl: self.ttl,
+            clock,
+            index: self.index,
+            slots: self.slots,
+            free: self.free,
+            head: self.head,
+            tail: self.tail,
+            stats: self.stats,
+        }
+    }
+
+    /// The cached value for `arg`, computed first if it is missing or expired.
+    pub fn value(&mut self, arg: K) -> V {
+        if let Some(value) = self.get(&arg) {
+            return value;
+        }
+        let value = (self.calculation)(arg.clone());
+        self.insert(arg, value.clone(), self.ttl);
+        value
+    }
+
+    /// The cached value without computing it; counts as a hit or a miss.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let now = self.clock.now();
+        let i = match self.index.get(key) {
+            Some(&i) => i,
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+        if self.slot(i).expires.is_some_and(|expires| expires <= now) {
+            self.remove_slot(i);
+            self.stats.expirations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.detach(i);
+        self.push_front(i);
+        Some(self.slot(i).value.clone())
+    }
+
+    /// Puts a value in without calling the calculation, with its own `ttl` (`None`: never
+    /// expires, and so does a `ttl` too long to add to the clock). Replaces an existing value
+    /// for `key`.
+    pub fn insert(&mut self, key: K, value: V, ttl: Option<Duration>) {
+        let expires = ttl.and_then(|ttl| self.clock.now().checked_add(ttl));
+        if let Some(&i) = self.index.get(&key) {
+            self.remove_slot(i);
+        }
+        if self.capacity.is_some_and(|capacity| self.index.len() >= capacity) {
+            self.remove_slot(self.tail);
+            self.stats.evictions += 1;
+        }
+
+        let slot = Slot { key: key.clone(), value, expires, prev: NIL, next: NIL };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.slots[i] = Some(slot);
+                i
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, i);
+        self.push_front(i);
+    }
+
+    /// Whether `key` has a value which is not expired; does not count or touch the order.
+    pub fn contains(&self, key: &K) -> bool {
+        let now = self.clock.now();
+        self.index.get(key)
+            .is_some_and(|&i| self.slot(i).expires.is_none_or(|expires| expires > now))
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = *self.index.get(key)?;
+        Some(self.remove_slot(i).value)
+    }
+
+    /// Drops every value, the statistics stay.
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.slots.clear();
+        self.free.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    /// Number of values held, expired ones included until they are looked up.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Keys from the most to the least recently used.
+    pub fn keys(&self) -> Vec<&K> {
+        let mut keys = Vec::with_capacity(self.len());
+        let mut i = self.head;
+        while i != NIL {
+            keys.push(&self.slot(i).key);
+            i = self.slot(i).next;
+        }
+        keys
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn slot(&self, i: usize) -> &Slot<K, V> {
+        self.slots[i].as_ref().expect("index points to a free slot")
+    }
+
+    fn slot_mut(&mut self, i: usize) -> &mut Slot<K, V> {
+        self.slots[i].as_mut().expect("index points to a free slot")
+    }
+
+    fn detach(&mut self, i: usize) {
+        let (prev, next) = (self.slot(i).prev, self.slot(i).next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.slot_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.slot_mut(next).prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        let head = self.head;
+        {
+            let slot = self.slot_mut(i);
+            slot.prev = NIL;
+            slot.next = head;
+        }
+        match head {
+            NIL => self.tail = i,
+            head => self.slot_mut(head).prev = i,
+        }
+        self.head = i;
+    }
+
+    fn remove_slot(&mut self, i: usize) -> Slot<K, V> {
+        self.detach(i);
+        let slot = self.slots[i].take().expect("index points to a free slot");
+        self.index.remove(&slot.key);
+        self.free.push(i);
+        slot
+    }
+}
+
+#[cfg(test)]
+fn counting_memo(calls: &std::cell::Cell<usize>) -> Memo<u32, String, impl Fn(u32) -> String + '_> {
+    Memo::with_capacity(3, move |n: u32| {
+        calls.set(calls.get() + 1);
+        n.to_string()
+    })
+}
+
+#[test]
+fn test_caches_and_counts() {
+    let calls = std::cell::Cell::new(0);
+    let mut memo = Memo::new(|word: String| {
+        calls.set(calls.get() + 1);
+        word.len()
+    });
+    assert_eq!(memo.value("hello".to_string()), 5);
+    assert_eq!(memo.value("hello".to_string()), 5);
+    assert_eq!(memo.value("hi".to_string()), 2);
+    assert_eq!(calls.get(), 2);
+    assert_eq!(memo.stats(), Stats { hits: 1, misses: 2, evictions: 0, expirations: 0 });
+    assert_eq!(memo.stats().to_string(), "1 hits, 2 misses (33% hit rate), 0 evicted, 0 expired");
+
+    assert_eq!(memo.remove(&"hi".to_string()), Some(2));
+    assert!(!memo.contains(&"hi".to_string()));
+    memo.clear();
+    assert!(memo.is_empty());
+    assert_eq!(memo.value("hello".to_string()), 5);
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn test_evicts_least_recently_used() {
+    let calls = std::cell::Cell::new(0);
+    let mut memo = counting_memo(&calls);
+    for n in [1, 2, 3].iter() {
+        memo.value(*n);
+    }
+    assert_eq!(memo.keys(), vec![&3, &2, &1]);
+
+    // using 1 makes 2 the oldest
+    assert_eq!(memo.value(1), "1");
+    assert_eq!(memo.keys(), vec![&1, &3, &2]);
+    memo.value(4);
+    assert_eq!(memo.keys(), vec![&4, &1, &3]);
+    assert!(!memo.contains(&2));
+    // `contains` does not count as a use
+    assert!(memo.contains(&3));
+    memo.value(5);
+    assert_eq!(memo.keys(), vec![&5, &4, &1]);
+
+    memo.value(2);
+    assert_eq!(calls.get(), 6);
+    assert_eq!(memo.len(), 3);
+    assert_eq!(memo.stats(), Stats { hits: 1, misses: 6, evictions: 3, expirations: 0 });
+    // freed slots are reused, the slot vector does not grow past the capacity
+    assert_eq!(memo.slots.len(), 3);
+}
+
+#[test]
+fn test_insert_replaces_and_keeps_capacity() {
+    let calls = std::cell::Cell::new(0);
+    let mut memo = counting_memo(&calls);
+    memo.insert(1, "one".to_string(), None);
+    memo.insert(2, "two".to_string(), None);
+    memo.insert(1, "uno".to_string(), None);
+    assert_eq!(memo.keys(), vec![&1, &2]);
+    assert_eq!(memo.value(1), "uno");
+    assert_eq!(calls.get(), 0);
+    assert_eq!(memo.stats().evictions, 0);
+}
+
+#[test]
+fn test_entries_expire() {
+    let clock = ManualClock::default();
+    let calls = std::cell::Cell::new(0);
+    let mut memo = counting_memo(&calls)
+        .with_ttl(Duration::from_secs(10))
+        .with_clock(clock.clone());
+
+    memo.value(1);
+    clock.advance(Duration::from_secs(5));
+    memo.value(2);
+    memo.insert(3, "forever".to_string(), None);
+    memo.insert(4, "short".to_string(), Some(Duration::from_secs(1)));
+    assert_eq!(memo.keys(), vec![&4, &3, &2]);
+
+    clock.advance(Duration::from_secs(5));
+    // 1 was evicted by 4 already, 2 has 5s left, 4 is gone
+    assert!(!memo.contains(&4));
+    assert_eq!(memo.get(&4), None);
+    assert_eq!(memo.value(2), "2");
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(memo.get(&2), None);
+    assert_eq!(memo.value(3), "forever");
+    assert_eq!(memo.len(), 1);
+
+    assert_eq!(calls.get(), 2);
+    assert_eq!(memo.stats(), Stats { hits: 2, misses: 4, evictions: 1, expirations: 2 });
+}
+
+#[test]
+fn test_closure_as_clock() {
+    let tick = std::rc::Rc::new(std::cell::Cell::new(0));
+    let now = std::rc::Rc::clone(&tick);
+    let mut memo = Memo::new(|n: u32| n * 2)
+        .with_ttl(Duration::from_secs(3))
+        .with_clock(move || Duration::from_secs(now.get()));
+
+    assert_eq!(memo.value(4), 8);
+    tick.set(2);
+    assert!(memo.contains(&4));
+    tick.set(3);
+    assert!(!memo.contains(&4));
+    assert_eq!(memo.value(4), 8);
+    assert_eq!(memo.stats(), Stats { hits: 0, misses: 2, evictions: 0, expirations: 1 });
+}
+
+#[test]
+fn test_huge_ttl_never_expires() {
+    let clock = ManualClock::default();
+    clock.advance(Duration::from_secs(1));
+    let mut memo = Memo::new(|n: u32| n + 1).with_ttl(Duration::MAX).with_clock(clock.clone());
+
+    assert_eq!(memo.value(1), 2);
+    memo.insert(2, 0, Some(Duration::MAX));
+    clock.advance(Duration::from_secs(u64::MAX / 2));
+    assert!(memo.contains(&1));
+    assert_eq!(memo.get(&2), Some(0));
+    assert_eq!(memo.stats().expirations, 0);
+}
@@ -0,0 +1,221 @@
+/// `memoize!` macro
+///
+/// `memo::Memo::value` can't memoize a recursive function: the closure would need the memo it
+/// is being called from. The macro turns a plain function definition into one which looks into
+/// a `Memo` first and fills it afterwards, so the recursive calls in the body hit the cache too:
+///
+/// ```text
+/// memoize! {
+///     fn fib(n: u64) -> u64 {
+///         if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+///     }
+/// }
+/// ```
+///
+/// - the key is the tuple of all arguments, `(n,)` here, so every argument must be `Clone` and
+///   owned (`String`, not `&str`: the cache outlives any borrow)
+/// - the cache is thread-local, no lock and no cross-talk between tests running in parallel
+/// - a module with the function's name (functions and modules don't share a namespace) holds
+///   the cache: `fib::clear()`, `fib::len()` and `fib::stats()`
+///
+/// NOTE: `macro_rules!` can't glue identifiers together (no `fib_clear`), that's why the helpers
+/// live in `mod fib` instead.
+
+use std::time::Instant;
+
+#[macro_export]
+macro_rules! memoize {
+    (
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $(#[$meta])*
+        $vis fn $name($($arg: $ty),*) -> $ret {
+            let key = ($($arg.clone(),)*);
+            // the borrow ends before the body runs, the recursive calls borrow again
+            if let Some(value) = $name::CACHE.with(|cache| cache.borrow_mut().get(&key)) {
+                return value;
+            }
+            // in a closure, so an early `return` in the body still ends up in the cache
+            #[allow(clippy::redundant_closure_call)]
+            let value = (move || -> $ret { $body })();
+            $name::CACHE.with(|cache| cache.borrow_mut().insert(key, value.clone(), None));
+            value
+        }
+
+        #[allow(dead_code)]
+        $vis mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            type Key = ($($ty,)*);
+
+            // the values are computed by the function above, never by the memo
+            fn computed_outside(_: Key) -> $ret {
+                unreachable!("memoize! fills the cache itself")
+            }
+
+            thread_local! {
+                pub(super) static CACHE: ::std::cell::RefCell<$crate::memo::Memo<Key, $ret, fn(Key) -> $ret>> =
+                    ::std::cell::RefCell::new($crate::memo::Memo::new(computed_outside as fn(Key) -> $ret));
+            }
+
+            /// Forgets every value of this thread, the statistics stay.
+            pub fn clear() {
+                CACHE.with(|cache| cache.borrow_mut().clear())
+            }
+
+            pub fn len() -> usize {
+                CACHE.with(|cache| cache.borrow().len())
+            }
+
+            pub fn stats() -> $crate::memo::Stats {
+                CACHE.with(|cache| cache.borrow().stats())
+            }
+        }
+    };
+}
+
+fn fib_plain(n: u64) -> u64 {
+    if n < 2 { n } else { fib_plain(n - 1) + fib_plain(n - 2) }
+}
+
+memoize! {
+    fn fib(n: u64) -> u64 {
+        if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+    }
+}
+
+memoize! {
+    /// Fewest single character inserts, removes and replaces turning `a` into `b`.
+    fn edit_distance(a: String, b: String) -> usize {
+        let (mut rest_a, mut rest_b) = (a.chars(), b.chars());
+        match (rest_a.next(), rest_b.next()) {
+            (None, _) => b.chars().count(),
+            (_, None) => a.chars().count(),
+            (Some(x), Some(y)) => {
+                let (rest_a, rest_b) = (rest_a.as_str().to_string(), rest_b.as_str().to_string());
+                if x == y {
+                    return edit_distance(rest_a, rest_b);
+                }
+                1 + edit_distance(rest_a.clone(), b.clone())
+                    .min(edit_distance(a.clone(), rest_b.clone()))
+                    .min(edit_distance(rest_a, rest_b))
+            }
+        }
+    }
+}
+
+pub fn run() {
+    let now = Instant::now();
+    println!("plain    fib(32) = {} in {:?}", fib_plain(32), now.elapsed());
+    let now = Instant::now();
+    println!("memoized fib(32) = {} in {:?} ({} values cached)", fib(32), now.elapsed(), fib::len());
+    println!("memoized fib(90) = {} | {}", fib(90), fib::stats());
+    fib::clear();
+
+    let (a, b) = ("intention", "execution");
+    let now = Instant::now();
+    println!("edit_distance({:?}, {:?}) = {} in {:?} | {}",
+             a, b, edit_distance(a.to_string(), b.to_string()), now.elapsed(), edit_distance::stats());
+}
+
+#[cfg(test)]
+thread_local! {
+    static CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Runs `f` and returns how many times the bodies below ran meanwhile.
+#[cfg(test)]
+fn calls<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    CALLS.with(|calls| calls.set(0));
+    let result = f();
+    (result, CALLS.with(|calls| calls.get()))
+}
+
+#[cfg(test)]
+fn count() {
+    CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+#[test]
+fn test_fib_call_counts() {
+    fn counted_plain(n: u64) -> u64 {
+        count();
+        if n < 2 { n } else { counted_plain(n - 1) + counted_plain(n - 2) }
+    }
+    memoize! {
+        fn counted(n: u64) -> u64 {
+            count();
+            if n < 2 { n } else { counted(n - 1) + counted(n - 2) }
+        }
+    }
+
+    assert_eq!(calls(|| counted_plain(20)), (6765, 21891));
+    // every n from 0 to 20 once
+    assert_eq!(calls(|| counted(20)), (6765, 21));
+    assert_eq!(calls(|| counted(20)), (6765, 0));
+    assert_eq!(calls(|| counted(22)), (17711, 2));
+    assert_eq!(counted::len(), 23);
+    let stats = counted::stats();
+    assert_eq!((stats.misses, stats.evictions), (23, 0));
+
+    counted::clear();
+    assert_eq!(counted::len(), 0);
+    assert_eq!(calls(|| counted(20)), (6765, 21));
+    assert_eq!(fib(90), 2_880_067_194_370_816_120);
+    assert_eq!(fib(90), fib(89) + fib(88));
+}
+
+#[test]
+fn test_edit_distance_with_tuple_keys() {
+    fn plain(a: &str, b: &str) -> usize {
+        count();
+        let (mut rest_a, mut rest_b) = (a.chars(), b.chars());
+        match (rest_a.next(), rest_b.next()) {
+            (None, _) => b.chars().count(),
+            (_, None) => a.chars().count(),
+            (Some(x), Some(y)) if x == y => plain(rest_a.as_str(), rest_b.as_str()),
+            _ => 1 + plain(rest_a.as_str(), b).min(plain(a, rest_b.as_str())).min(plain(rest_a.as_str(), rest_b.as_str())),
+        }
+    }
+    memoize! {
+        fn counted(a: String, b: String) -> usize {
+            count();
+            match (a.chars().next(), b.chars().next()) {
+                (None, _) => b.chars().count(),
+                (_, None) => a.chars().count(),
+                (Some(x), Some(y)) => {
+                    let (rest_a, rest_b) = (a[x.len_utf8()..].to_string(), b[y.len_utf8()..].to_string());
+                    if x == y {
+                        return counted(rest_a, rest_b);
+                    }
+                    1 + counted(rest_a.clone(), b.clone())
+                        .min(counted(a.clone(), rest_b.clone()))
+                        .min(counted(rest_a, rest_b))
+                }
+            }
+        }
+    }
+
+    let (distance, plain_calls) = calls(|| plain("kitten", "sitting"));
+    assert_eq!(distance, 3);
+    let (distance, memo_calls) = calls(|| counted("kitten".to_string(), "sitting".to_string()));
+    assert_eq!(distance, 3);
+    // at most one body run per pair of suffixes
+    assert!(memo_calls <= 7 * 8, "{} calls", memo_calls);
+    assert!(plain_calls > 10 * memo_calls, "{} plain vs {} memoized calls", plain_calls, memo_calls);
+    assert_eq!(counted::len(), memo_calls);
+
+    assert_eq!(edit_distance("intention".to_string(), "execution".to_string()), 5);
+    assert_eq!(edit_distance(String::new(), "abc".to_string()), 3);
+    assert_eq!(edit_distance("flaw".to_string(), "lawn".to_string()), 2);
+}
+
+#[test]
+fn test_cache_is_per_thread() {
+    assert_eq!(fib(30), 832_040);
+    assert!(fib::len() >= 31);
+    let other = std::thread::spawn(fib::len).join().unwrap();
+    assert_eq!(other, 0);
+}
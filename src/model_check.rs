@@ -0,0 +1,699 @@
+/// Deterministic interleaving explorer (test only)
+///
+/// Races like a lost update in `mutexs::multi_thread_mutex` (if the counter was not behind a
+/// `Mutex`) only show up when the OS happens to switch threads at the wrong moment. Running the
+/// test again and again hopes for that moment, this module forces it instead.
+///
+/// Threads of a model run on real OS threads but only one of them is allowed to run at a time.
+/// Every operation on the shims below (`Mutex`, `AtomicUsize`, `channel`, `thread::spawn`, `join`)
+/// is a switch point where the scheduler picks which thread goes next. The list of picks is the
+/// `schedule`, and running the same closure with the same schedule gives the same execution.
+///
+/// - `explore` tries every schedule (depth-first, up to a number of executions)
+/// - `explore_random` tries random schedules from a seed
+/// - `replay` runs exactly one schedule, e.g. the one printed by a failure
+///
+/// Only sequentially consistent interleavings are explored, weaker memory orderings are not modelled.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, Ordering};
+use std::sync::{Arc, Condvar, MutexGuard as StdMutexGuard};
+use std::thread as std_thread;
+
+type StdMutex<T> = std::sync::Mutex<T>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Runnable,
+    Blocked,
+    Finished,
+}
+
+enum Chooser {
+    // indexes into the enabled threads, missing steps take the first one
+    Dfs(Vec<usize>),
+    Random(u64),
+    Replay(Vec<usize>),
+}
+
+struct State {
+    status: Vec<Status>,
+    active: usize,
+    chooser: Chooser,
+    // (picked index, number of enabled threads) for every step, to find the next DFS branch
+    trace: Vec<(usize, usize)>,
+    schedule: Vec<usize>,
+    max_steps: usize,
+    failure: Option<String>,
+    aborted: bool,
+    done: bool,
+    handles: Vec<std_thread::JoinHandle<()>>,
+}
+
+impl State {
+    fn fail(&mut self, message: String) {
+        if self.failure.is_none() {
+            self.failure = Some(message);
+        }
+        self.aborted = true;
+    }
+
+    fn wake_blocked(&mut self) {
+        for s in self.status.iter_mut() {
+            if *s == Status::Blocked {
+                *s = Status::Runnable;
+            }
+        }
+    }
+}
+
+struct Execution {
+    state: StdMutex<State>,
+    cvar: Condvar,
+}
+
+/// Panic payload used to unwind the threads of an aborted execution.
+struct Abort;
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Execution>, usize)>> = const { RefCell::new(None) };
+}
+
+fn current() -> (Arc<Execution>, usize) {
+    CURRENT
+        .with(|c| c.borrow().clone())
+        .expect("model_check shims can only be used inside explore / explore_random / replay")
+}
+
+fn next_random(seed: &mut u64) -> u64 {
+    // xorshift64*
+    *seed ^= *seed >> 12;
+    *seed ^= *seed << 25;
+    *seed ^= *seed >> 27;
+    seed.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+impl Execution {
+    /// Pick the next thread to run, called by the running thread with the state locked.
+    fn pick_next(&self, st: &mut State) {
+        self.cvar.notify_all();
+
+        let enabled: Vec<usize> = (0..st.status.len()).filter(|&t| st.status[t] == Status::Runnable).collect();
+
+        if enabled.is_empty() {
+            if st.status.iter().all(|s| *s == Status::Finished) {
+                st.done = true;
+            } else {
+                let blocked: Vec<usize> = (0..st.status.len()).filter(|&t| st.status[t] == Status::Blocked).collect();
+                st.fail(format!("deadlock: threads {:?} are blocked forever", blocked));
+            }
+            return;
+        }
+
+        if st.schedule.len() >= st.max_steps {
+            st.fail(format!("no progress after {} steps, livelock?", st.max_steps));
+            return;
+        }
+
+        let step = st.schedule.len();
+        let index = match &mut st.chooser {
+            Chooser::Dfs(prefix) => prefix.get(step).cloned().unwrap_or(0).min(enabled.len() - 1),
+            Chooser::Random(seed) => (next_random(seed) % enabled.len() as u64) as usize,
+            Chooser::Replay(schedule) => match schedule.get(step).and_then(|t| enabled.iter().position(|e| e == t)) {
+                Some(index) => index,
+                None => {
+                    st.fail(format!("replayed schedule diverged at step {}, enabled threads: {:?}", step, enabled));
+                    return;
+                }
+            },
+        };
+
+        st.trace.push((index, enabled.len()));
+        st.active = enabled[index];
+        st.schedule.push(st.active);
+    }
+
+    /// Sleep until it is `me`'s turn, unwind if the execution was aborted meanwhile.
+    fn wait_turn<'a>(&self, mut st: StdMutexGuard<'a, State>, me: usize) -> StdMutexGuard<'a, State> {
+        while (st.active != me || st.status[me] != Status::Runnable) && !st.aborted {
+            st = self.cvar.wait(st).unwrap();
+        }
+
+        if st.aborted {
+            drop(st);
+            panic::resume_unwind(Box::new(Abort));
+        }
+
+        st
+    }
+}
+
+/// Switch point: the scheduler may run other threads before the caller continues.
+fn switch() {
+    let (exec, me) = current();
+    let mut st = exec.state.lock().unwrap();
+    exec.pick_next(&mut st);
+    drop(exec.wait_turn(st, me));
+}
+
+/// Block until `ready` gives a value, it is checked again every time a blocked thread may
+/// have been released (a mutex unlocked, a message sent, a thread finished).
+fn block_on<R, F>(mut ready: F) -> R
+    where F: FnMut() -> Option<R> {
+    let (exec, me) = current();
+
+    loop {
+        switch();
+
+        if let Some(r) = ready() {
+            return r;
+        }
+
+        let mut st = exec.state.lock().unwrap();
+        st.status[me] = Status::Blocked;
+        exec.pick_next(&mut st);
+        drop(exec.wait_turn(st, me));
+    }
+}
+
+fn wake_blocked() {
+    let (exec, _) = current();
+    exec.state.lock().unwrap().wake_blocked();
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("thread panicked")
+    }
+}
+
+/// Run `f` as model thread `id` on a new OS thread, it waits for its first turn before starting.
+fn start_thread<F, T>(exec: Arc<Execution>, id: usize, f: F, result: Arc<StdMutex<Option<T>>>) -> std_thread::JoinHandle<()>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    std_thread::spawn(move || {
+        CURRENT.with(|c| *c.borrow_mut() = Some((Arc::clone(&exec), id)));
+
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            drop(exec.wait_turn(exec.state.lock().unwrap(), id));
+            f()
+        }));
+
+        let mut st = exec.state.lock().unwrap();
+        match outcome {
+            Ok(value) => *result.lock().unwrap() = Some(value),
+            Err(payload) => {
+                if !payload.is::<Abort>() {
+                    st.fail(format!("thread {} panicked: {}", id, panic_message(payload.as_ref())));
+                }
+            }
+        }
+
+        st.status[id] = Status::Finished;
+        st.wake_blocked();
+
+        if st.aborted {
+            exec.cvar.notify_all();
+        } else {
+            exec.pick_next(&mut st);
+        }
+    })
+}
+
+/// Outcome of one execution.
+struct Run {
+    trace: Vec<(usize, usize)>,
+    schedule: Vec<usize>,
+    failure: Option<String>,
+}
+
+fn run_once(chooser: Chooser, max_steps: usize, f: Arc<dyn Fn() + Send + Sync>) -> Run {
+    let exec = Arc::new(Execution {
+        state: StdMutex::new(State {
+            status: vec![Status::Runnable],
+            active: 0,
+            chooser,
+            trace: vec![],
+            schedule: vec![],
+            max_steps,
+            failure: None,
+            aborted: false,
+            done: false,
+            handles: vec![],
+        }),
+        cvar: Condvar::new(),
+    });
+
+    let main = start_thread(Arc::clone(&exec), 0, move || f(), Arc::new(StdMutex::new(None)));
+
+    {
+        let mut st = exec.state.lock().unwrap();
+        while !st.done && !st.aborted {
+            st = exec.cvar.wait(st).unwrap();
+        }
+    }
+
+    main.join().unwrap();
+    loop {
+        let handle = exec.state.lock().unwrap().handles.pop();
+        match handle {
+            Some(handle) => handle.join().unwrap(),
+            None => break,
+        }
+    }
+
+    let mut st = exec.state.lock().unwrap();
+    Run { trace: std::mem::take(&mut st.trace), schedule: std::mem::take(&mut st.schedule), failure: st.failure.take() }
+}
+
+/// Deepest step which still has an unexplored sibling, then everything before it unchanged.
+fn next_prefix(trace: &[(usize, usize)]) -> Option<Vec<usize>> {
+    let k = trace.iter().rposition(|&(index, enabled)| index + 1 < enabled)?;
+    let mut prefix: Vec<usize> = trace[..k].iter().map(|&(index, _)| index).collect();
+    prefix.push(trace[k].0 + 1);
+    Some(prefix)
+}
+
+const MAX_STEPS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub executions: usize,
+    /// `true` when every interleaving was explored.
+    pub complete: bool,
+}
+
+/// A failing interleaving, `replay(&failure.schedule, f)` runs it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub message: String,
+    pub schedule: Vec<usize>,
+    pub execution: usize,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (execution #{}), replay with schedule {:?}", self.message, self.execution, self.schedule)
+    }
+}
+
+/// Explore interleavings of `f` depth-first, at most `max_executions` of them.
+pub fn explore<F>(max_executions: usize, f: F) -> Result<Report, Failure>
+    where F: Fn() + Send + Sync + 'static {
+    let f: Arc<dyn Fn() + Send + Sync> = Arc::new(f);
+    let mut prefix = vec![];
+
+    for execution in 1..=max_executions {
+        let run = run_once(Chooser::Dfs(prefix), MAX_STEPS, Arc::clone(&f));
+
+        if let Some(message) = run.failure {
+            return Err(Failure { message, schedule: run.schedule, execution });
+        }
+
+        match next_prefix(&run.trace) {
+            Some(next) => prefix = next,
+            None => return Ok(Report { executions: execution, complete: true }),
+        }
+    }
+
+    Ok(Report { executions: max_executions, complete: false })
+}
+
+/// Run `f` under `iterations` random schedules derived from `seed`.
+pub fn explore_random<F>(seed: u64, iterations: usize, f: F) -> Result<Report, Failure>
+    where F: Fn() + Send + Sync + 'static {
+    let f: Arc<dyn Fn() + Send + Sync> = Arc::new(f);
+    let mut rng = seed | 1;
+
+    for execution in 1..=iterations {
+        let run = run_once(Chooser::Random(next_random(&mut rng) | 1), MAX_STEPS, Arc::clone(&f));
+
+        if let Some(message) = run.failure {
+            return Err(Failure { message, schedule: run.schedule, execution });
+        }
+    }
+
+    Ok(Report { executions: iterations, complete: false })
+}
+
+/// Run `f` once with exactly this schedule.
+pub fn replay<F>(schedule: &[usize], f: F) -> Result<Report, Failure>
+    where F: Fn() + Send + Sync + 'static {
+    let run = run_once(Chooser::Replay(schedule.to_vec()), MAX_STEPS, Arc::new(f));
+
+    match run.failure {
+        Some(message) => Err(Failure { message, schedule: run.schedule, execution: 1 }),
+        None => Ok(Report { executions: 1, complete: false }),
+    }
+}
+
+/// Shim of `std::sync::Mutex`, waiting for the lock lets the other threads run.
+pub struct Mutex<T> {
+    owner: StdMutex<Option<usize>>,
+    // only touched by the model owner, so this lock is never contended
+    data: StdMutex<T>,
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    data: Option<StdMutexGuard<'a, T>>,
+}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Mutex<T> {
+        Mutex { owner: StdMutex::new(None), data: StdMutex::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let (_, me) = current();
+
+        block_on(|| {
+            let mut owner = self.owner.lock().unwrap();
+            if owner.is_none() {
+                *owner = Some(me);
+                Some(())
+            } else {
+                None
+            }
+        });
+
+        MutexGuard { mutex: self, data: Some(self.data.lock().unwrap_or_else(|e| e.into_inner())) }
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data.as_ref().unwrap()
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data.as_mut().unwrap()
+    }
+}
+
+/// Unlocking is not a switch point, the next operation of this thread is.
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.data.take();
+        *self.mutex.owner.lock().unwrap() = None;
+        wake_blocked();
+    }
+}
+
+/// Shim of `std::sync::atomic::AtomicUsize`, every access is a switch point.
+pub struct AtomicUsize {
+    inner: atomic::AtomicUsize,
+}
+
+impl AtomicUsize {
+    pub fn new(value: usize) -> AtomicUsize {
+        AtomicUsize { inner: atomic::AtomicUsize::new(value) }
+    }
+
+    pub fn load(&self) -> usize {
+        switch();
+        self.inner.load(Ordering::SeqCst)
+    }
+
+    pub fn store(&self, value: usize) {
+        switch();
+        self.inner.store(value, Ordering::SeqCst)
+    }
+
+    pub fn fetch_add(&self, value: usize) -> usize {
+        switch();
+        self.inner.fetch_add(value, Ordering::SeqCst)
+    }
+}
+
+struct Chan<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+}
+
+/// Shim of `std::sync::mpsc::channel`.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let chan = Arc::new(StdMutex::new(Chan { queue: VecDeque::new(), senders: 1 }));
+    (Sender { chan: Arc::clone(&chan) }, Receiver { chan })
+}
+
+pub struct Sender<T> {
+    chan: Arc<StdMutex<Chan<T>>>,
+}
+
+pub struct Receiver<T> {
+    chan: Arc<StdMutex<Chan<T>>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        switch();
+        self.chan.lock().unwrap().queue.push_back(value);
+        wake_blocked();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.chan.lock().unwrap().senders += 1;
+        Sender { chan: Arc::clone(&self.chan) }
+    }
+}
+
+/// The receiver may be waiting for a message which will never come now.
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.lock().unwrap().senders -= 1;
+        wake_blocked();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// `None` once the queue is empty and every sender is gone.
+    pub fn recv(&self) -> Option<T> {
+        block_on(|| {
+            let mut chan = self.chan.lock().unwrap();
+            match chan.queue.pop_front() {
+                Some(value) => Some(Some(value)),
+                None if chan.senders == 0 => Some(None),
+                None => None,
+            }
+        })
+    }
+}
+
+/// Shim of `std::thread`.
+pub mod thread {
+    use super::*;
+
+    pub struct JoinHandle<T> {
+        result: Arc<StdMutex<Option<T>>>,
+        id: usize,
+    }
+
+    pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+        let (exec, _) = current();
+        let result = Arc::new(StdMutex::new(None));
+
+        let id = {
+            let mut st = exec.state.lock().unwrap();
+            st.status.push(Status::Runnable);
+            st.status.len() - 1
+        };
+
+        let handle = start_thread(Arc::clone(&exec), id, f, Arc::clone(&result));
+        exec.state.lock().unwrap().handles.push(handle);
+
+        switch();
+
+        JoinHandle { result, id }
+    }
+
+    impl<T> JoinHandle<T> {
+        /// A panic in the joined thread already failed the whole execution, so there is no `Err`.
+        pub fn join(self) -> T {
+            let (exec, _) = current();
+
+            block_on(|| {
+                if exec.state.lock().unwrap().status[self.id] == Status::Finished {
+                    self.result.lock().unwrap().take()
+                } else {
+                    None
+                }
+            })
+        }
+    }
+}
+
+/// `mutexs::multi_thread_mutex` on the shims, with fewer threads.
+fn mutex_counter(threads: usize) {
+    let counter = Arc::new(Mutex::new(0));
+    let mut handles = vec![];
+
+    for _ in 0..threads {
+        let counter = Arc::clone(&counter);
+        handles.push(thread::spawn(move || {
+            let mut num = counter.lock();
+            *num += 1;
+        }));
+    }
+
+    for handle in handles {
+        handle.join();
+    }
+
+    assert_eq!(*counter.lock(), threads);
+}
+
+/// The same counter without a lock: read, then write back, another thread can slip in between.
+fn racy_counter() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut handles = vec![];
+
+    for _ in 0..2 {
+        let counter = Arc::clone(&counter);
+        handles.push(thread::spawn(move || {
+            let num = counter.load();
+            counter.store(num + 1);
+        }));
+    }
+
+    for handle in handles {
+        handle.join();
+    }
+
+    assert_eq!(counter.load(), 2, "lost update");
+}
+
+#[test]
+fn test_mutex_counter_is_correct_in_every_interleaving() {
+    // the number of interleavings grows exponentially with threads, two already cover the lesson
+    let report = explore(100_000, || mutex_counter(2)).unwrap();
+    assert!(report.complete);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn test_atomic_fetch_add_is_correct_in_every_interleaving() {
+    let report = explore(100_000, || {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                thread::spawn(move || counter.fetch_add(1))
+            })
+            .collect();
+
+        let mut seen: Vec<usize> = handles.into_iter().map(|h| h.join()).collect();
+        seen.sort();
+        assert_eq!(seen, vec![0, 1]);
+        assert_eq!(counter.load(), 2);
+    }).unwrap();
+
+    assert!(report.complete);
+}
+
+#[test]
+fn test_lost_update_is_found_and_replayed() {
+    let failure = explore(100_000, racy_counter).unwrap_err();
+    assert!(failure.message.contains("lost update"), "{}", failure);
+
+    // the same schedule fails the same way, every time
+    for _ in 0..3 {
+        let again = replay(&failure.schedule, racy_counter).unwrap_err();
+        assert_eq!(again.message, failure.message);
+        assert_eq!(again.schedule, failure.schedule);
+    }
+}
+
+#[test]
+fn test_random_exploration_is_reproducible_from_seed() {
+    let first = explore_random(42, 1000, racy_counter).unwrap_err();
+    let second = explore_random(42, 1000, racy_counter).unwrap_err();
+
+    assert!(first.message.contains("lost update"));
+    assert_eq!(first, second);
+    assert!(replay(&first.schedule, racy_counter).is_err());
+}
+
+/// Two threads taking the same two locks in opposite order.
+fn lock_order_deadlock() {
+    let a = Arc::new(Mutex::new(()));
+    let b = Arc::new(Mutex::new(()));
+
+    let (a1, b1) = (Arc::clone(&a), Arc::clone(&b));
+    let first = thread::spawn(move || {
+        let _a = a1.lock();
+        let _b = b1.lock();
+    });
+
+    let second = thread::spawn(move || {
+        let _b = b.lock();
+        let _a = a.lock();
+    });
+
+    first.join();
+    second.join();
+}
+
+#[test]
+fn test_lock_order_deadlock_is_reported() {
+    let failure = explore(100_000, lock_order_deadlock).unwrap_err();
+
+    assert!(failure.message.starts_with("deadlock"), "{}", failure);
+    assert_eq!(replay(&failure.schedule, lock_order_deadlock).unwrap_err().message, failure.message);
+}
+
+#[test]
+fn test_channel_keeps_per_producer_order() {
+    let report = explore(100_000, || {
+        let (tx, rx) = channel();
+
+        let handles: Vec<_> = (0..2)
+            .map(|p| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    tx.send((p, 0));
+                    tx.send((p, 1));
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut received = vec![];
+        while let Some(msg) = rx.recv() {
+            received.push(msg);
+        }
+
+        for handle in handles {
+            handle.join();
+        }
+
+        assert_eq!(received.len(), 4);
+        for p in 0..2 {
+            let mine: Vec<_> = received.iter().filter(|m| m.0 == p).collect();
+            assert_eq!(mine, vec![&(p, 0), &(p, 1)]);
+        }
+    }).unwrap();
+
+    assert!(report.complete);
+}
+
+#[test]
+fn test_schedule_diverging_from_program_is_reported() {
+    // thread 5 never exists
+    let failure = replay(&[0, 5], racy_counter).unwrap_err();
+    assert!(failure.message.contains("diverged"), "{}", failure);
+}
@@ -3,6 +3,7 @@ use std::sync::Mutex;
 pub fn run() {
 //    simple();
 //    another_simple();
+//    poisoned();
     multi_thread_mutex();
 }
 
@@ -59,3 +60,188 @@ fn multi_thread_mutex() {
 
     println!("Result: {}", *counter.lock().unwrap());
 }
+
+// Mutex poisoning
+//
+// When a thread panics while it holds a `MutexGuard`, the guard is dropped during unwinding and
+// the mutex is marked as `poisoned`. Every later call to `lock()` returns `Err(PoisonError)`
+// since the data may have been left half-updated. The error still owns the guard, so the data
+// can be taken back with `PoisonError::into_inner` once we know how to make it consistent again.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::MutexGuard;
+
+/// Guard returned by `lock_recover`, it derefs to the protected data like `MutexGuard` does
+/// and remembers whether the lock had to be taken back from a poisoned mutex.
+pub struct Recovered<'a, T> {
+    guard: MutexGuard<'a, T>,
+    poisoned: bool,
+}
+
+impl<'a, T> Recovered<'a, T> {
+    pub fn was_poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
+
+impl<'a, T> Deref for Recovered<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for Recovered<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Lock the mutex even if it was poisoned. The mutex stays poisoned, so other callers of `lock()`
+/// still see that something went wrong.
+pub fn lock_recover<T>(m: &Mutex<T>) -> Recovered<'_, T> {
+    match m.lock() {
+        Ok(guard) => Recovered { guard, poisoned: false },
+        Err(err) => Recovered { guard: err.into_inner(), poisoned: true },
+    }
+}
+
+/// Lock the mutex and, if it was poisoned, run `repair` on the data to restore its invariants.
+/// Since the data is consistent again after `repair`, the poison flag is cleared and later
+/// calls to `lock()` succeed as usual.
+pub fn lock_recover_with<T, F>(m: &Mutex<T>, repair: F) -> Recovered<'_, T>
+    where F: FnOnce(&mut T) {
+    let mut recovered = lock_recover(m);
+
+    if recovered.poisoned {
+        repair(&mut recovered);
+        m.clear_poison();
+    }
+
+    recovered
+}
+
+/// Two accounts moving money between each other, the total must never change.
+#[derive(Debug)]
+struct Accounts {
+    a: i64,
+    b: i64,
+    total: i64,
+}
+
+impl Accounts {
+    fn is_consistent(&self) -> bool {
+        self.a + self.b == self.total
+    }
+}
+
+#[allow(dead_code)]
+fn poisoned() {
+    let m = Arc::new(Mutex::new(Accounts { a: 100, b: 0, total: 100 }));
+
+    let worker = {
+        let m = Arc::clone(&m);
+        thread::spawn(move || {
+            let mut acc = m.lock().unwrap();
+            acc.a -= 30;
+            panic!("worker died in the middle of a transfer"); // `b` never receives the money
+        })
+    };
+
+    assert!(worker.join().is_err());
+
+    println!("lock() failed = {}", m.lock().is_err()); // expect true since the mutex is poisoned now
+
+    {
+        let acc = lock_recover(&m);
+        // expect a = 70, b = 0, consistent = false
+        println!("recovered = {:?}, was poisoned = {}, consistent = {}", *acc, acc.was_poisoned(), acc.is_consistent());
+    }
+
+    {
+        // finish the interrupted transfer so the invariant holds again
+        let acc = lock_recover_with(&m, |acc| acc.b = acc.total - acc.a);
+        println!("repaired = {:?}, consistent = {}", *acc, acc.is_consistent()); // expect a = 70, b = 30
+    }
+
+    println!("lock() failed = {}", m.lock().is_err()); // expect false since the poison was cleared
+}
+
+#[test]
+fn test_lock_recover_keeps_data() {
+    let m = Arc::new(Mutex::new(vec![1, 2, 3]));
+
+    let m1 = Arc::clone(&m);
+    let result = thread::spawn(move || {
+        let mut v = m1.lock().unwrap();
+        v.push(4);
+        panic!("worker died while holding the lock");
+    }).join();
+
+    assert!(result.is_err());
+    assert!(m.is_poisoned());
+
+    {
+        let v = lock_recover(&m);
+        assert!(v.was_poisoned());
+        assert_eq!(*v, vec![1, 2, 3, 4]);
+    }
+
+    // plain recover does not clear the poison flag
+    assert!(m.lock().is_err());
+}
+
+#[test]
+fn test_lock_recover_not_poisoned() {
+    let m = Mutex::new(5);
+
+    let mut num = lock_recover_with(&m, |_| panic!("repair must not run on a healthy mutex"));
+    assert!(!num.was_poisoned());
+    *num = 6;
+    drop(num);
+
+    assert_eq!(*m.lock().unwrap(), 6);
+}
+
+#[test]
+fn test_lock_recover_with_repairs_invariant() {
+    let m = Arc::new(Mutex::new(Accounts { a: 100, b: 0, total: 100 }));
+
+    let m1 = Arc::clone(&m);
+    let result = thread::spawn(move || {
+        let mut acc = m1.lock().unwrap();
+        // money left `a` but the thread dies before it reaches `b`
+        acc.a -= 30;
+        panic!("transfer interrupted");
+    }).join();
+
+    assert!(result.is_err());
+    assert!(!m.lock().unwrap_or_else(|e| e.into_inner()).is_consistent());
+
+    {
+        let acc = lock_recover_with(&m, |acc| acc.b = acc.total - acc.a);
+        assert!(acc.was_poisoned());
+        assert!(acc.is_consistent());
+        assert_eq!((acc.a, acc.b), (70, 30));
+    }
+
+    // the repaired mutex can be used by other threads again
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let m = Arc::clone(&m);
+        handles.push(thread::spawn(move || {
+            let mut acc = m.lock().unwrap();
+            acc.a -= 1;
+            acc.b += 1;
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let acc = m.lock().unwrap();
+    assert!(acc.is_consistent());
+    assert_eq!((acc.a, acc.b), (60, 40));
+}
@@ -0,0 +1,311 @@
+/// Hand-written `Rc<T>` / `Weak<T>`
+///
+/// `MyBox<T>` in `smart_pointer.rs` only shows `Deref`. This is what `Rc` does behind it:
+/// one heap block holds the value and two counters, every `MyRc` is a raw pointer to that block.
+///
+///     MyRc ─┐
+///     MyRc ─┼─► [ strong = 2 | weak = 1 + 1 | value ]
+///     MyWeak┘
+///
+/// - `strong` counts the `MyRc`s; when it reaches 0 the value is dropped
+/// - `weak` counts the `MyWeak`s plus one shared by all the `MyRc`s together; when it reaches 0
+///   the block itself is freed (a `MyWeak` must still be able to read `strong` after the value
+///   is gone, to know that `upgrade` has to fail)
+///
+/// Counters are `Cell`s because they change behind `&self`, and like `std::rc::Rc` nothing is
+/// atomic: the raw pointer makes `MyRc` neither `Send` nor `Sync`, so it can't leave its thread.
+
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+
+struct Inner<T> {
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    // dropped by hand when `strong` reaches 0, the block can outlive it
+    value: ManuallyDrop<T>,
+}
+
+pub struct MyRc<T> {
+    ptr: NonNull<Inner<T>>,
+    // tells the drop checker that a `MyRc<T>` owns (and may drop) a `T`
+    _owns: PhantomData<Inner<T>>,
+}
+
+pub struct MyWeak<T> {
+    // None for `MyWeak::new()`, which never had a value
+    ptr: Option<NonNull<Inner<T>>>,
+}
+
+impl<T> MyRc<T> {
+    pub fn new(value: T) -> MyRc<T> {
+        let inner = Box::new(Inner {
+            strong: Cell::new(1),
+            weak: Cell::new(1),
+            value: ManuallyDrop::new(value),
+        });
+        MyRc::from_inner(NonNull::from(Box::leak(inner)))
+    }
+
+    fn from_inner(ptr: NonNull<Inner<T>>) -> MyRc<T> {
+        MyRc { ptr, _owns: PhantomData }
+    }
+
+    fn inner(&self) -> &Inner<T> {
+        // the block lives as long as there is a MyRc
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn strong_count(this: &MyRc<T>) -> usize {
+        this.inner().strong.get()
+    }
+
+    pub fn weak_count(this: &MyRc<T>) -> usize {
+        this.inner().weak.get() - 1
+    }
+
+    pub fn ptr_eq(this: &MyRc<T>, other: &MyRc<T>) -> bool {
+        this.ptr == other.ptr
+    }
+
+    pub fn downgrade(this: &MyRc<T>) -> MyWeak<T> {
+        let inner = this.inner();
+        inner.weak.set(inner.weak.get() + 1);
+        MyWeak { ptr: Some(this.ptr) }
+    }
+
+    /// `&mut T` only if this is the one pointer to the value, no other `MyRc` and no `MyWeak`.
+    pub fn get_mut(this: &mut MyRc<T>) -> Option<&mut T> {
+        if MyRc::strong_count(this) == 1 && MyRc::weak_count(this) == 0 {
+            // unique: nobody else can read the value while the &mut lives
+            Some(unsafe { &mut this.ptr.as_mut().value })
+        } else {
+            None
+        }
+    }
+
+    /// Clone-on-write: `&mut T`, copying the value first if it is shared with other `MyRc`s.
+    /// If only `MyWeak`s point to it, the value is moved to a new block and they can't upgrade anymore.
+    pub fn make_mut(this: &mut MyRc<T>) -> &mut T
+        where T: Clone {
+        if MyRc::strong_count(this) != 1 {
+            *this = MyRc::new((**this).clone());
+        } else if MyRc::weak_count(this) != 0 {
+            let inner = this.inner();
+            // take the value out without dropping it and leave the block to the weak pointers
+            let value = unsafe { ptr::read(&*inner.value) };
+            inner.strong.set(0);
+            inner.weak.set(inner.weak.get() - 1);
+            let old = std::mem::replace(this, MyRc::new(value));
+            // `old` must not run Drop: it would drop the value we just moved out
+            std::mem::forget(old);
+        }
+        MyRc::get_mut(this).unwrap()
+    }
+}
+
+impl<T> Clone for MyRc<T> {
+    fn clone(&self) -> MyRc<T> {
+        let inner = self.inner();
+        inner.strong.set(inner.strong.get() + 1);
+        MyRc::from_inner(self.ptr)
+    }
+}
+
+impl<T> Deref for MyRc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T> Drop for MyRc<T> {
+    fn drop(&mut self) {
+        let inner = self.inner();
+        inner.strong.set(inner.strong.get() - 1);
+        if inner.strong.get() > 0 {
+            return;
+        }
+        // last MyRc: the value goes now, the block once the last MyWeak is gone
+        unsafe { ManuallyDrop::drop(&mut self.ptr.as_mut().value) };
+        release_weak(self.ptr);
+    }
+}
+
+/// Gives back one weak reference, frees the block on the last one.
+fn release_weak<T>(ptr: NonNull<Inner<T>>) {
+    let inner = unsafe { ptr.as_ref() };
+    inner.weak.set(inner.weak.get() - 1);
+    if inner.weak.get() == 0 {
+        // value is ManuallyDrop and was dropped already, this only frees the memory
+        drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for MyRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T> MyWeak<T> {
+    /// A weak pointer to nothing, `upgrade` always fails.
+    pub fn new() -> MyWeak<T> {
+        MyWeak { ptr: None }
+    }
+
+    fn inner(&self) -> Option<&Inner<T>> {
+        // a MyWeak keeps the block (not the value) alive
+        self.ptr.map(|ptr| unsafe { &*ptr.as_ptr() })
+    }
+
+    pub fn upgrade(&self) -> Option<MyRc<T>> {
+        let inner = self.inner()?;
+        if inner.strong.get() == 0 {
+            return None;
+        }
+        inner.strong.set(inner.strong.get() + 1);
+        self.ptr.map(MyRc::from_inner)
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner().map_or(0, |inner| inner.strong.get())
+    }
+}
+
+impl<T> Default for MyWeak<T> {
+    fn default() -> MyWeak<T> {
+        MyWeak::new()
+    }
+}
+
+impl<T> Clone for MyWeak<T> {
+    fn clone(&self) -> MyWeak<T> {
+        if let Some(inner) = self.inner() {
+            inner.weak.set(inner.weak.get() + 1);
+        }
+        MyWeak { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for MyWeak<T> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            release_weak(ptr);
+        }
+    }
+}
+
+impl<T> fmt::Debug for MyWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(MyWeak)")
+    }
+}
+
+#[cfg(test)]
+struct DropCounter<'a>(&'a Cell<usize>);
+
+#[cfg(test)]
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn test_clone_and_drop_counts() {
+    let drops = Cell::new(0);
+    let a = MyRc::new(DropCounter(&drops));
+    assert_eq!(MyRc::strong_count(&a), 1);
+
+    let b = MyRc::clone(&a);
+    assert!(MyRc::ptr_eq(&a, &b));
+    assert_eq!(MyRc::strong_count(&a), 2);
+    drop(a);
+    assert_eq!(MyRc::strong_count(&b), 1);
+    assert_eq!(drops.get(), 0);
+    drop(b);
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn test_weak_upgrade() {
+    let drops = Cell::new(0);
+    let strong = MyRc::new(DropCounter(&drops));
+    let weak = MyRc::downgrade(&strong);
+    let weak2 = weak.clone();
+    assert_eq!(MyRc::weak_count(&strong), 2);
+    assert_eq!(weak.strong_count(), 1);
+
+    let upgraded = weak.upgrade().unwrap();
+    assert_eq!(MyRc::strong_count(&strong), 2);
+    drop(upgraded);
+    drop(strong);
+
+    // value is gone as soon as the last MyRc is, the weak pointers only see that
+    assert_eq!(drops.get(), 1);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(weak2.strong_count(), 0);
+    assert!(MyWeak::<i32>::new().upgrade().is_none());
+}
+
+#[test]
+fn test_get_mut_requires_unique() {
+    let mut a = MyRc::new(5);
+    *MyRc::get_mut(&mut a).unwrap() += 1;
+    assert_eq!(*a, 6);
+
+    let b = MyRc::clone(&a);
+    assert!(MyRc::get_mut(&mut a).is_none());
+    drop(b);
+
+    let weak = MyRc::downgrade(&a);
+    assert!(MyRc::get_mut(&mut a).is_none());
+    drop(weak);
+    assert!(MyRc::get_mut(&mut a).is_some());
+}
+
+#[test]
+fn test_make_mut_clones_on_write() {
+    let mut a = MyRc::new(String::from("a"));
+    let b = MyRc::clone(&a);
+    MyRc::make_mut(&mut a).push('!');
+    assert_eq!((a.as_str(), b.as_str()), ("a!", "a"));
+    assert!(!MyRc::ptr_eq(&a, &b));
+    assert_eq!(MyRc::strong_count(&b), 1);
+
+    // unique: changes in place
+    let before = &*a as *const String;
+    MyRc::make_mut(&mut a).push('!');
+    assert_eq!(&*a as *const String, before);
+
+    // only weak pointers left: the value moves away from them
+    let weak = MyRc::downgrade(&a);
+    MyRc::make_mut(&mut a).push('?');
+    assert_eq!(*a, "a!!?");
+    assert!(weak.upgrade().is_none());
+    assert_eq!(MyRc::weak_count(&a), 0);
+}
+
+#[test]
+fn test_make_mut_moves_value_exactly_once() {
+    let drops = Cell::new(0);
+    // std Rc is Clone without cloning the counter inside
+    let mut a = MyRc::new(std::rc::Rc::new(DropCounter(&drops)));
+    let weak = MyRc::downgrade(&a);
+    MyRc::make_mut(&mut a);
+    drop(weak);
+    assert_eq!(drops.get(), 0);
+    drop(a);
+    assert_eq!(drops.get(), 1);
+}
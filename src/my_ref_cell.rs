@@ -0,0 +1,180 @@
+/// Hand-written `RefCell<T>`
+///
+/// `RefCell` moves the borrow rules from compile time to run time. It only needs one number:
+///
+/// - `0`  nobody borrows the value
+/// - `n`  n shared borrows (`Ref`) are alive
+/// - `-1` one mutable borrow (`RefMut`) is alive
+///
+/// `borrow` / `borrow_mut` check and update the flag, the guards put it back in their `Drop`.
+/// As long as the flag is right, handing out `&T` / `&mut T` from `&self` through the
+/// `UnsafeCell` can never create a `&mut` aliasing anything. Like `RefCell`, the `Cell` flag makes
+/// `MyRefCell` not `Sync`, so this bookkeeping never races.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+const WRITING: isize = -1;
+
+pub struct MyRefCell<T> {
+    borrow: Cell<isize>,
+    value: UnsafeCell<T>,
+}
+
+pub struct Ref<'b, T> {
+    cell: &'b MyRefCell<T>,
+}
+
+pub struct RefMut<'b, T> {
+    cell: &'b MyRefCell<T>,
+}
+
+impl<T> MyRefCell<T> {
+    pub fn new(value: T) -> MyRefCell<T> {
+        MyRefCell { borrow: Cell::new(0), value: UnsafeCell::new(value) }
+    }
+
+    /// Shared borrow, `None` while the value is mutably borrowed.
+    pub fn try_borrow(&self) -> Option<Ref<'_, T>> {
+        let flag = self.borrow.get();
+        if flag == WRITING {
+            return None;
+        }
+        self.borrow.set(flag + 1);
+        Some(Ref { cell: self })
+    }
+
+    /// Mutable borrow, `None` while any other borrow is alive.
+    pub fn try_borrow_mut(&self) -> Option<RefMut<'_, T>> {
+        if self.borrow.get() != 0 {
+            return None;
+        }
+        self.borrow.set(WRITING);
+        Some(RefMut { cell: self })
+    }
+
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut().expect("already borrowed")
+    }
+
+    /// `&mut self` proves there is no guard alive, no flag check needed.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // flag > 0: only shared references exist
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for Ref<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(self.cell.borrow.get() - 1);
+    }
+}
+
+impl<T> Deref for RefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // flag == WRITING: this guard is the only way to the value
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for RefMut<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(0);
+    }
+}
+
+/// Panics like `borrow` if the value is currently mutably borrowed.
+impl<T: Clone> Clone for MyRefCell<T> {
+    fn clone(&self) -> MyRefCell<T> {
+        MyRefCell::new(self.borrow().clone())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_borrow() {
+            Some(value) => f.debug_struct("MyRefCell").field("value", &*value).finish(),
+            None => f.debug_struct("MyRefCell").field("value", &"<borrowed>").finish(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_shared_borrows() {
+    let cell = MyRefCell::new(vec![1, 2]);
+    let a = cell.borrow();
+    let b = cell.borrow();
+    assert_eq!(a.len() + b.len(), 4);
+    assert!(cell.try_borrow_mut().is_none());
+    drop(a);
+    assert!(cell.try_borrow_mut().is_none());
+    drop(b);
+    assert!(cell.try_borrow_mut().is_some());
+}
+
+#[test]
+fn test_mutable_borrow_is_exclusive() {
+    let cell = MyRefCell::new(5);
+    {
+        let mut value = cell.borrow_mut();
+        *value += 1;
+        assert!(cell.try_borrow().is_none());
+        assert!(cell.try_borrow_mut().is_none());
+        assert_eq!(format!("{:?}", cell), r#"MyRefCell { value: "<borrowed>" }"#);
+    }
+    assert_eq!(*cell.borrow(), 6);
+    assert_eq!(format!("{:?}", cell), "MyRefCell { value: 6 }");
+
+    let mut cell = cell;
+    *cell.get_mut() += 1;
+    assert_eq!(cell.into_inner(), 7);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn test_borrow_mut_while_borrowed_panics() {
+    let cell = MyRefCell::new(0);
+    let _reader = cell.borrow();
+    let _writer = cell.borrow_mut();
+}
+
+#[test]
+#[should_panic(expected = "already mutably borrowed")]
+fn test_borrow_while_mutably_borrowed_panics() {
+    let cell = MyRefCell::new(0);
+    let _writer = cell.borrow_mut();
+    let _reader = cell.borrow();
+}
@@ -0,0 +1,318 @@
+/// Parallel iterator
+///
+/// `par_slice` works on `&mut [T]` in place. This is the read-only, `par_iter` flavoured side:
+/// a source (slice, `Vec` or integer `Range`) is cut into chunks, at most `threads()` scoped
+/// threads take a run of neighbour chunks each, and the per-chunk results are put back
+/// together in chunk order. So
+/// `par_map` / `par_filter` give exactly what `map` / `filter` give, and `par_reduce` only
+/// needs an associative `op`, not a commutative one.
+///
+/// `par_map` and `par_filter` are lazy: they are fused into one `filter_map` which runs when
+/// `collect_vec`, `par_reduce` or `par_sum` is called.
+
+use std::iter::Sum;
+use std::ops::Range;
+use std::panic;
+use std::thread;
+use std::time::Instant;
+
+use crate::par_slice::threads;
+
+/// A `ParIter` straight from its producer, nothing mapped or filtered yet.
+pub type Source<P> = ParIter<P, fn(<P as Producer>::Item) -> Option<<P as Producer>::Item>>;
+
+/// Something that can be cut in two at an index and then walked sequentially.
+pub trait Producer: Send + Sized {
+    type Item;
+    type IntoIter: Iterator<Item = Self::Item>;
+
+    fn len(&self) -> usize;
+    /// `[0, index)` and `[index, len)`.
+    fn split_at(self, index: usize) -> (Self, Self);
+    fn into_seq_iter(self) -> Self::IntoIter;
+
+    fn into_par_iter(self) -> Source<Self> {
+        ParIter { producer: self, op: Some, chunk_size: None }
+    }
+}
+
+impl<'a, T: Sync> Producer for &'a [T] {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        <[T]>::split_at(self, index)
+    }
+
+    fn into_seq_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Send> Producer for Vec<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn split_at(mut self, index: usize) -> (Self, Self) {
+        let right = self.split_off(index);
+        (self, right)
+    }
+
+    fn into_seq_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self)
+    }
+}
+
+macro_rules! range_producer {
+    ($($t:ty => $unsigned:ty),*) => {
+        $(
+            impl Producer for Range<$t> {
+                type Item = $t;
+                type IntoIter = Range<$t>;
+
+                fn len(&self) -> usize {
+                    if self.start >= self.end {
+                        return 0;
+                    }
+                    // same trick as `iterator::Stepping`: the distance fits the unsigned type
+                    self.end.wrapping_sub(self.start) as $unsigned as usize
+                }
+
+                fn split_at(self, index: usize) -> (Self, Self) {
+                    let mid = self.start.wrapping_add(index as $t);
+                    (self.start..mid, mid..self.end)
+                }
+
+                fn into_seq_iter(self) -> Self::IntoIter {
+                    self
+                }
+            }
+        )*
+    };
+}
+
+range_producer!(u32 => u32, u64 => u64, usize => usize, i32 => u32, i64 => u64);
+
+/// `slice.par_iter()`, the borrowing shorthand for `(&slice[..]).into_par_iter()`.
+pub trait ParSlice<T: Sync> {
+    fn par_iter(&self) -> Source<&[T]>;
+}
+
+impl<T: Sync> ParSlice<T> for [T] {
+    fn par_iter(&self) -> Source<&[T]> {
+        self.into_par_iter()
+    }
+}
+
+pub struct ParIter<P, F> {
+    producer: P,
+    /// every `par_map` / `par_filter` so far, fused
+    op: F,
+    chunk_size: Option<usize>,
+}
+
+impl<P, F, T> ParIter<P, F>
+    where P: Producer, F: Fn(P::Item) -> Option<T> + Sync, T: Send {
+    /// Fixed chunk size instead of one chunk per thread. There are still at most `threads()`
+    /// threads, each one takes several chunks in a row.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than 0");
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    pub fn par_map<U, G>(self, g: G) -> ParIter<P, impl Fn(P::Item) -> Option<U> + Sync>
+        where G: Fn(T) -> U + Sync, U: Send {
+        let op = self.op;
+        ParIter { producer: self.producer, op: move |item| op(item).map(&g), chunk_size: self.chunk_size }
+    }
+
+    pub fn par_filter<G>(self, predicate: G) -> ParIter<P, impl Fn(P::Item) -> Option<T> + Sync>
+        where G: Fn(&T) -> bool + Sync {
+        let op = self.op;
+        ParIter { producer: self.producer, op: move |item| op(item).filter(&predicate), chunk_size: self.chunk_size }
+    }
+
+    /// Same elements, same order as the sequential `filter_map(..).collect()`.
+    pub fn collect_vec(self) -> Vec<T> {
+        let op = &self.op;
+        let pieces = run_chunks(self.producer, self.chunk_size, |chunk| chunk.filter_map(op).collect::<Vec<_>>());
+        let mut all = Vec::with_capacity(pieces.iter().map(Vec::len).sum());
+        pieces.into_iter().for_each(|piece| all.extend(piece));
+        all
+    }
+
+    /// Every chunk folds from `identity()`, then the chunk results are folded left to right.
+    /// `op` has to be associative, `identity()` has to be neutral for it.
+    pub fn par_reduce<I, R>(self, identity: I, op: R) -> T
+        where I: Fn() -> T + Sync, R: Fn(T, T) -> T + Sync {
+        let (map, reduce) = (&self.op, &op);
+        run_chunks(self.producer, self.chunk_size, |chunk| chunk.filter_map(map).fold(identity(), reduce))
+            .into_iter()
+            .fold(identity(), reduce)
+    }
+
+    pub fn par_sum<S>(self) -> S
+        where S: Sum<T> + Sum<S> + Send {
+        let op = &self.op;
+        run_chunks(self.producer, self.chunk_size, |chunk| chunk.filter_map(op).sum::<S>())
+            .into_iter()
+            .sum()
+    }
+}
+
+/// Cut `producer` into chunks (one per thread unless `chunk_size` says otherwise), run `work` on
+/// each of them, return the results in chunk order.
+fn run_chunks<P, R, W>(producer: P, chunk_size: Option<usize>, work: W) -> Vec<R>
+    where P: Producer, R: Send, W: Fn(P::IntoIter) -> R + Sync {
+    run_chunks_on(producer, chunk_size, threads(), work)
+}
+
+/// At most `workers` scoped threads, each one goes through a run of neighbour chunks.
+fn run_chunks_on<P, R, W>(producer: P, chunk_size: Option<usize>, workers: usize, work: W) -> Vec<R>
+    where P: Producer, R: Send, W: Fn(P::IntoIter) -> R + Sync {
+    let len = producer.len();
+    let chunk_size = chunk_size.unwrap_or_else(|| len.div_ceil(workers.max(1)).max(1));
+
+    // cut from the back, so a `Vec` only ever moves the one chunk it splits off
+    let mut chunks = Vec::with_capacity(len.div_ceil(chunk_size).max(1));
+    let mut rest = producer;
+    while rest.len() > chunk_size {
+        let last = (rest.len() - 1) / chunk_size * chunk_size;
+        let (left, right) = rest.split_at(last);
+        chunks.push(right);
+        rest = left;
+    }
+    chunks.push(rest);
+
+    // `chunks` is last to first: hand out runs from the back, so the runs come out in order
+    let workers = workers.clamp(1, chunks.len());
+    let mut runs: Vec<Vec<P>> = Vec::with_capacity(workers);
+    for worker in 0..workers {
+        let take = chunks.len() / (workers - worker);
+        runs.push(chunks.split_off(chunks.len() - take).into_iter().rev().collect());
+    }
+
+    let work = &work;
+    let run = move |run: Vec<P>| run.into_iter().map(|chunk| work(chunk.into_seq_iter())).collect::<Vec<_>>();
+    let first = runs.remove(0);
+    thread::scope(|s| {
+        let handles: Vec<_> = runs.into_iter().map(|chunks| s.spawn(move || run(chunks))).collect();
+        // the current thread takes the first run instead of just waiting
+        let mut results = run(first);
+        for handle in handles {
+            results.extend(handle.join().unwrap_or_else(|e| panic::resume_unwind(e)));
+        }
+        results
+    })
+}
+
+pub fn run() {
+    let n = 20_000_000u64;
+    let collatz_len = |mut x: u64| {
+        let mut steps = 0u32;
+        while x > 1 {
+            x = if x.is_multiple_of(2) { x / 2 } else { 3 * x + 1 };
+            steps += 1;
+        }
+        steps
+    };
+
+    let now = Instant::now();
+    let seq: u64 = (1..n / 20).map(collatz_len).filter(|s| s % 2 == 0).map(u64::from).sum();
+    println!("sequential map/filter/sum : {} in {:?}", seq, now.elapsed());
+
+    let now = Instant::now();
+    let par: u64 = (1..n / 20).into_par_iter().par_map(collatz_len).par_filter(|s| s % 2 == 0).par_map(u64::from).par_sum();
+    println!("parallel map/filter/sum   : {} in {:?} ({} threads)", par, now.elapsed(), threads());
+    assert_eq!(seq, par);
+
+    let words: Vec<String> = (0..100_000).map(|i| format!("w{}", i % 997)).collect();
+    let now = Instant::now();
+    let longest = words.par_iter()
+        .par_filter(|w| w.ends_with('7'))
+        .par_reduce(|| &words[0], |a, b| if b.len() > a.len() { b } else { a });
+    println!("parallel reduce           : first longest word ending in 7 is {} in {:?}", longest, now.elapsed());
+
+    let squares = (0..n as i64).into_par_iter().with_chunk_size(n as usize / 8).par_map(|x| x * x).collect_vec();
+    println!("parallel collect          : {} squares, last {:?}", squares.len(), squares.last());
+}
+
+#[test]
+fn test_map_filter_keep_order() {
+    let data: Vec<i64> = (0..10_007).map(|i| (i * 7919 + 13) % 1009 - 500).collect();
+    let expected: Vec<i64> = data.iter().map(|x| x * 3).filter(|x| x % 2 == 0).collect();
+
+    assert_eq!(data.par_iter().par_map(|x| x * 3).par_filter(|x| x % 2 == 0).collect_vec(), expected);
+    // many more chunks than threads, and a last chunk shorter than the others
+    for chunk_size in [1000, 333, 10_007, 20_000].iter() {
+        let par = data.par_iter().with_chunk_size(*chunk_size).par_map(|x| x * 3).par_filter(|x| x % 2 == 0).collect_vec();
+        assert_eq!(par, expected);
+    }
+
+    let owned = data.clone().into_par_iter().with_chunk_size(100).par_filter(|x| *x > 0).collect_vec();
+    assert_eq!(owned, data.iter().copied().filter(|x| *x > 0).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_threads_are_bounded() {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    for workers in [1, 3, 4, 64].iter() {
+        let ids = Mutex::new(HashSet::new());
+        let indexes = run_chunks_on(0usize..1000, Some(1), *workers, |chunk| {
+            ids.lock().unwrap().insert(thread::current().id());
+            chunk.collect::<Vec<_>>()
+        });
+        assert_eq!(indexes.concat(), (0..1000).collect::<Vec<_>>());
+        assert!(ids.into_inner().unwrap().len() <= *workers);
+    }
+
+    // uneven: 10 chunks on 4 workers, the last chunk shorter
+    let lens = run_chunks_on((0..29).collect::<Vec<u8>>(), Some(3), 4, |chunk| chunk.len());
+    assert_eq!(lens, vec![3, 3, 3, 3, 3, 3, 3, 3, 3, 2]);
+    assert_eq!(run_chunks_on(0u32..0, None, 4, |chunk| chunk.count()), vec![0]);
+}
+
+#[test]
+fn test_ranges() {
+    assert_eq!((0u32..10).into_par_iter().with_chunk_size(3).collect_vec(), (0..10).collect::<Vec<_>>());
+    assert_eq!((-5i64..5).into_par_iter().with_chunk_size(4).par_map(|x| x * x).par_sum::<i64>(), 85);
+    assert_eq!((i32::MIN..i32::MIN + 3).into_par_iter().collect_vec(), vec![i32::MIN, i32::MIN + 1, i32::MIN + 2]);
+    assert!((7usize..7).into_par_iter().collect_vec().is_empty());
+    let (start, end) = (9u64, 2u64);
+    assert!((start..end).into_par_iter().collect_vec().is_empty());
+    assert_eq!((1u64..1_000_001).into_par_iter().with_chunk_size(65_536).par_sum::<u64>(), 500_000_500_000);
+}
+
+#[test]
+fn test_reduce_only_needs_associativity() {
+    let words: Vec<String> = (0..500).map(|i| i.to_string()).collect();
+    let expected: String = words.concat();
+
+    // string concatenation is not commutative: chunk results must be combined in order
+    for chunk_size in [1, 7, 64, 500].iter() {
+        let joined = words.par_iter()
+            .with_chunk_size(*chunk_size)
+            .par_map(|w| w.clone())
+            .par_reduce(String::new, |a, b| a + &b);
+        assert_eq!(joined, expected);
+    }
+    let empty: &[u8] = &[];
+    assert_eq!(empty.par_iter().par_map(|x| *x as u32).par_reduce(|| 0, |a, b| a + b), 0);
+}
+
+#[test]
+#[should_panic(expected = "bad element")]
+fn test_panic_reaches_caller() {
+    (0u32..100).into_par_iter().with_chunk_size(10).par_map(|x| assert!(x != 95, "bad element")).collect_vec();
+}
@@ -0,0 +1,343 @@
+/// Parallel slice processing
+///
+/// `advance_unsafe::split_at_mut` gives us two non-overlapping `&mut` halves of one slice.
+/// Since the halves never alias, each of them can be sent to a different thread. Splitting
+/// again and again gives one piece per thread, and `thread::scope` lets those threads borrow
+/// the pieces because every scoped thread is joined before the scope (and the borrow) ends.
+
+use std::ops::Add;
+use std::thread::{self, Scope};
+use std::time::Instant;
+
+use crate::advance_unsafe::split_at_mut;
+
+pub fn run() {
+    let len = 2_000_000;
+    let data: Vec<u64> = (0..len as u64).map(|i| (i * 7919 + 13) % 1_000_003).collect();
+
+    // a few rounds of a LCG per element so there is some work to share
+    let scramble = |x: &mut u64| {
+        *x = (0..50).fold(*x, |acc, _| acc.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407));
+    };
+
+    let mut seq = data.clone();
+    let now = Instant::now();
+    seq.iter_mut().for_each(scramble);
+    println!("sequential for_each   : {:?}", now.elapsed());
+
+    let mut par = data.clone();
+    let now = Instant::now();
+    par_for_each_mut(&mut par, scramble);
+    println!("parallel for_each     : {:?}", now.elapsed());
+    assert_eq!(seq, par);
+
+    let mut seq = data.clone();
+    let now = Instant::now();
+    seq.sort();
+    println!("sequential sort       : {:?}", now.elapsed());
+
+    let mut par = data.clone();
+    let now = Instant::now();
+    par_merge_sort(&mut par);
+    println!("parallel merge sort   : {:?}", now.elapsed());
+    assert_eq!(seq, par);
+
+    let mut seq = data.clone();
+    let now = Instant::now();
+    prefix_sum(&mut seq);
+    println!("sequential prefix sum : {:?}", now.elapsed());
+
+    let mut par = data;
+    let now = Instant::now();
+    par_prefix_sum(&mut par);
+    println!("parallel prefix sum   : {:?}", now.elapsed());
+    assert_eq!(seq, par);
+}
+
+/// Number of threads we aim to keep busy.
+pub fn threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+/// Chunk size which gives roughly one chunk per thread.
+fn chunk_size_for(len: usize) -> usize {
+    len.div_ceil(threads()).max(1)
+}
+
+/// Run `f` on every `chunk_size` long piece of `slice` (the last one may be shorter) in parallel.
+///
+/// The pieces are the same as the ones of `slice.chunks_mut(chunk_size)` and `f` also gets the
+/// index of the piece, so work done on one piece can be matched with work done on another.
+/// At most `threads()` threads run, each one takes a run of neighbour pieces.
+pub fn par_chunks_mut<T, F>(slice: &mut [T], chunk_size: usize, f: F)
+    where T: Send, F: Fn(usize, &mut [T]) + Sync {
+    par_chunks_mut_on(slice, chunk_size, threads(), f);
+}
+
+fn par_chunks_mut_on<T, F>(slice: &mut [T], chunk_size: usize, workers: usize, f: F)
+    where T: Send, F: Fn(usize, &mut [T]) + Sync {
+    assert!(chunk_size > 0, "chunk size must be greater than 0");
+
+    if slice.is_empty() {
+        return;
+    }
+
+    let workers = workers.clamp(1, slice.len().div_ceil(chunk_size));
+    thread::scope(|s| split_and_spawn(s, slice, chunk_size, 0, workers, &f));
+}
+
+/// Split `slice` in two at a chunk boundary, hand the left half and its share of the workers to
+/// a new scoped thread and keep going with the right half on the current one until a single
+/// worker is left, which then goes through its chunks one by one.
+fn split_and_spawn<'scope, T, F>(
+    s: &'scope Scope<'scope, '_>,
+    slice: &'scope mut [T],
+    chunk_size: usize,
+    index: usize,
+    workers: usize,
+    f: &'scope F,
+) where T: Send, F: Fn(usize, &mut [T]) + Sync {
+    if workers <= 1 {
+        for (i, chunk) in slice.chunks_mut(chunk_size).enumerate() {
+            f(index + i, chunk);
+        }
+        return;
+    }
+
+    // every worker gets at least one chunk since there are never more workers than chunks
+    let chunks = slice.len().div_ceil(chunk_size);
+    let left_workers = workers / 2;
+    let left_chunks = chunks * left_workers / workers;
+    let (left, right) = split_at_mut(slice, left_chunks * chunk_size);
+
+    s.spawn(move || split_and_spawn(s, left, chunk_size, index, left_workers, f));
+    split_and_spawn(s, right, chunk_size, index + left_chunks, workers - left_workers, f);
+}
+
+/// Run `f` on every element of `slice` in parallel.
+pub fn par_for_each_mut<T, F>(slice: &mut [T], f: F)
+    where T: Send, F: Fn(&mut T) + Sync {
+    let chunk_size = chunk_size_for(slice.len());
+
+    par_chunks_mut(slice, chunk_size, |_, chunk| chunk.iter_mut().for_each(&f));
+}
+
+/// Merge the two sorted runs `chunk[..mid]` and `chunk[mid..]` into one sorted run.
+fn merge<T: Ord + Clone>(chunk: &mut [T], mid: usize) {
+    if mid >= chunk.len() || chunk[mid - 1] <= chunk[mid] {
+        return; // only one run, or the runs are already in order
+    }
+
+    let left = chunk[..mid].to_vec();
+    let (mut i, mut j, mut k) = (0, mid, 0);
+
+    // `k` never passes `j`, so writing to `chunk[k]` never overwrites an unread right element
+    while i < left.len() && j < chunk.len() {
+        if left[i] <= chunk[j] {
+            chunk[k] = left[i].clone();
+            i += 1;
+        } else {
+            chunk[k] = chunk[j].clone();
+            j += 1;
+        }
+        k += 1;
+    }
+
+    while i < left.len() {
+        chunk[k] = left[i].clone();
+        i += 1;
+        k += 1;
+    }
+}
+
+/// Sort every chunk in parallel, then merge neighbour runs pairwise (also in parallel) until
+/// one run covers the whole slice.
+pub fn par_merge_sort<T>(slice: &mut [T])
+    where T: Ord + Clone + Send {
+    let chunk_size = chunk_size_for(slice.len());
+    par_merge_sort_chunked(slice, chunk_size);
+}
+
+fn par_merge_sort_chunked<T>(slice: &mut [T], chunk_size: usize)
+    where T: Ord + Clone + Send {
+    let mut width = chunk_size;
+
+    par_chunks_mut(slice, width, |_, chunk| chunk.sort());
+
+    while width < slice.len() {
+        par_chunks_mut(slice, width * 2, |_, chunk| merge(chunk, width));
+        width *= 2;
+    }
+}
+
+/// Sequential inclusive prefix sum: `[1, 2, 3]` becomes `[1, 3, 6]`.
+pub fn prefix_sum<T>(slice: &mut [T])
+    where T: Copy + Add<Output = T> {
+    for i in 1..slice.len() {
+        slice[i] = slice[i - 1] + slice[i];
+    }
+}
+
+/// Parallel inclusive prefix sum in two passes:
+/// 1. every chunk computes its own prefix sum in parallel
+/// 2. the total of all previous chunks is added to every element of a chunk in parallel
+pub fn par_prefix_sum<T>(slice: &mut [T])
+    where T: Copy + Add<Output = T> + Send + Sync {
+    let chunk_size = chunk_size_for(slice.len());
+    par_prefix_sum_chunked(slice, chunk_size);
+}
+
+fn par_prefix_sum_chunked<T>(slice: &mut [T], chunk_size: usize)
+    where T: Copy + Add<Output = T> + Send + Sync {
+    par_chunks_mut(slice, chunk_size, |_, chunk| prefix_sum(chunk));
+
+    // the last element of each chunk is the chunk total, `offsets[i]` sums chunks before `i`
+    let mut offsets: Vec<Option<T>> = vec![None];
+    for chunk in slice.chunks(chunk_size) {
+        let last = chunk[chunk.len() - 1];
+        let prev = offsets[offsets.len() - 1];
+        offsets.push(Some(prev.map_or(last, |p| p + last)));
+    }
+
+    par_chunks_mut(slice, chunk_size, |index, chunk| {
+        if let Some(offset) = offsets[index] {
+            chunk.iter_mut().for_each(|x| *x = offset + *x);
+        }
+    });
+}
+
+#[cfg(test)]
+fn chunk_sizes_for_test(len: usize) -> Vec<usize> {
+    vec![1, 3, 64, len / 8 + 1]
+}
+
+#[test]
+fn test_par_chunks_mut_matches_chunks_mut() {
+    let mut v: Vec<usize> = vec![0; 103];
+
+    par_chunks_mut(&mut v, 10, |index, chunk| chunk.iter_mut().for_each(|x| *x = index));
+
+    let expected: Vec<usize> = (0..103).map(|i| i / 10).collect();
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn test_par_chunks_mut_bounds_threads() {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    // a chunk per element, but only a few workers, each with its run of chunks
+    for workers in [1, 3, 4, 16].iter() {
+        let mut v: Vec<usize> = vec![0; 100_003];
+        let ids = Mutex::new(HashSet::new());
+
+        par_chunks_mut_on(&mut v, 1, *workers, |index, chunk| {
+            ids.lock().unwrap().insert(thread::current().id());
+            chunk[0] = index;
+        });
+
+        assert_eq!(v, (0..100_003).collect::<Vec<_>>());
+        assert!(ids.into_inner().unwrap().len() <= *workers);
+    }
+
+    // never more workers than chunks
+    let mut small = vec![0; 3];
+    par_chunks_mut_on(&mut small, 2, 8, |index, chunk| chunk.iter_mut().for_each(|x| *x = index + 1));
+    assert_eq!(small, vec![1, 1, 2]);
+}
+
+#[test]
+fn test_par_chunks_mut_empty_and_small() {
+    let mut empty: Vec<i32> = vec![];
+    par_chunks_mut(&mut empty, 4, |_, _| panic!("no chunk in an empty slice"));
+
+    let mut small = vec![1, 2, 3];
+    par_chunks_mut(&mut small, 100, |index, chunk| {
+        assert_eq!(index, 0);
+        chunk.reverse();
+    });
+    assert_eq!(small, vec![3, 2, 1]);
+}
+
+#[test]
+fn test_par_for_each_mut() {
+    let mut v: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+
+    par_for_each_mut(&mut v, |s| s.push('!'));
+
+    let expected: Vec<String> = (0..1000).map(|i| format!("{}!", i)).collect();
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn test_par_merge_sort_matches_sort() {
+    for len in [0, 1, 2, 7, 100, 1001, 100_000].iter() {
+        let data: Vec<i64> = (0..*len as i64).map(|i| (i * 7919 + 13) % 1009 - 500).collect();
+
+        let mut seq = data.clone();
+        seq.sort();
+
+        let mut par = data.clone();
+        par_merge_sort(&mut par);
+        assert_eq!(seq, par);
+
+        // force several chunks whatever the number of cpus is
+        for chunk_size in chunk_sizes_for_test(*len).iter() {
+            let mut par = data.clone();
+            par_merge_sort_chunked(&mut par, *chunk_size);
+            assert_eq!(seq, par);
+        }
+    }
+}
+
+#[test]
+fn test_par_merge_sort_is_stable() {
+    let data: Vec<(u8, usize)> = (0..10_000).map(|i| ((i % 7) as u8, i)).collect();
+
+    let mut seq = data.clone();
+    seq.sort_by_key(|p| p.0);
+
+    // order pairs by key only so equal keys keep their original order
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct ByKey((u8, usize));
+    impl PartialOrd for ByKey {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for ByKey {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            (self.0).0.cmp(&(other.0).0)
+        }
+    }
+
+    let mut par: Vec<ByKey> = data.into_iter().map(ByKey).collect();
+    par_merge_sort_chunked(&mut par, 100);
+
+    let par: Vec<(u8, usize)> = par.into_iter().map(|p| p.0).collect();
+    assert_eq!(seq, par);
+}
+
+#[test]
+fn test_par_prefix_sum_matches_prefix_sum() {
+    for len in [0, 1, 2, 7, 100, 1001, 100_000].iter() {
+        let data: Vec<u64> = (0..*len as u64).map(|i| i % 13).collect();
+
+        let mut seq = data.clone();
+        prefix_sum(&mut seq);
+
+        let mut par = data.clone();
+        par_prefix_sum(&mut par);
+        assert_eq!(seq, par);
+
+        for chunk_size in chunk_sizes_for_test(*len).iter() {
+            let mut par = data.clone();
+            par_prefix_sum_chunked(&mut par, *chunk_size);
+            assert_eq!(seq, par);
+        }
+    }
+
+    let mut v = vec![1, 2, 3, 4];
+    par_prefix_sum_chunked(&mut v, 3);
+    assert_eq!(v, vec![1, 3, 6, 10]);
+}
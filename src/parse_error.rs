@@ -0,0 +1,19 @@
+/// Errors of the line based text formats
+///
+/// `quota`, `workout` and `shoes` each read a small text file one line at a time. They all
+/// report a bad line the same way, so they share this one error type.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1 based, 0 when the error is about the file as a whole
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
@@ -0,0 +1,511 @@
+/// Quota service
+///
+/// `smart_pointer::ref_cell::LimitTracker` is the book version: one user, fixed 75/90/100%
+/// thresholds, one `Messenger`, and it sends again on every `set_value`. This grows it into
+/// something a service could run:
+///
+/// - `Rule`s are configurable: "at <percent>% send <message> with <level>"
+/// - every user has a `Quota` (limit per window of seconds), with a default for everyone else
+/// - usage is counted per window and starts from 0 when the next window begins
+/// - each rule fires at most once per window; a jump over several thresholds only sends the
+///   highest one
+/// - alerts go to every registered `Messenger`: stdout, a log file, or memory (tests)
+///
+/// The messengers still take `&self`, so the ones keeping state use `RefCell` inside, same as
+/// the mock in the lesson.
+///
+/// `cargo run -- quota [<file>] [--log <path>]` loads rules, quotas and usage events from a
+/// file (`fixtures/quota.txt` when none is given) and prints the alerts, see `parse` for the
+/// format.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::parse_error::ParseError;
+
+pub trait Messenger {
+    fn send(&self, msg: &str);
+}
+
+/// Prints every message.
+pub struct Stdout;
+
+impl Messenger for Stdout {
+    fn send(&self, msg: &str) {
+        println!("{}", msg);
+    }
+}
+
+/// Appends every message as one line to a file.
+pub struct LogFile {
+    file: RefCell<BufWriter<File>>,
+}
+
+impl LogFile {
+    pub fn create(path: &str) -> io::Result<LogFile> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(LogFile { file: RefCell::new(BufWriter::new(file)) })
+    }
+}
+
+impl Messenger for LogFile {
+    fn send(&self, msg: &str) {
+        let mut file = self.file.borrow_mut();
+        // an alert must not take the service down, losing a log line is the lesser evil
+        if let Err(e) = writeln!(file, "{}", msg).and_then(|_| file.flush()) {
+            eprintln!("quota: can not write the log: {}", e);
+        }
+    }
+}
+
+/// Keeps the messages, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Memory {
+    sent: RefCell<Vec<String>>,
+}
+
+#[cfg(test)]
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        self.sent.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Messenger for Memory {
+    fn send(&self, msg: &str) {
+        self.sent.borrow_mut().push(msg.to_string());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub percent: u64,
+    pub level: String,
+    pub message: String,
+}
+
+impl Rule {
+    pub fn new(percent: u64, level: &str, message: &str) -> Rule {
+        Rule { percent, level: level.to_string(), message: message.to_string() }
+    }
+
+    /// The thresholds of the original `LimitTracker`.
+    pub fn defaults() -> Vec<Rule> {
+        vec![
+            Rule::new(75, "warning", "Warning: You've used up over 75% of your quota!"),
+            Rule::new(90, "urgent", "Urgent warning: You've used up over 90% of your quota!"),
+            Rule::new(100, "error", "Error: You are over your quota!"),
+        ]
+    }
+
+    fn crossed(&self, used: u64, limit: u64) -> bool {
+        // numbers from the quota file can be anything up to u64::MAX, the products fit a u128
+        used as u128 * 100 >= self.percent as u128 * limit as u128
+    }
+}
+
+/// The rule with the highest threshold `used` has reached, if any.
+pub fn highest(rules: &[Rule], used: u64, limit: u64) -> Option<&Rule> {
+    rules.iter()
+        .filter(|rule| rule.crossed(used, limit))
+        .max_by_key(|rule| rule.percent)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u64,
+    /// Length of a window in seconds, 0 never resets.
+    pub window: u64,
+}
+
+impl Quota {
+    fn window_of(&self, time: u64) -> u64 {
+        time.checked_div(self.window).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub time: u64,
+    pub user: String,
+    pub amount: u64,
+}
+
+impl Usage {
+    pub fn new(time: u64, user: &str, amount: u64) -> Usage {
+        Usage { time, user: user.to_string(), amount }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub time: u64,
+    pub user: String,
+    pub level: String,
+    pub message: String,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] t={} {} ({}/{}): {}",
+               self.level, self.time, self.user, self.used, self.limit, self.message)
+    }
+}
+
+/// Usage of one user in the current window, and which rules already fired in it.
+struct Window {
+    index: u64,
+    used: u64,
+    fired: Vec<bool>,
+}
+
+pub struct QuotaService<'a> {
+    rules: Vec<Rule>,
+    default: Option<Quota>,
+    quotas: HashMap<String, Quota>,
+    windows: HashMap<String, Window>,
+    messengers: Vec<&'a dyn Messenger>,
+}
+
+impl<'a> QuotaService<'a> {
+    pub fn new(mut rules: Vec<Rule>) -> QuotaService<'a> {
+        rules.sort_by_key(|rule| rule.percent);
+        QuotaService {
+            rules,
+            default: None,
+            quotas: HashMap::new(),
+            windows: HashMap::new(),
+            messengers: vec![],
+        }
+    }
+
+    /// Quota of every user without an own one. Without it they are not limited.
+    pub fn set_default_quota(&mut self, quota: Quota) {
+        self.default = Some(quota);
+    }
+
+    pub fn set_quota(&mut self, user: &str, quota: Quota) {
+        self.quotas.insert(user.to_string(), quota);
+    }
+
+    pub fn add_messenger(&mut self, messenger: &'a dyn Messenger) {
+        self.messengers.push(messenger);
+    }
+
+    pub fn quota(&self, user: &str) -> Option<Quota> {
+        self.quotas.get(user).copied().or(self.default)
+    }
+
+    /// What `user` used in the window of `time`.
+    pub fn used(&self, user: &str, time: u64) -> u64 {
+        match (self.quota(user), self.windows.get(user)) {
+            (Some(quota), Some(window)) if window.index == quota.window_of(time) => window.used,
+            _ => 0,
+        }
+    }
+
+    /// Counts the usage and sends an alert if it reached a threshold which did not fire in
+    /// this window yet. Events are expected in time order.
+    pub fn record(&mut self, usage: &Usage) -> Option<Alert> {
+        let quota = self.quota(&usage.user)?;
+        let index = quota.window_of(usage.time);
+        let rules = self.rules.len();
+        let window = self.windows.entry(usage.user.clone())
+            .or_insert_with(|| Window { index, used: 0, fired: vec![false; rules] });
+        if window.index != index {
+            *window = Window { index, used: 0, fired: vec![false; rules] };
+        }
+        window.used = window.used.saturating_add(usage.amount);
+
+        // every crossed rule is marked, but only the highest new one is sent: after a jump
+        // straight to 100% a "75%" warning would only be noise
+        let mut alert = None;
+        for (rule, fired) in self.rules.iter().zip(window.fired.iter_mut()) {
+            if rule.crossed(window.used, quota.limit) && !*fired {
+                *fired = true;
+                alert = Some(rule);
+            }
+        }
+        let alert = alert.map(|rule| Alert {
+            time: usage.time,
+            user: usage.user.clone(),
+            level: rule.level.clone(),
+            message: rule.message.clone(),
+            used: window.used,
+            limit: quota.limit,
+        })?;
+
+        let line = alert.to_string();
+        for messenger in &self.messengers {
+            messenger.send(&line);
+        }
+        Some(alert)
+    }
+}
+
+/// Everything one quota file describes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+    pub default: Option<Quota>,
+    pub quotas: Vec<(String, Quota)>,
+    pub usage: Vec<Usage>,
+}
+
+impl Config {
+    /// A service with the rules and quotas of the file; the usage is left to the caller.
+    pub fn service<'a>(&self) -> QuotaService<'a> {
+        let mut service = QuotaService::new(self.rules.clone());
+        if let Some(quota) = self.default {
+            service.set_default_quota(quota);
+        }
+        for (user, quota) in &self.quotas {
+            service.set_quota(user, *quota);
+        }
+        service
+    }
+}
+
+/// One entry per line, `#` starts a comment:
+///
+/// ```text
+/// rule <percent> <level> <message...>     no rule lines: Rule::defaults()
+/// quota <user> <limit> <window seconds>   user `default` applies to everyone else
+/// use <time> <user> <amount>              in time order
+/// ```
+pub fn parse(text: &str) -> Result<Config, ParseError> {
+    let mut config = Config { rules: vec![], default: None, quotas: vec![], usage: vec![] };
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| ParseError { line: i + 1, message };
+        let number = |word: Option<&str>, what: &str| -> Result<u64, ParseError> {
+            let word = word.ok_or_else(|| error(format!("missing {}", what)))?;
+            word.parse().map_err(|_| error(format!("{} is not a number: {:?}", what, word)))
+        };
+
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some("rule") => {
+                let percent = number(words.next(), "percent")?;
+                let level = words.next().ok_or_else(|| error("missing level".to_string()))?;
+                let message = words.collect::<Vec<_>>().join(" ");
+                if message.is_empty() {
+                    return Err(error("missing message".to_string()));
+                }
+                config.rules.push(Rule::new(percent, level, &message));
+            }
+            Some("quota") => {
+                let user = words.next().ok_or_else(|| error("missing user".to_string()))?;
+                let quota = Quota {
+                    limit: number(words.next(), "limit")?,
+                    window: number(words.next(), "window")?,
+                };
+                if user == "default" {
+                    config.default = Some(quota);
+                } else {
+                    config.quotas.push((user.to_string(), quota));
+                }
+            }
+            Some("use") => {
+                let time = number(words.next(), "time")?;
+                let user = words.next().ok_or_else(|| error("missing user".to_string()))?;
+                let amount = number(words.next(), "amount")?;
+                config.usage.push(Usage::new(time, user, amount));
+            }
+            Some(other) => return Err(error(format!("unknown entry {:?}", other))),
+        }
+    }
+
+    if config.rules.is_empty() {
+        config.rules = Rule::defaults();
+    }
+    Ok(config)
+}
+
+/// `quota [<file>] [--log <path>]`
+pub fn run(args: &[String]) {
+    let mut file = None;
+    let mut log = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => log = args.next(),
+            _ => file = Some(arg),
+        }
+    }
+
+    let text = match file {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return eprintln!("quota: can not read {}: {}", path, e),
+        },
+        None => include_str!("../fixtures/quota.txt").to_string(),
+    };
+    let config = match parse(&text) {
+        Ok(config) => config,
+        Err(e) => return eprintln!("quota: {}: {}", file.map_or("fixtures/quota.txt", |f| f.as_str()), e),
+    };
+    let log = match log.map(|path| LogFile::create(path)).transpose() {
+        Ok(log) => log,
+        Err(e) => return eprintln!("quota: can not open the log: {}", e),
+    };
+
+    let mut service = config.service();
+    service.add_messenger(&Stdout);
+    if let Some(log) = &log {
+        service.add_messenger(log);
+    }
+    let alerts = config.usage.iter().filter_map(|usage| service.record(usage)).count();
+
+    println!("{} usage events, {} alerts", config.usage.len(), alerts);
+    if let Some(last) = config.usage.last() {
+        let mut users: Vec<&str> = config.usage.iter().map(|usage| usage.user.as_str()).collect();
+        users.sort_unstable();
+        users.dedup();
+        for user in users {
+            match service.quota(user) {
+                Some(quota) => println!("{}: {}/{} in the window of t={}",
+                                        user, service.used(user, last.time), quota.limit, last.time),
+                None => println!("{}: no quota", user),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn service_with_memory(memory: &Memory) -> QuotaService<'_> {
+    let mut service = QuotaService::new(Rule::defaults());
+    service.set_default_quota(Quota { limit: 100, window: 60 });
+    service.add_messenger(memory);
+    service
+}
+
+#[test]
+fn test_each_rule_fires_once_per_window() {
+    let memory = Memory::new();
+    let mut service = service_with_memory(&memory);
+
+    let levels: Vec<_> = [(0, 50), (1, 30), (2, 1), (3, 10), (4, 5), (5, 20), (59, 50)].iter()
+        .map(|&(time, amount)| service.record(&Usage::new(time, "alice", amount)).map(|a| a.level))
+        .collect();
+    let expected = [None, Some("warning"), None, Some("urgent"), None, Some("error"), None];
+    assert_eq!(levels, expected.iter().map(|l| l.map(String::from)).collect::<Vec<_>>());
+    assert_eq!(service.used("alice", 59), 166);
+
+    // next window: counting and the rules start over
+    assert_eq!(service.record(&Usage::new(60, "alice", 10)), None);
+    assert_eq!(service.used("alice", 60), 10);
+    assert_eq!(service.used("alice", 120), 0);
+    assert_eq!(service.record(&Usage::new(61, "alice", 70)).unwrap().level, "warning");
+
+    assert_eq!(memory.messages(), vec![
+        "[warning] t=1 alice (80/100): Warning: You've used up over 75% of your quota!",
+        "[urgent] t=3 alice (91/100): Urgent warning: You've used up over 90% of your quota!",
+        "[error] t=5 alice (116/100): Error: You are over your quota!",
+        "[warning] t=61 alice (80/100): Warning: You've used up over 75% of your quota!",
+    ]);
+}
+
+#[test]
+fn test_jump_sends_only_the_highest_rule() {
+    let memory = Memory::new();
+    let mut service = service_with_memory(&memory);
+
+    assert_eq!(service.record(&Usage::new(0, "bob", 95)).unwrap().level, "urgent");
+    // 75% already counts as sent
+    assert_eq!(service.record(&Usage::new(1, "bob", 0)), None);
+    assert_eq!(service.record(&Usage::new(2, "bob", 5)).unwrap().level, "error");
+    assert_eq!(memory.messages().len(), 2);
+}
+
+#[test]
+fn test_huge_numbers_do_not_overflow() {
+    let mut service = QuotaService::new(vec![Rule::new(u64::MAX, "never", "unreachable"), Rule::new(50, "info", "half")]);
+    service.set_default_quota(Quota { limit: u64::MAX, window: 0 });
+
+    assert_eq!(service.record(&Usage::new(0, "carol", u64::MAX / 2)), None);
+    assert_eq!(service.record(&Usage::new(1, "carol", 1)).unwrap().level, "info");
+    // the total stops at u64::MAX instead of wrapping around to a small number
+    assert_eq!(service.record(&Usage::new(2, "carol", u64::MAX)), None);
+    assert_eq!(service.used("carol", 2), u64::MAX);
+
+    let config = parse("rule 90 urgent almost\nquota default 18446744073709551615 0\nuse 0 dave 18446744073709551615\n").unwrap();
+    assert_eq!(config.service().record(&config.usage[0]).unwrap().level, "urgent");
+}
+
+#[test]
+fn test_quotas_per_user_and_sinks() {
+    let first = Memory::new();
+    let second = Memory::new();
+    let mut service = QuotaService::new(vec![
+        Rule::new(100, "error", "over"),
+        Rule::new(50, "info", "half"),
+    ]);
+    service.set_quota("alice", Quota { limit: 10, window: 0 });
+    service.add_messenger(&first);
+    service.add_messenger(&second);
+
+    // no default quota: bob is not limited
+    assert_eq!(service.record(&Usage::new(0, "bob", 1000)), None);
+    assert_eq!(service.record(&Usage::new(0, "alice", 5)).unwrap().message, "half");
+    // window 0 never resets
+    assert_eq!(service.record(&Usage::new(1_000_000, "alice", 5)).unwrap().message, "over");
+    assert_eq!(service.used("alice", 5_000_000), 10);
+
+    service.set_default_quota(Quota { limit: 2000, window: 10 });
+    assert_eq!(service.record(&Usage::new(3, "bob", 1000)).unwrap().level, "info");
+    assert_eq!(first.messages(), second.messages());
+    assert_eq!(first.messages().len(), 3);
+}
+
+#[test]
+fn test_log_file_messenger() {
+    let path = std::env::temp_dir().join(format!("sandbox-quota-{}.log", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    {
+        let log = LogFile::create(path).unwrap();
+        log.send("one");
+        log.send("two");
+    }
+    LogFile::create(path).unwrap().send("three");
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "one\ntwo\nthree\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_parse_fixture_and_errors() {
+    let config = parse(include_str!("../fixtures/quota.txt")).unwrap();
+    assert_eq!(config.default, Some(Quota { limit: 100, window: 3600 }));
+    assert_eq!(config.quotas[0], ("alice".to_string(), Quota { limit: 50, window: 60 }));
+    assert_eq!(config.rules.len(), 3);
+
+    let memory = Memory::new();
+    let mut service = config.service();
+    service.add_messenger(&memory);
+    let alerts: Vec<_> = config.usage.iter().filter_map(|usage| service.record(usage)).collect();
+    assert_eq!(alerts.iter().map(|a| (a.time, a.user.as_str(), a.level.as_str())).collect::<Vec<_>>(),
+               vec![(20, "alice", "warning"), (40, "alice", "over"), (70, "alice", "warning"),
+                    (300, "bob", "warning"), (3500, "bob", "over")]);
+    assert_eq!(memory.messages().len(), alerts.len());
+
+    assert_eq!(parse("use 1 alice\n").unwrap_err(), ParseError { line: 1, message: "missing amount".to_string() });
+    assert_eq!(parse("# rules\nrule x warning hi").unwrap_err().to_string(),
+               "line 2: percent is not a number: \"x\"");
+    assert_eq!(parse("rule 50 info").unwrap_err().message, "missing message");
+    assert_eq!(parse("limit 5").unwrap_err().message, "unknown entry \"limit\"");
+    assert_eq!(parse("").unwrap().rules, Rule::defaults());
+}
@@ -0,0 +1,426 @@
+/// Reference-cycle detector for `Rc` graphs
+///
+/// `smart_pointer::reference_cycle` leaks two nodes: they point at each other, so their strong
+/// counts never reach 0 once the outside handles are gone. Nothing complains, the memory is just
+/// lost. This module finds such leaks by tracing:
+///
+/// - nodes are registered by name, the registry only keeps a `Weak` so it never keeps them alive
+/// - each node type says which `Rc`s it holds strongly (`Trace`)
+/// - for every node, `strong_count - strong links from other registered nodes` is the number of
+///   handles held from outside the graph (locals, other structs); such nodes are roots
+/// - whatever is not reachable from a root is alive only because of the graph itself => leaked
+/// - the leaked nodes are split into strongly-connected components, every component with a cycle
+///   is reported together with the edges to turn into `Weak` to break it
+///
+/// NOTE: an `Rc` held by an unregistered node counts as "from outside", so register every node
+/// type which takes part in the cycle, otherwise the leak looks reachable and is not reported.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Implemented by node types to list the `Rc`s they hold (not the `Weak`s, those don't leak).
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// Collects the outgoing strong links of one node.
+#[derive(Default)]
+pub struct Tracer {
+    edges: Vec<(String, usize)>,
+}
+
+impl Tracer {
+    /// Records a strong link named `label` (e.g. the field name) to `target`.
+    pub fn edge<U: ?Sized>(&mut self, label: &str, target: &Rc<U>) {
+        self.edges.push((label.to_string(), address(target)));
+    }
+}
+
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// One live node at the time of the check: its strong count and outgoing links.
+type Snapshot = (usize, Vec<(String, usize)>);
+
+struct Entry {
+    name: String,
+    address: usize,
+    // type-erased `Weak<T>`: returns None once the node is freed
+    probe: Box<dyn Fn() -> Option<Snapshot>>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+/// A strong link `from.label -> to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub label: String,
+    pub to: String,
+}
+
+/// A cycle nothing outside the graph points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leak {
+    /// Nodes of the strongly-connected component, in registration order.
+    pub nodes: Vec<String>,
+    /// Turning all these links into `Weak` makes the component acyclic.
+    pub weaken: Vec<Edge>,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{} -> {}", self.from, self.label, self.to)
+    }
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "leaked cycle [{}], make a Weak: ", self.nodes.join(", "))?;
+        for (i, edge) in self.weaken.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", edge)?;
+        }
+        Ok(())
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Starts watching `node`. The registry only holds a `Weak`, it doesn't change the strong count.
+    pub fn register<T: Trace + 'static>(&mut self, name: &str, node: &Rc<T>) {
+        let weak = Rc::downgrade(node);
+        self.entries.push(Entry {
+            name: name.to_string(),
+            address: address(node),
+            probe: Box::new(move || {
+                // read the count before upgrade() adds one
+                let strong = weak.strong_count();
+                let node = weak.upgrade()?;
+                let mut tracer = Tracer::default();
+                node.trace(&mut tracer);
+                Some((strong, tracer.edges))
+            }),
+        });
+    }
+
+    /// Names of the registered nodes which are still allocated.
+    pub fn alive(&self) -> Vec<String> {
+        self.entries.iter()
+            .filter(|entry| (entry.probe)().is_some())
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    /// Every cycle which is only kept alive by itself.
+    pub fn find_leaks(&self) -> Vec<Leak> {
+        let graph = Graph::snapshot(&self.entries);
+        let reachable = graph.reachable_from_roots();
+        let leaked: Vec<bool> = reachable.iter().map(|r| !r).collect();
+
+        graph.components(&leaked).into_iter()
+            .filter(|component| graph.is_cyclic(component))
+            .map(|component| Leak {
+                nodes: component.iter().map(|&i| graph.names[i].clone()).collect(),
+                weaken: graph.back_edges(&component).into_iter()
+                    .map(|(from, label, to)| Edge {
+                        from: graph.names[from].clone(),
+                        label,
+                        to: graph.names[to].clone(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// The live registered nodes, indexed 0..n in registration order.
+struct Graph {
+    names: Vec<String>,
+    strong: Vec<usize>,
+    edges: Vec<Vec<(String, usize)>>,
+}
+
+impl Graph {
+    fn snapshot(entries: &[Entry]) -> Graph {
+        let live: Vec<(&Entry, Snapshot)> = entries.iter()
+            .filter_map(|entry| (entry.probe)().map(|snapshot| (entry, snapshot)))
+            .collect();
+        let index: HashMap<usize, usize> = live.iter().enumerate()
+            .map(|(i, (entry, _))| (entry.address, i))
+            .collect();
+
+        let mut graph = Graph { names: vec![], strong: vec![], edges: vec![] };
+        for (entry, (strong, edges)) in live {
+            graph.names.push(entry.name.clone());
+            graph.strong.push(strong);
+            // links to unregistered targets can't be part of a detected cycle
+            graph.edges.push(edges.into_iter()
+                .filter_map(|(label, target)| index.get(&target).map(|&to| (label, to)))
+                .collect());
+        }
+        graph
+    }
+
+    fn reachable_from_roots(&self) -> Vec<bool> {
+        let mut internal = vec![0; self.names.len()];
+        for edges in &self.edges {
+            for &(_, to) in edges {
+                internal[to] += 1;
+            }
+        }
+
+        let mut reachable = vec![false; self.names.len()];
+        let mut stack: Vec<usize> = (0..self.names.len())
+            .filter(|&i| self.strong[i] > internal[i])
+            .collect();
+        while let Some(node) = stack.pop() {
+            if !reachable[node] {
+                reachable[node] = true;
+                stack.extend(self.edges[node].iter().map(|&(_, to)| to));
+            }
+        }
+        reachable
+    }
+
+    /// Strongly-connected components of the nodes in `keep` (Kosaraju), members sorted.
+    fn components(&self, keep: &[bool]) -> Vec<Vec<usize>> {
+        let n = self.names.len();
+        let mut reverse = vec![vec![]; n];
+        for from in 0..n {
+            for &(_, to) in &self.edges[from] {
+                reverse[to].push(from);
+            }
+        }
+
+        // 1. order the nodes by DFS finish time
+        let mut visited = vec![false; n];
+        let mut order = vec![];
+        for start in (0..n).filter(|&i| keep[i]) {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut stack = vec![(start, 0)];
+            while let Some((node, next)) = stack.pop() {
+                match self.edges[node].get(next) {
+                    Some(&(_, to)) => {
+                        stack.push((node, next + 1));
+                        if keep[to] && !visited[to] {
+                            visited[to] = true;
+                            stack.push((to, 0));
+                        }
+                    }
+                    None => order.push(node),
+                }
+            }
+        }
+
+        // 2. walk the reversed graph in reverse finish order, each tree is one component
+        let mut assigned = vec![false; n];
+        let mut components = vec![];
+        for &start in order.iter().rev() {
+            if assigned[start] {
+                continue;
+            }
+            assigned[start] = true;
+            let mut component = vec![];
+            let mut stack = vec![start];
+            while let Some(node) = stack.pop() {
+                component.push(node);
+                for &from in &reverse[node] {
+                    if keep[from] && !assigned[from] {
+                        assigned[from] = true;
+                        stack.push(from);
+                    }
+                }
+            }
+            component.sort_unstable();
+            components.push(component);
+        }
+        components.sort();
+        components
+    }
+
+    fn is_cyclic(&self, component: &[usize]) -> bool {
+        component.len() > 1 || self.edges[component[0]].iter().any(|&(_, to)| to == component[0])
+    }
+
+    /// Back edges of a DFS inside `component` from its first registered node. Removing the
+    /// back edges of any DFS leaves a graph without cycles, so weakening them is enough.
+    fn back_edges(&self, component: &[usize]) -> Vec<(usize, String, usize)> {
+        let n = self.names.len();
+        let mut inside = vec![false; n];
+        for &node in component {
+            inside[node] = true;
+        }
+
+        let mut on_stack = vec![false; n];
+        let mut visited = vec![false; n];
+        let mut back = vec![];
+        let mut stack = vec![(component[0], 0)];
+        visited[component[0]] = true;
+        on_stack[component[0]] = true;
+        while let Some((node, next)) = stack.pop() {
+            match self.edges[node].get(next) {
+                Some((label, to)) => {
+                    stack.push((node, next + 1));
+                    if !inside[*to] {
+                        continue;
+                    }
+                    if on_stack[*to] {
+                        back.push((node, label.clone(), *to));
+                    } else if !visited[*to] {
+                        visited[*to] = true;
+                        on_stack[*to] = true;
+                        stack.push((*to, 0));
+                    }
+                }
+                None => on_stack[node] = false,
+            }
+        }
+        back
+    }
+}
+
+#[cfg(test)]
+use std::cell::RefCell;
+
+#[cfg(test)]
+struct Node {
+    links: RefCell<Vec<Rc<Node>>>,
+}
+
+#[cfg(test)]
+impl Trace for Node {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (i, link) in self.links.borrow().iter().enumerate() {
+            tracer.edge(&format!("links[{}]", i), link);
+        }
+    }
+}
+
+#[cfg(test)]
+fn nodes(registry: &mut Registry, names: &[&str]) -> Vec<Rc<Node>> {
+    names.iter()
+        .map(|name| {
+            let node = Rc::new(Node { links: RefCell::new(vec![]) });
+            registry.register(name, &node);
+            node
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn link(from: &Rc<Node>, to: &Rc<Node>) {
+    from.links.borrow_mut().push(Rc::clone(to));
+}
+
+/// Clears every link so a leaked test graph is freed after all.
+#[cfg(test)]
+fn unlink(nodes: &[std::rc::Weak<Node>]) {
+    for node in nodes.iter().filter_map(|n| n.upgrade()) {
+        node.links.borrow_mut().clear();
+    }
+}
+
+#[test]
+fn test_acyclic_graph_does_not_leak() {
+    let mut registry = Registry::new();
+    let n = nodes(&mut registry, &["a", "b", "c"]);
+    link(&n[0], &n[1]);
+    link(&n[0], &n[2]);
+    link(&n[1], &n[2]);
+
+    assert!(registry.find_leaks().is_empty());
+    drop(n);
+    assert!(registry.alive().is_empty());
+    assert!(registry.find_leaks().is_empty());
+}
+
+#[test]
+fn test_cycle_is_only_reported_once_unreachable() {
+    let mut registry = Registry::new();
+    let n = nodes(&mut registry, &["root", "a", "b"]);
+    link(&n[0], &n[1]);
+    link(&n[1], &n[2]);
+    link(&n[2], &n[1]);
+
+    // `root` is held by a local and reaches the cycle
+    assert!(registry.find_leaks().is_empty());
+
+    let weak: Vec<_> = n.iter().map(Rc::downgrade).collect();
+    drop(n);
+    assert_eq!(registry.alive(), vec!["a", "b"]); // root was freed, the cycle was not
+    let leaks = registry.find_leaks();
+    assert_eq!(leaks.len(), 1);
+    assert_eq!(leaks[0].nodes, vec!["a", "b"]);
+    assert_eq!(leaks[0].to_string(), "leaked cycle [a, b], make a Weak: b.links[0] -> a");
+
+    unlink(&weak);
+    assert!(registry.alive().is_empty());
+}
+
+#[test]
+fn test_separate_cycles_and_self_loop() {
+    let mut registry = Registry::new();
+    let n = nodes(&mut registry, &["a", "b", "c", "d", "e", "self"]);
+    // a -> b -> c -> a plus a shortcut c -> b, d -> e -> d, self -> self
+    link(&n[0], &n[1]);
+    link(&n[1], &n[2]);
+    link(&n[2], &n[0]);
+    link(&n[2], &n[1]);
+    link(&n[3], &n[4]);
+    link(&n[4], &n[3]);
+    link(&n[5], &n[5]);
+
+    let weak: Vec<_> = n.iter().map(Rc::downgrade).collect();
+    drop(n);
+    let leaks = registry.find_leaks();
+    let found: Vec<(Vec<&str>, Vec<String>)> = leaks.iter()
+        .map(|leak| (
+            leak.nodes.iter().map(String::as_str).collect(),
+            leak.weaken.iter().map(Edge::to_string).collect(),
+        ))
+        .collect();
+    assert_eq!(found, vec![
+        (vec!["a", "b", "c"], vec!["c.links[0] -> a".to_string(), "c.links[1] -> b".to_string()]),
+        (vec!["d", "e"], vec!["e.links[0] -> d".to_string()]),
+        (vec!["self"], vec!["self.links[0] -> self".to_string()]),
+    ]);
+
+    unlink(&weak);
+    assert!(registry.alive().is_empty());
+}
+
+#[test]
+fn test_weakening_suggested_edges_frees_the_cycle() {
+    let mut registry = Registry::new();
+    let n = nodes(&mut registry, &["a", "b", "c"]);
+    link(&n[0], &n[1]);
+    link(&n[1], &n[2]);
+    link(&n[2], &n[0]);
+    link(&n[2], &n[1]);
+
+    let weak: Vec<_> = n.iter().map(Rc::downgrade).collect();
+    drop(n);
+    let leaks = registry.find_leaks();
+
+    // remove exactly the suggested links, whatever is left must be freed on its own
+    for edge in &leaks[0].weaken {
+        let from = &weak[["a", "b", "c"].iter().position(|&name| name == edge.from).unwrap()];
+        let index: usize = edge.label.trim_start_matches("links[").trim_end_matches(']').parse().unwrap();
+        from.upgrade().unwrap().links.borrow_mut()[index] = Rc::new(Node { links: RefCell::new(vec![]) });
+    }
+    assert!(registry.alive().is_empty());
+}
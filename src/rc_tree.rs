@@ -0,0 +1,351 @@
+/// Tree with `Rc` children and `Weak` parents
+///
+/// `smart_pointer::tree_weak` links one leaf to one branch by hand. This is the same layout with
+/// the bookkeeping done in one place, so the two directions never disagree:
+///
+/// - a parent owns its children: `children: RefCell<Vec<Rc<Node<T>>>>`
+/// - a child only points back: `parent: RefCell<Weak<Node<T>>>`, no cycle, nothing leaks
+/// - every operation which moves a node updates both its parent's `children` and its `parent`
+///
+/// Nodes are handed out as `Rc<Node<T>>`. Holding such a handle keeps that node (and its subtree)
+/// alive even after it is removed from the tree, dropping the last one frees it.
+
+use std::cell::{Ref, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+pub struct Node<T> {
+    value: T,
+    parent: RefCell<Weak<Node<T>>>,
+    children: RefCell<Vec<Rc<Node<T>>>>,
+}
+
+pub struct Tree<T> {
+    root: Rc<Node<T>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TreeError {
+    /// The new parent is the node itself or one of its descendants.
+    WouldCycle,
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::WouldCycle => write!(f, "a node can not be moved below itself"),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn new(value: T) -> Rc<Node<T>> {
+        Rc::new(Node {
+            value,
+            parent: RefCell::new(Weak::new()),
+            children: RefCell::new(vec![]),
+        })
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn parent(&self) -> Option<Rc<Node<T>>> {
+        self.parent.borrow().upgrade()
+    }
+
+    pub fn children(&self) -> Ref<'_, Vec<Rc<Node<T>>>> {
+        self.children.borrow()
+    }
+
+    /// Appends a new last child and returns it.
+    pub fn add_child(self: &Rc<Self>, value: T) -> Rc<Node<T>> {
+        let child = Node::new(value);
+        self.attach(&child);
+        child
+    }
+
+    /// Detaches this node and everything below it. The subtree is freed once the returned tree
+    /// and any other handles are dropped. `None` for a root, which has nothing to detach from.
+    pub fn remove_subtree(self: &Rc<Self>) -> Option<Tree<T>> {
+        let parent = self.parent()?;
+        parent.children.borrow_mut().retain(|child| !Rc::ptr_eq(child, self));
+        *self.parent.borrow_mut() = Weak::new();
+        Some(Tree { root: Rc::clone(self) })
+    }
+
+    /// Moves this node, with its subtree, to the end of `new_parent`'s children.
+    pub fn reparent(self: &Rc<Self>, new_parent: &Rc<Node<T>>) -> Result<(), TreeError> {
+        if new_parent.path_to_root().iter().any(|node| Rc::ptr_eq(node, self)) {
+            return Err(TreeError::WouldCycle);
+        }
+        self.remove_subtree();
+        new_parent.attach(self);
+        Ok(())
+    }
+
+    fn attach(self: &Rc<Self>, child: &Rc<Node<T>>) {
+        *child.parent.borrow_mut() = Rc::downgrade(self);
+        self.children.borrow_mut().push(Rc::clone(child));
+    }
+
+    /// Number of edges up to the root, 0 for the root itself.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut parent = self.parent();
+        while let Some(node) = parent {
+            depth += 1;
+            parent = node.parent();
+        }
+        depth
+    }
+
+    /// This node, its parent, ... up to the root.
+    pub fn path_to_root(self: &Rc<Self>) -> Vec<Rc<Node<T>>> {
+        let mut path = vec![Rc::clone(self)];
+        while let Some(parent) = path[path.len() - 1].parent() {
+            path.push(parent);
+        }
+        path
+    }
+
+    /// Node, then each child subtree from first to last.
+    pub fn pre_order(self: &Rc<Self>) -> PreOrder<T> {
+        PreOrder { stack: vec![Rc::clone(self)] }
+    }
+
+    /// Each child subtree from first to last, then the node.
+    pub fn post_order(self: &Rc<Self>) -> PostOrder<T> {
+        PostOrder { stack: vec![(Rc::clone(self), false)] }
+    }
+
+    /// Breadth first: the node, all nodes one level below, and so on.
+    pub fn level_order(self: &Rc<Self>) -> LevelOrder<T> {
+        LevelOrder { queue: VecDeque::from(vec![Rc::clone(self)]) }
+    }
+
+    /// First node in pre-order whose value matches.
+    pub fn find<P>(self: &Rc<Self>, mut predicate: P) -> Option<Rc<Node<T>>>
+        where P: FnMut(&T) -> bool {
+        self.pre_order().find(|node| predicate(&node.value))
+    }
+}
+
+/// Frees deep trees without one level of recursion per level of the tree.
+impl<T> Drop for Node<T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(self.children.get_mut());
+        while let Some(child) = stack.pop() {
+            // a child still held elsewhere keeps its subtree, nothing to do below it
+            if let Ok(mut child) = Rc::try_unwrap(child) {
+                stack.append(child.children.get_mut());
+            }
+        }
+    }
+}
+
+impl<T> Tree<T> {
+    pub fn new(value: T) -> Tree<T> {
+        Tree { root: Node::new(value) }
+    }
+
+    pub fn root(&self) -> &Rc<Node<T>> {
+        &self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.pre_order().count()
+    }
+
+    pub fn pre_order(&self) -> PreOrder<T> {
+        self.root.pre_order()
+    }
+
+    pub fn post_order(&self) -> PostOrder<T> {
+        self.root.post_order()
+    }
+
+    pub fn level_order(&self) -> LevelOrder<T> {
+        self.root.level_order()
+    }
+
+    pub fn find<P>(&self, predicate: P) -> Option<Rc<Node<T>>>
+        where P: FnMut(&T) -> bool {
+        self.root.find(predicate)
+    }
+}
+
+/// One node per line, indented by two spaces per level below the root:
+///
+///     root
+///       a
+///         a1
+///       b
+impl<T: fmt::Display> fmt::Display for Tree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let base = self.root.depth();
+        for node in self.pre_order() {
+            writeln!(f, "{:indent$}{}", "", node.value, indent = 2 * (node.depth() - base))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PreOrder<T> {
+    stack: Vec<Rc<Node<T>>>,
+}
+
+impl<T> Iterator for PreOrder<T> {
+    type Item = Rc<Node<T>>;
+
+    fn next(&mut self) -> Option<Rc<Node<T>>> {
+        let node = self.stack.pop()?;
+        // reversed, so the first child is popped first
+        self.stack.extend(node.children.borrow().iter().rev().cloned());
+        Some(node)
+    }
+}
+
+pub struct PostOrder<T> {
+    // (node, children already pushed)
+    stack: Vec<(Rc<Node<T>>, bool)>,
+}
+
+impl<T> Iterator for PostOrder<T> {
+    type Item = Rc<Node<T>>;
+
+    fn next(&mut self) -> Option<Rc<Node<T>>> {
+        loop {
+            let (node, expanded) = self.stack.pop()?;
+            if expanded {
+                return Some(node);
+            }
+            let children: Vec<_> = node.children.borrow().iter().rev().map(|c| (Rc::clone(c), false)).collect();
+            self.stack.push((node, true));
+            self.stack.extend(children);
+        }
+    }
+}
+
+pub struct LevelOrder<T> {
+    queue: VecDeque<Rc<Node<T>>>,
+}
+
+impl<T> Iterator for LevelOrder<T> {
+    type Item = Rc<Node<T>>;
+
+    fn next(&mut self) -> Option<Rc<Node<T>>> {
+        let node = self.queue.pop_front()?;
+        self.queue.extend(node.children.borrow().iter().cloned());
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+fn values<I: Iterator<Item = Rc<Node<&'static str>>>>(nodes: I) -> Vec<&'static str> {
+    nodes.map(|node| node.value).collect()
+}
+
+///     root
+///       a
+///         a1
+///         a2
+///       b
+///         b1
+#[cfg(test)]
+fn sample() -> Tree<&'static str> {
+    let tree = Tree::new("root");
+    let a = tree.root().add_child("a");
+    a.add_child("a1");
+    a.add_child("a2");
+    tree.root().add_child("b").add_child("b1");
+    tree
+}
+
+#[test]
+fn test_traversals() {
+    let tree = sample();
+    assert_eq!(values(tree.pre_order()), vec!["root", "a", "a1", "a2", "b", "b1"]);
+    assert_eq!(values(tree.post_order()), vec!["a1", "a2", "a", "b1", "b", "root"]);
+    assert_eq!(values(tree.level_order()), vec!["root", "a", "b", "a1", "a2", "b1"]);
+    assert_eq!(tree.len(), 6);
+    assert_eq!(tree.to_string(), "root\n  a\n    a1\n    a2\n  b\n    b1\n");
+}
+
+#[test]
+fn test_find_depth_and_path() {
+    let tree = sample();
+    let a2 = tree.find(|v| *v == "a2").unwrap();
+    assert_eq!(a2.depth(), 2);
+    assert_eq!(values(a2.path_to_root().into_iter()), vec!["a2", "a", "root"]);
+    assert_eq!(tree.root().depth(), 0);
+    assert!(tree.root().parent().is_none());
+    assert!(tree.find(|v| *v == "zzz").is_none());
+
+    let b = tree.find(|v| v.starts_with('b')).unwrap();
+    assert_eq!(*b.value(), "b");
+    assert_eq!(values(b.children().iter().cloned()), vec!["b1"]);
+}
+
+#[test]
+fn test_remove_subtree_frees_nodes() {
+    let tree = sample();
+    let a = tree.find(|v| *v == "a").unwrap();
+    let a1 = Rc::downgrade(&a.children()[0]);
+
+    // tree owns a, plus our handle; a's children point back weakly
+    assert_eq!(Rc::strong_count(&a), 2);
+    assert_eq!(Rc::weak_count(&a), 2);
+    assert_eq!(Rc::weak_count(tree.root()), 2);
+
+    let removed = a.remove_subtree().unwrap();
+    assert_eq!(removed.to_string(), "a\n  a1\n  a2\n");
+    assert!(a.parent().is_none());
+    assert_eq!(Rc::weak_count(tree.root()), 1);
+    assert_eq!(values(tree.pre_order()), vec!["root", "b", "b1"]);
+
+    let a_weak = Rc::downgrade(&a);
+    drop(a);
+    drop(removed);
+    assert!(a_weak.upgrade().is_none());
+    assert!(a1.upgrade().is_none());
+    assert!(tree.root().remove_subtree().is_none());
+}
+
+#[test]
+fn test_reparent() {
+    let tree = sample();
+    let a = tree.find(|v| *v == "a").unwrap();
+    let b1 = tree.find(|v| *v == "b1").unwrap();
+
+    a.reparent(&b1).unwrap();
+    assert_eq!(tree.to_string(), "root\n  b\n    b1\n      a\n        a1\n        a2\n");
+    assert_eq!(a.depth(), 3);
+    assert!(Rc::ptr_eq(&a.parent().unwrap(), &b1));
+    assert_eq!(Rc::strong_count(&a), 2); // only b1 and our handle, root let go of it
+
+    let a2 = tree.find(|v| *v == "a2").unwrap();
+    assert_eq!(a.reparent(&a2), Err(TreeError::WouldCycle));
+    assert_eq!(a.reparent(&a), Err(TreeError::WouldCycle));
+    assert_eq!(a.depth(), 3);
+}
+
+#[test]
+fn test_drop_tree_frees_everything() {
+    let tree = sample();
+    let weak: Vec<_> = tree.pre_order().map(|node| Rc::downgrade(&node)).collect();
+    drop(tree);
+    assert!(weak.iter().all(|node| node.upgrade().is_none()));
+
+    // a very deep tree is freed without overflowing the stack
+    let tree = Tree::new(0);
+    let mut last = Rc::clone(tree.root());
+    for i in 1..200_000 {
+        last = last.add_child(i);
+    }
+    assert_eq!(last.depth(), 199_999);
+    drop(last);
+    drop(tree);
+}
@@ -0,0 +1,355 @@
+/// Lazy infinite sequences
+///
+/// `iterator::Counter` is a bounded stepping range. These keep going until the numbers run
+/// out: every generator works with `checked_*` arithmetic and simply ends when the next value
+/// would overflow, so `take_while`, `zip` and friends never see a wrapped value.
+///
+/// - `fibonacci()`             0, 1, 1, 2, 3, 5, ..
+/// - `primes()`                incremental sieve, no upper limit has to be known up front
+/// - `collatz(n)`              n, then n / 2 or 3n + 1 until 1 (finite, as far as anyone knows)
+/// - `arithmetic(a, d)`        a, a + d, a + 2d, ..
+/// - `geometric(a, r)`         a, a * r, a * r², ..
+/// - `recurrence(init, f)`     the initial values, then `f(last init.len() values)` forever
+///
+/// `cargo run --release -- sequences` times each one against the same thing written as a plain
+/// loop. Where they differ, it's the overflow checks: the loops just assume nothing overflows,
+/// which lets the compiler fold the arithmetic sum into a formula.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time::Instant;
+
+use crate::iterator::Stepping;
+
+pub struct Fibonacci {
+    current: Option<u64>,
+    next: Option<u64>,
+}
+
+pub fn fibonacci() -> Fibonacci {
+    Fibonacci { current: Some(0), next: Some(1) }
+}
+
+impl Iterator for Fibonacci {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let current = self.current?;
+        let after = self.next.and_then(|next| next.checked_add(current));
+        self.current = self.next;
+        self.next = after;
+        Some(current)
+    }
+}
+
+pub struct Primes {
+    /// next odd composite to expect -> 2p of the prime p which produces it
+    composites: HashMap<u64, u64>,
+    candidate: u64,
+}
+
+pub fn primes() -> Primes {
+    Primes { composites: HashMap::new(), candidate: 2 }
+}
+
+impl Iterator for Primes {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.candidate == 2 {
+            self.candidate = 3;
+            return Some(2);
+        }
+        loop {
+            let candidate = self.candidate;
+            self.candidate = candidate.checked_add(2)?;
+            match self.composites.remove(&candidate) {
+                // composite: move its prime on to the next odd multiple nobody else claimed
+                Some(step) => {
+                    let mut multiple = candidate + step;
+                    while self.composites.contains_key(&multiple) {
+                        multiple += step;
+                    }
+                    self.composites.insert(multiple, step);
+                }
+                None => {
+                    // smaller multiples of it have a smaller prime factor, those are covered
+                    if let Some(square) = candidate.checked_mul(candidate) {
+                        self.composites.insert(square, 2 * candidate);
+                    }
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+}
+
+pub struct Collatz {
+    next: Option<u64>,
+}
+
+/// Empty for 0, which never reaches 1.
+pub fn collatz(start: u64) -> Collatz {
+    Collatz { next: Some(start).filter(|&n| n > 0) }
+}
+
+impl Iterator for Collatz {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let n = self.next?;
+        self.next = match n {
+            1 => None,
+            n if n % 2 == 0 => Some(n / 2),
+            n => n.checked_mul(3).and_then(|n| n.checked_add(1)),
+        };
+        Some(n)
+    }
+}
+
+/// `next` combined with `by` gives the value after it.
+pub struct Progression<T> {
+    next: Option<T>,
+    by: T,
+    step: fn(T, T) -> Option<T>,
+}
+
+impl<T: Copy> Iterator for Progression<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let value = self.next?;
+        self.next = (self.step)(value, self.by);
+        Some(value)
+    }
+}
+
+/// `Counter::range` without the end.
+pub fn arithmetic<T: Stepping>(start: T, difference: T) -> Progression<T> {
+    Progression { next: Some(start), by: difference, step: T::forward }
+}
+
+pub fn geometric<T: Stepping>(start: T, ratio: T) -> Progression<T> {
+    Progression { next: Some(start), by: ratio, step: T::scale }
+}
+
+pub struct Recurrence<T, F> {
+    /// the last `order` values, oldest first
+    window: VecDeque<T>,
+    /// how many initial values are still to be yielded
+    initial: usize,
+    f: F,
+    done: bool,
+}
+
+/// Yields `init`, then `f(&window)` where `window` holds the last `init.len()` values, oldest
+/// first. Ends when `f` returns `None`.
+pub fn recurrence<T, F>(init: Vec<T>, f: F) -> Recurrence<T, F>
+    where T: Clone, F: FnMut(&[T]) -> Option<T> {
+    assert!(!init.is_empty(), "a recurrence needs at least one initial value");
+    Recurrence { initial: init.len(), window: init.into(), f, done: false }
+}
+
+impl<T, F> Iterator for Recurrence<T, F>
+    where T: Clone, F: FnMut(&[T]) -> Option<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.initial > 0 {
+            self.initial -= 1;
+            return self.window.get(self.window.len() - 1 - self.initial).cloned();
+        }
+        if self.done {
+            return None;
+        }
+        let next = match (self.f)(self.window.make_contiguous()) {
+            Some(next) => next,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+        self.window.pop_front();
+        self.window.push_back(next.clone());
+        Some(next)
+    }
+}
+
+/// Runs `f` a few times and returns the fastest run; the result goes through `black_box` so
+/// the work can't be optimised away.
+fn bench<R>(f: impl Fn() -> R) -> std::time::Duration {
+    (0..5).map(|_| {
+        let now = Instant::now();
+        black_box(f());
+        now.elapsed()
+    }).min().unwrap_or_default()
+}
+
+fn fib_sum_iter(limit: u64) -> u64 {
+    fibonacci().take_while(|&n| n < limit).filter(|n| n % 2 == 0).sum()
+}
+
+fn fib_sum_loop(limit: u64) -> u64 {
+    let (mut a, mut b, mut sum) = (0u64, 1u64, 0);
+    while a < limit {
+        if a % 2 == 0 {
+            sum += a;
+        }
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+    sum
+}
+
+fn longest_collatz_iter(below: u64) -> (u64, usize) {
+    (1..below).map(|n| (n, collatz(n).count())).max_by_key(|&(_, len)| len).unwrap_or((0, 0))
+}
+
+fn longest_collatz_loop(below: u64) -> (u64, usize) {
+    let mut best = (0, 0);
+    for start in 1..below {
+        let (mut n, mut len) = (start, 1);
+        while n != 1 {
+            n = if n % 2 == 0 { n / 2 } else { 3 * n + 1 };
+            len += 1;
+        }
+        if len >= best.1 {
+            best = (start, len);
+        }
+    }
+    best
+}
+
+fn arithmetic_sum_iter(terms: usize) -> u64 {
+    arithmetic(7u64, 3).take(terms).sum()
+}
+
+fn arithmetic_sum_loop(terms: usize) -> u64 {
+    let (mut value, mut sum) = (7u64, 0);
+    for _ in 0..terms {
+        sum += value;
+        value += 3;
+    }
+    sum
+}
+
+fn prime_sum_iter(below: u64) -> u64 {
+    primes().take_while(|&p| p < below).sum()
+}
+
+/// Same incremental sieve, written out inline.
+fn prime_sum_loop(below: u64) -> u64 {
+    let mut composites: HashMap<u64, u64> = HashMap::new();
+    let mut sum = if below > 2 { 2 } else { 0 };
+    let mut candidate = 3;
+    while candidate < below {
+        match composites.remove(&candidate) {
+            Some(step) => {
+                let mut multiple = candidate + step;
+                while composites.contains_key(&multiple) {
+                    multiple += step;
+                }
+                composites.insert(multiple, step);
+            }
+            None => {
+                composites.insert(candidate * candidate, 2 * candidate);
+                sum += candidate;
+            }
+        }
+        candidate += 2;
+    }
+    sum
+}
+
+pub fn run() {
+    println!("fibonacci: {:?}", fibonacci().take(12).collect::<Vec<_>>());
+    println!("primes:    {:?}", primes().take(12).collect::<Vec<_>>());
+    println!("collatz 6: {:?}", collatz(6).collect::<Vec<_>>());
+    println!("3 + 4n:    {:?}", arithmetic(3i32, 4).take(6).collect::<Vec<_>>());
+    println!("2^n:       {:?} .. {} terms fit a u64",
+             geometric(1u64, 2).take(6).collect::<Vec<_>>(), geometric(1u64, 2).count());
+    let pell = recurrence(vec![0u64, 1], |w| w[1].checked_mul(2)?.checked_add(w[0]));
+    println!("pell:      {:?}", pell.take(8).collect::<Vec<_>>());
+    let twins: Vec<_> = primes().zip(primes().skip(1)).filter(|(p, q)| q - p == 2).take(5).collect();
+    println!("twin primes: {:?}", twins);
+
+    println!("\n{:<28} {:>12} {:>12}", "iterator vs loop", "iterator", "loop");
+    let rows: Vec<(&str, std::time::Duration, std::time::Duration)> = vec![
+        ("even fibonacci < 4e18", bench(|| fib_sum_iter(black_box(4_000_000_000_000_000_000))),
+         bench(|| fib_sum_loop(black_box(4_000_000_000_000_000_000)))),
+        ("longest collatz < 300k", bench(|| longest_collatz_iter(black_box(300_000))),
+         bench(|| longest_collatz_loop(black_box(300_000)))),
+        ("arithmetic sum, 10M terms", bench(|| arithmetic_sum_iter(black_box(10_000_000))),
+         bench(|| arithmetic_sum_loop(black_box(10_000_000)))),
+        ("prime sum < 2M", bench(|| prime_sum_iter(black_box(2_000_000))),
+         bench(|| prime_sum_loop(black_box(2_000_000)))),
+    ];
+    for (name, iter, hand) in rows {
+        println!("{:<28} {:>12.2?} {:>12.2?}", name, iter, hand);
+    }
+}
+
+#[test]
+fn test_fibonacci_ends_before_overflow() {
+    assert_eq!(fibonacci().take(10).collect::<Vec<_>>(), vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+    // F(93) is the last one below 2^64
+    assert_eq!(fibonacci().count(), 94);
+    assert_eq!(fibonacci().last(), Some(12_200_160_415_121_876_738));
+    assert_eq!(fib_sum_iter(4_000_000), 4_613_732);
+}
+
+#[test]
+fn test_primes() {
+    assert_eq!(primes().take(10).collect::<Vec<_>>(), vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29]);
+    assert_eq!(primes().take_while(|&p| p < 10_000).count(), 1229);
+    assert_eq!(primes().nth(9_999), Some(104_729));
+    let gaps: Vec<u64> = primes().zip(primes().skip(1)).map(|(p, q)| q - p).take(6).collect();
+    assert_eq!(gaps, vec![1, 2, 2, 4, 2, 4]);
+}
+
+#[test]
+fn test_collatz() {
+    assert_eq!(collatz(6).collect::<Vec<_>>(), vec![6, 3, 10, 5, 16, 8, 4, 2, 1]);
+    assert_eq!(collatz(27).count(), 112);
+    assert_eq!(collatz(27).max(), Some(9232));
+    assert_eq!(collatz(1).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(collatz(0).next(), None);
+    // 3n + 1 would overflow: the sequence stops instead
+    assert_eq!(collatz(u64::MAX).collect::<Vec<_>>(), vec![u64::MAX]);
+}
+
+#[test]
+fn test_progressions() {
+    assert_eq!(arithmetic(-3i32, 4).take(4).collect::<Vec<_>>(), vec![-3, 1, 5, 9]);
+    assert_eq!(arithmetic(250u8, 2).collect::<Vec<_>>(), vec![250, 252, 254]);
+    assert_eq!(geometric(3u32, 10).take_while(|&n| n < 100_000).collect::<Vec<_>>(), vec![3, 30, 300, 3000, 30000]);
+    assert_eq!(geometric(1u64, 2).count(), 64);
+    assert_eq!(geometric(5i8, 1).take(3).collect::<Vec<_>>(), vec![5, 5, 5]);
+    // the bounded version of the same thing
+    use crate::iterator::Counter;
+    assert!(arithmetic(1u32, 3).zip(Counter::range(1, 30, 3)).all(|(a, c)| a == c));
+}
+
+#[test]
+fn test_recurrences() {
+    let tribonacci = recurrence(vec![0u64, 0, 1], |w| w[0].checked_add(w[1])?.checked_add(w[2]));
+    assert_eq!(tribonacci.take(10).collect::<Vec<_>>(), vec![0, 0, 1, 1, 2, 4, 7, 13, 24, 44]);
+    let fib = recurrence(vec![0u64, 1], |w| w[0].checked_add(w[1]));
+    assert!(fib.zip(fibonacci()).all(|(a, b)| a == b));
+    assert_eq!(recurrence(vec![0u64, 1], |w| w[0].checked_add(w[1])).count(), fibonacci().count());
+    // first order: a plain iterate
+    let halves = recurrence(vec![100u32], |w| Some(w[0] / 2).filter(|&n| n > 0));
+    assert_eq!(halves.collect::<Vec<_>>(), vec![100, 50, 25, 12, 6, 3, 1]);
+}
+
+#[test]
+fn test_loops_compute_the_same() {
+    assert_eq!(fib_sum_iter(1 << 60), fib_sum_loop(1 << 60));
+    assert_eq!(longest_collatz_iter(10_000), longest_collatz_loop(10_000));
+    assert_eq!(longest_collatz_iter(10_000), (6171, 262));
+    assert_eq!(arithmetic_sum_iter(1000), arithmetic_sum_loop(1000));
+    assert_eq!(prime_sum_iter(100_000), prime_sum_loop(100_000));
+    assert_eq!(prime_sum_iter(10), 17);
+}
@@ -0,0 +1,319 @@
+/// Thread-safe memoizer
+///
+/// `memo::Memo` needs `&mut self`, so threads would have to share it behind one `Mutex` and
+/// hold that lock while the expensive closure runs, or drop it and risk computing the same key
+/// twice. `SharedMemo::value` takes `&self`:
+///
+/// - the first caller for a key puts a `Running` entry in the map and computes without any lock
+/// - later callers for that key find the entry and block on its `Condvar` until it is `Ready`
+/// - if the closure panics, the entry becomes `Failed`: the caller and every waiter get an
+///   `Err`, and the entry is removed so the next call tries again
+/// - `sharded(n)` splits the map into n `Mutex<HashMap>` picked by key hash, so threads working
+///   on different keys rarely wait for the same map lock
+///
+/// `cargo run -- shared_memo` compares 1 shard with 16 under a few threads (on one core there
+/// is nothing to win, the locks never collide).
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// The computation for a key panicked, with the panic message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failed(pub String);
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "computation panicked: {}", self.0)
+    }
+}
+
+enum State<V> {
+    Running,
+    Ready(V),
+    Failed(String),
+}
+
+/// A computation other callers can wait for.
+struct Flight<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+enum Slot<V> {
+    Ready(V),
+    Running(Arc<Flight<V>>),
+}
+
+/// A hit only locks its shard: the value sits in the map once it is ready, and the shard keeps
+/// its own counters so shards share nothing.
+struct Shard<K, V> {
+    map: HashMap<K, Slot<V>>,
+    stats: Stats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Found ready.
+    pub hits: usize,
+    /// Ran the closure.
+    pub computed: usize,
+    /// Found running and waited for it.
+    pub waited: usize,
+    /// Got `Failed`, as the caller or as a waiter.
+    pub failed: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hits, {} computed, {} waited, {} failed",
+               self.hits, self.computed, self.waited, self.failed)
+    }
+}
+
+pub struct SharedMemo<K, V, F> where F: Fn(K) -> V {
+    calculation: F,
+    hasher: RandomState,
+    shards: Vec<Mutex<Shard<K, V>>>,
+}
+
+impl<K, V, F> SharedMemo<K, V, F>
+    where K: Hash + Eq + Clone, V: Clone, F: Fn(K) -> V {
+    /// One map for all keys.
+    pub fn new(calculation: F) -> SharedMemo<K, V, F> {
+        SharedMemo::sharded(1, calculation)
+    }
+
+    pub fn sharded(shards: usize, calculation: F) -> SharedMemo<K, V, F> {
+        assert!(shards > 0, "a memo needs at least one shard");
+        SharedMemo {
+            calculation,
+            hasher: RandomState::new(),
+            shards: (0..shards).map(|_| Mutex::new(Shard { map: HashMap::new(), stats: Stats::default() })).collect(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K, V>> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    /// The value for `arg`. The closure runs at most once per key at a time, every other caller
+    /// gets the result of that run.
+    pub fn value(&self, arg: K) -> Result<V, Failed> {
+        let shard = self.shard(&arg);
+        let flight = {
+            let mut shard = shard.lock().unwrap();
+            match shard.map.get(&arg) {
+                Some(Slot::Ready(value)) => {
+                    let value = value.clone();
+                    shard.stats.hits += 1;
+                    return Ok(value);
+                }
+                Some(Slot::Running(flight)) => {
+                    let flight = Arc::clone(flight);
+                    shard.stats.waited += 1;
+                    flight
+                }
+                None => {
+                    let flight = Arc::new(Flight { state: Mutex::new(State::Running), done: Condvar::new() });
+                    shard.map.insert(arg.clone(), Slot::Running(Arc::clone(&flight)));
+                    shard.stats.computed += 1;
+                    drop(shard);
+                    return self.compute(arg, &flight);
+                }
+            }
+        };
+
+        let mut state = flight.state.lock().unwrap();
+        while let State::Running = *state {
+            state = flight.done.wait(state).unwrap();
+        }
+        match &*state {
+            State::Ready(value) => Ok(value.clone()),
+            State::Failed(message) => {
+                self.shard(&arg).lock().unwrap().stats.failed += 1;
+                Err(Failed(message.clone()))
+            }
+            State::Running => unreachable!(),
+        }
+    }
+
+    fn compute(&self, arg: K, flight: &Flight<V>) -> Result<V, Failed> {
+        // no lock is held here: a panic can not poison anything, only this key fails
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (self.calculation)(arg.clone())))
+            .map_err(|payload| {
+                let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                Failed(message)
+            });
+
+        {
+            let mut shard = self.shard(&arg).lock().unwrap();
+            match &result {
+                Ok(value) => {
+                    shard.map.insert(arg, Slot::Ready(value.clone()));
+                }
+                Err(_) => {
+                    // the waiters hold the flight, later callers start over
+                    shard.map.remove(&arg);
+                    shard.stats.failed += 1;
+                }
+            }
+        }
+        *flight.state.lock().unwrap() = match &result {
+            Ok(value) => State::Ready(value.clone()),
+            Err(Failed(message)) => State::Failed(message.clone()),
+        };
+        flight.done.notify_all();
+        result
+    }
+
+    /// Number of keys with a value or a computation running.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().map.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.shards.iter().fold(Stats::default(), |total, shard| {
+            let stats = shard.lock().unwrap().stats;
+            Stats {
+                hits: total.hits + stats.hits,
+                computed: total.computed + stats.computed,
+                waited: total.waited + stats.waited,
+                failed: total.failed + stats.failed,
+            }
+        })
+    }
+}
+
+pub fn run() {
+    let threads = 8;
+    let keys = 2_000u64;
+    // cheap work, so the time goes into the map locks
+    let square = |n: u64| n.wrapping_mul(n);
+
+    for &shards in &[1, 16] {
+        let memo = SharedMemo::sharded(shards, square);
+        let now = Instant::now();
+        thread::scope(|s| {
+            for t in 0..threads {
+                let memo = &memo;
+                s.spawn(move || {
+                    for round in 0..50 {
+                        for key in 0..keys {
+                            let key = (key + t * 131 + round) % keys;
+                            assert_eq!(memo.value(key), Ok(key * key));
+                        }
+                    }
+                });
+            }
+        });
+        println!("{:>2} shard(s): {:?} | {} keys | {}", shards, now.elapsed(), memo.len(), memo.stats());
+        assert!(!memo.is_empty());
+    }
+}
+
+#[cfg(test)]
+fn slow_square(calls: &AtomicUsize) -> impl Fn(u64) -> u64 + Sync + '_ {
+    move |n| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(std::time::Duration::from_millis(50));
+        n * n
+    }
+}
+
+#[test]
+fn test_concurrent_callers_share_one_computation() {
+    let calls = AtomicUsize::new(0);
+    let memo = SharedMemo::new(slow_square(&calls));
+    let barrier = std::sync::Barrier::new(8);
+
+    let results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| s.spawn(|| {
+                barrier.wait();
+                memo.value(7)
+            }))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(results.iter().all(|r| *r == Ok(49)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let stats = memo.stats();
+    assert_eq!((stats.computed, stats.hits + stats.waited, stats.failed), (1, 7, 0));
+
+    assert_eq!(memo.value(7), Ok(49));
+    assert_eq!(memo.value(8), Ok(64));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(memo.len(), 2);
+}
+
+#[test]
+fn test_panic_fails_every_waiter_then_retries() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Barrier;
+
+    let broken = AtomicBool::new(true);
+    let barrier = Barrier::new(4);
+    let memo = SharedMemo::new(|n: u64| {
+        // every caller is in `value` before the first one fails
+        thread::sleep(std::time::Duration::from_millis(100));
+        if broken.swap(false, Ordering::SeqCst) {
+            panic!("boom on {}", n);
+        }
+        n + 1
+    });
+
+    let results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| s.spawn(|| {
+                barrier.wait();
+                memo.value(1)
+            }))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(results.iter().all(|r| *r == Err(Failed("boom on 1".to_string()))), "{:?}", results);
+    assert_eq!(memo.stats().failed, 4);
+    assert!(memo.is_empty());
+
+    assert_eq!(memo.value(1), Ok(2));
+    assert_eq!(memo.stats().computed, 2);
+    assert_eq!(Failed("x".to_string()).to_string(), "computation panicked: x");
+}
+
+#[test]
+fn test_sharded_keeps_keys_apart() {
+    let calls = AtomicUsize::new(0);
+    let memo = SharedMemo::sharded(4, |n: u64| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        n * 2
+    });
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for n in 0..100 {
+                    assert_eq!(memo.value(n), Ok(n * 2));
+                }
+            });
+        }
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 100);
+    assert_eq!(memo.len(), 100);
+    // with 100 keys every shard gets some
+    assert!(memo.shards.iter().all(|shard| !shard.lock().unwrap().map.is_empty()));
+    let stats = memo.stats();
+    assert_eq!(stats.computed + stats.hits + stats.waited, 400);
+}
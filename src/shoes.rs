@@ -0,0 +1,452 @@
+/// Shoe inventory
+///
+/// `iterator::shoes_in_my_size` is one hard-coded filter over a `Vec<Shoe>`. Here the shoes come
+/// from a CSV file (`name,style,size,price`, columns in any order, no quoting) and a query is a
+/// pipeline of stages separated by `|`, run left to right:
+///
+/// - `size 10`, `size 9..12`, `size 9..=11`, `size 12..`   sizes, ranges read like Rust ranges
+/// - `price ..100`                                          same for prices
+/// - `style boot`                                           style contains the word, any case
+/// - `sort price [asc|desc]`                                stable, by name, style, size or price
+/// - `limit 3`
+/// - `group style`                                          counts per value, must come last
+///
+/// ```text
+/// cargo run -- shoes query "size 10"                          (that is shoes_in_my_size)
+/// cargo run -- shoes query "size 9..=11 | style boot | sort price desc | limit 2"
+/// cargo run -- shoes query "price ..100 | group style" --file <csv>
+/// ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::iter_ext::IterExt;
+use crate::parse_error::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shoe {
+    pub name: String,
+    pub style: String,
+    pub size: u32,
+    pub price: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Style,
+    Size,
+    Price,
+}
+
+impl Field {
+    fn parse(word: &str) -> Option<Field> {
+        match word {
+            "name" => Some(Field::Name),
+            "style" => Some(Field::Style),
+            "size" => Some(Field::Size),
+            "price" => Some(Field::Price),
+            _ => None,
+        }
+    }
+
+    fn compare(self, a: &Shoe, b: &Shoe) -> Ordering {
+        match self {
+            Field::Name => a.name.cmp(&b.name),
+            Field::Style => a.style.cmp(&b.style),
+            Field::Size => a.size.cmp(&b.size),
+            Field::Price => a.price.cmp(&b.price),
+        }
+    }
+
+    fn value(self, shoe: &Shoe) -> String {
+        match self {
+            Field::Name => shoe.name.clone(),
+            Field::Style => shoe.style.clone(),
+            Field::Size => shoe.size.to_string(),
+            Field::Price => shoe.price.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Name => "name",
+            Field::Style => "style",
+            Field::Size => "size",
+            Field::Price => "price",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inventory {
+    pub shoes: Vec<Shoe>,
+}
+
+impl Inventory {
+    /// A header line naming the four columns, then one shoe per line; blank lines are skipped.
+    pub fn parse(csv: &str) -> Result<Inventory, ParseError> {
+        let mut lines = csv.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let (header_index, header) = match lines.next() {
+            Some(header) => header,
+            None => return Ok(Inventory { shoes: vec![] }),
+        };
+        let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+        let column = |name: &str| {
+            columns.iter().position(|c| *c == name)
+                .ok_or_else(|| ParseError { line: header_index + 1, message: format!("no {} column", name) })
+        };
+        let (name, style, size, price) = (column("name")?, column("style")?, column("size")?, column("price")?);
+
+        let mut shoes = vec![];
+        for (i, line) in lines {
+            let error = |message: String| ParseError { line: i + 1, message };
+            let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+            if cells.len() != columns.len() {
+                return Err(error(format!("expected {} values, found {}", columns.len(), cells.len())));
+            }
+            let number = |at: usize, what: &str| {
+                cells[at].parse::<u32>().map_err(|_| error(format!("{} is not a number: {:?}", what, cells[at])))
+            };
+            shoes.push(Shoe {
+                name: cells[name].to_string(),
+                style: cells[style].to_string(),
+                size: number(size, "size")?,
+                price: number(price, "price")?,
+            });
+        }
+        Ok(Inventory { shoes })
+    }
+
+    pub fn query(&self, query: &Query) -> Output<'_> {
+        let mut shoes: Vec<&Shoe> = self.shoes.iter().collect();
+        for stage in &query.stages {
+            match stage {
+                Stage::Size(range) => shoes.retain(|s| range.contains(&s.size)),
+                Stage::Price(range) => shoes.retain(|s| range.contains(&s.price)),
+                Stage::Style(word) => shoes.retain(|s| s.style.to_lowercase().contains(word.as_str())),
+                Stage::Sort(field, descending) => shoes.sort_by(|a, b| {
+                    let order = field.compare(a, b);
+                    if *descending { order.reverse() } else { order }
+                }),
+                Stage::Limit(n) => shoes.truncate(*n),
+                Stage::Group(field) => {
+                    shoes.sort_by(|a, b| field.compare(a, b));
+                    let counts = shoes.iter()
+                        .group_by(|s| field.value(s))
+                        .map(|(value, group)| (value, group.len()))
+                        .collect();
+                    return Output::Groups(*field, counts);
+                }
+            }
+        }
+        Output::Shoes(shoes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    Size(RangeInclusive<u32>),
+    Price(RangeInclusive<u32>),
+    /// lower case already
+    Style(String),
+    /// field, descending
+    Sort(Field, bool),
+    Limit(usize),
+    Group(Field),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// 1 based, like `ParseError::line`
+    pub stage: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stage {}: {}", self.stage, self.message)
+    }
+}
+
+/// `10`, `9..12`, `9..=11`, `12..`, `..12` or `..=11`.
+fn parse_range(text: &str) -> Option<RangeInclusive<u32>> {
+    let bound = |text: &str, empty: u32| if text.is_empty() { Some(empty) } else { text.parse().ok() };
+    if let Some(at) = text.find("..=") {
+        let (start, end) = (&text[..at], &text[at + 3..]);
+        if end.is_empty() {
+            return None;
+        }
+        Some(bound(start, 0)?..=bound(end, 0)?)
+    } else if let Some(at) = text.find("..") {
+        let (start, end) = (&text[..at], &text[at + 2..]);
+        let start = bound(start, 0)?;
+        match bound(end, u32::MAX)? {
+            u32::MAX if end.is_empty() => Some(start..=u32::MAX),
+            // `..0` holds nothing
+            0 => Some(RangeInclusive::new(1, 0)),
+            end => Some(start..=end - 1),
+        }
+    } else {
+        let n = text.parse().ok()?;
+        Some(n..=n)
+    }
+}
+
+/// The words after a keyword, with the spaces around a range operator taken out: `9 ..= 11`
+/// is one word `9..=11`, `9 11` stays two.
+fn range_words<'a>(words: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut joined: Vec<String> = vec![];
+    for word in words {
+        match joined.last_mut() {
+            Some(last) if last.ends_with("..") || last.ends_with("..=") || word.starts_with("..") => last.push_str(word),
+            _ => joined.push(word.to_string()),
+        }
+    }
+    joined
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let mut stages = vec![];
+        if text.trim().is_empty() {
+            return Ok(Query { stages });
+        }
+
+        for (i, stage) in text.split('|').enumerate() {
+            let error = |message: String| QueryError { stage: i + 1, message };
+            if stages.last().is_some_and(|last| matches!(last, Stage::Group(_))) {
+                return Err(error("nothing can follow group".to_string()));
+            }
+            let mut words = stage.split_whitespace();
+            let keyword = words.next().ok_or_else(|| error("empty stage".to_string()))?;
+            let field = |word: Option<&str>| {
+                let word = word.ok_or_else(|| error(format!("{} needs a field", keyword)))?;
+                Field::parse(word).ok_or_else(|| error(format!("unknown field {:?}", word)))
+            };
+            // ranges may be written with spaces: `size 9 ..= 11`
+            let rest = range_words(words.clone());
+            let extra = |word: Option<&str>| match word {
+                Some(word) => Err(error(format!("unexpected {:?} after {}", word, keyword))),
+                None => Ok(()),
+            };
+            let single = || {
+                extra(rest.get(1).map(String::as_str))?;
+                Ok(rest.first().map_or("", String::as_str))
+            };
+
+            stages.push(match keyword {
+                "size" | "price" => {
+                    let rest = single()?;
+                    let range = parse_range(rest).ok_or_else(|| error(format!("bad {} range {:?}", keyword, rest)))?;
+                    if keyword == "size" { Stage::Size(range) } else { Stage::Price(range) }
+                }
+                "style" if !rest.is_empty() => Stage::Style(words.collect::<Vec<_>>().join(" ").to_lowercase()),
+                "style" => return Err(error("style needs a word".to_string())),
+                "sort" => {
+                    let field = field(words.next())?;
+                    let descending = match words.next() {
+                        None | Some("asc") => false,
+                        Some("desc") => true,
+                        Some(other) => return Err(error(format!("sort order must be asc or desc, not {:?}", other))),
+                    };
+                    extra(words.next())?;
+                    Stage::Sort(field, descending)
+                }
+                "limit" => {
+                    let rest = single()?;
+                    Stage::Limit(rest.parse().map_err(|_| error(format!("limit is not a number: {:?}", rest)))?)
+                }
+                "group" => {
+                    let field = field(words.next())?;
+                    extra(words.next())?;
+                    Stage::Group(field)
+                }
+                other => return Err(error(format!("unknown stage {:?}", other))),
+            });
+        }
+        Ok(Query { stages })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output<'a> {
+    Shoes(Vec<&'a Shoe>),
+    /// value and count, in the order of the field
+    Groups(Field, Vec<(String, usize)>),
+}
+
+impl fmt::Display for Output<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Shoes(shoes) => {
+                writeln!(f, "{:<12} {:<8} {:>4} {:>6}", "name", "style", "size", "price")?;
+                for shoe in shoes {
+                    writeln!(f, "{:<12} {:<8} {:>4} {:>6}", shoe.name, shoe.style, shoe.size, shoe.price)?;
+                }
+                write!(f, "({} shoes)", shoes.len())
+            }
+            Output::Groups(field, groups) => {
+                writeln!(f, "{:<12} {:>5}", field, "count")?;
+                for (value, count) in groups {
+                    writeln!(f, "{:<12} {:>5}", value, count)?;
+                }
+                write!(f, "({} groups)", groups.len())
+            }
+        }
+    }
+}
+
+pub fn run(args: &[String]) {
+    let mut query = None;
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "query" => args.next().map(|q| query = Some(q.clone())).ok_or("query needs an expression"),
+            "--file" => args.next().map(|f| file = Some(f.clone())).ok_or("--file needs a value"),
+            _ => Err("usage: shoes [query \"<expr>\"] [--file <csv>]"),
+        };
+        if let Err(e) = parsed {
+            return eprintln!("shoes: {}", e);
+        }
+    }
+
+    let text = match &file {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return eprintln!("shoes: can not read {}: {}", path, e),
+        },
+        None => include_str!("../fixtures/shoes.csv").to_string(),
+    };
+    let inventory = match Inventory::parse(&text) {
+        Ok(inventory) => inventory,
+        Err(e) => return eprintln!("shoes: {}: {}", file.as_deref().unwrap_or("fixtures/shoes.csv"), e),
+    };
+
+    // without a query, show what the language can do
+    let queries = match query {
+        Some(query) => vec![query],
+        None => ["size 10", "size 9..=11 | style boot | sort price desc | limit 2", "price ..100 | group style"]
+            .iter().map(|q| q.to_string()).collect(),
+    };
+    for text in &queries {
+        match Query::parse(text) {
+            Ok(query) => println!("> {}\n{}\n", text, inventory.query(&query)),
+            Err(e) => eprintln!("shoes: {:?}: {}", text, e),
+        }
+    }
+}
+
+#[cfg(test)]
+fn fixture() -> Inventory {
+    Inventory::parse(include_str!("../fixtures/shoes.csv")).unwrap()
+}
+
+#[cfg(test)]
+fn run_query<'a>(inventory: &'a Inventory, query: &str) -> Output<'a> {
+    inventory.query(&Query::parse(query).unwrap())
+}
+
+#[test]
+fn test_parse_csv() {
+    let inventory = fixture();
+    assert_eq!(inventory.shoes.len(), 20);
+    assert_eq!(inventory.shoes[0], Shoe { name: "Runner One".to_string(), style: "sneaker".to_string(), size: 10, price: 89 });
+
+    // columns in any order, blank lines skipped
+    let inventory = Inventory::parse("size, price,style,name\n\n7,10,boot,A\n").unwrap();
+    assert_eq!(inventory.shoes, vec![Shoe { name: "A".to_string(), style: "boot".to_string(), size: 7, price: 10 }]);
+    assert_eq!(Inventory::parse("").unwrap().shoes, vec![]);
+
+    let error = |csv: &str| Inventory::parse(csv).unwrap_err().to_string();
+    assert_eq!(error("name,style,size\n"), "line 1: no price column");
+    assert_eq!(error("\n  \nname,style,size\n"), "line 3: no price column");
+    assert_eq!(error("name,style,size,price\nA,boot,7,10\nB,boot,big,10\n"), "line 3: size is not a number: \"big\"");
+    assert_eq!(error("name,style,size,price\nA,boot,7\n"), "line 2: expected 4 values, found 3");
+}
+
+#[test]
+fn test_size_is_shoes_in_my_size() {
+    let inventory = fixture();
+    let expected: Vec<&Shoe> = inventory.shoes.iter().filter(|s| s.size == 10).collect();
+
+    assert_eq!(run_query(&inventory, "size 10"), Output::Shoes(expected));
+    let sizes = |query| match run_query(&inventory, query) {
+        Output::Shoes(shoes) => shoes.iter().map(|s| s.size).collect::<Vec<_>>(),
+        Output::Groups(..) => panic!("no group stage"),
+    };
+    assert!(sizes("size 9..11").iter().all(|s| (9..11).contains(s)));
+    assert_eq!(sizes("size 9..11").len(), 9);
+    assert_eq!(sizes("size 9 ..= 11").len(), 13);
+    assert_eq!(sizes("size 9 ..=11"), sizes("size 9..= 11"));
+    assert_eq!(sizes("size 12 .."), vec![12, 13, 12, 13, 12]);
+    assert_eq!(sizes("size 12.."), vec![12, 13, 12, 13, 12]);
+    assert_eq!(sizes("size ..=8"), vec![8, 8]);
+    assert!(sizes("size 0..0").is_empty());
+    assert_eq!(sizes("").len(), 20);
+}
+
+#[test]
+fn test_pipeline() {
+    let inventory = fixture();
+    let names = |query| match run_query(&inventory, query) {
+        Output::Shoes(shoes) => shoes.iter().map(|s| format!("{} {}", s.name, s.size)).collect::<Vec<_>>(),
+        Output::Groups(..) => panic!("no group stage"),
+    };
+
+    assert_eq!(names("size 9..=11 | style BOOT | sort price desc | limit 2"), vec!["Hiker 10", "Hiker 11"]);
+    assert_eq!(names("style sand | sort price | limit 2"), vec!["Flip 12", "Beach Day 9"]);
+    // stable: equal prices keep the file order
+    assert_eq!(names("price 130..=150 | sort price"), vec!["Chelsea 9", "Chelsea 10", "Hiker 10", "Hiker 11"]);
+    // stages run in order, limiting first gives a different answer
+    assert_eq!(names("limit 3 | sort size"), vec!["Court Low 9", "Runner One 10", "Runner One 11"]);
+}
+
+#[test]
+fn test_group_counts() {
+    let inventory = fixture();
+    let groups = |query| match run_query(&inventory, query) {
+        Output::Groups(_, groups) => groups,
+        Output::Shoes(_) => panic!("expected groups"),
+    };
+    let expected = |pairs: &[(&str, usize)]| pairs.iter().map(|(v, c)| (v.to_string(), *c)).collect::<Vec<_>>();
+
+    assert_eq!(groups("group style"), expected(&[("boot", 6), ("loafer", 3), ("sandal", 4), ("sneaker", 7)]));
+    assert_eq!(groups("price ..100 | group style"), expected(&[("loafer", 2), ("sandal", 4), ("sneaker", 6)]));
+    // numbers group in number order, not text order
+    assert_eq!(groups("style boot | group size"), expected(&[("9", 1), ("10", 2), ("11", 1), ("12", 1), ("13", 1)]));
+    assert!(groups("size 20 | group name").is_empty());
+
+    let shown = run_query(&inventory, "style loafer | group name").to_string();
+    assert_eq!(shown, "name         count\nPenny            2\nTassel           1\n(2 groups)");
+}
+
+#[test]
+fn test_query_errors() {
+    let error = |query| Query::parse(query).unwrap_err().to_string();
+    assert_eq!(error("size ten"), "stage 1: bad size range \"ten\"");
+    assert_eq!(error("size 9..=11 | colour red"), "stage 2: unknown stage \"colour\"");
+    assert_eq!(error("sort weight"), "stage 1: unknown field \"weight\"");
+    assert_eq!(error("sort size up"), "stage 1: sort order must be asc or desc, not \"up\"");
+    assert_eq!(error("group style | limit 2"), "stage 2: nothing can follow group");
+    assert_eq!(error("size 10 || limit 2"), "stage 2: empty stage");
+    assert_eq!(error("style"), "stage 1: style needs a word");
+    assert_eq!(error("limit -1"), "stage 1: limit is not a number: \"-1\"");
+    // extra words are not glued together, only a range may have spaces in it
+    assert_eq!(error("size 9 11"), "stage 1: unexpected \"11\" after size");
+    assert_eq!(error("limit 1 0"), "stage 1: unexpected \"0\" after limit");
+    assert_eq!(error("price .. 100 200"), "stage 1: unexpected \"200\" after price");
+    assert_eq!(error("sort size desc now"), "stage 1: unexpected \"now\" after sort");
+    assert_eq!(error("group style size"), "stage 1: unexpected \"size\" after group");
+}
@@ -14,6 +14,11 @@ enum List {
 }
 
 use self::List::{Cons, Nil};
+use std::rc::Rc;
+
+/// NOTE: this `List` only holds `i32`, can not be shared and has no operations,
+/// `cons_list::List<T>` is the generic, `Rc`-shared version used by the rest of this file
+use crate::cons_list;
 
 pub fn run() {
     let list = Cons(1,
@@ -22,6 +27,21 @@ pub fn run() {
                 Box::new(Nil))))));
 
     println!("List: {:?}", list);
+
+    let list: cons_list::List<i32> = (1..=3).collect();
+    println!("cons_list: {} | len = {}", list, list.len());
+    println!("tail = {} | mapped = {}", list.tail().unwrap_or_default(), list.map(|x| x * 10));
+    println!("reversed = {} | sum = {}", list.reverse(), list.fold(0, |acc, x| acc + x));
+    println!("shared = {} | head = {:?}", list.cons(0), list.head());
+    println!("empty = {}", cons_list::List::<i32>::new().is_empty());
+
+    my_pointers();
+
+    drop_order();
+
+    reference_cycle::detect();
+
+    tree_weak::demo();
 }
 
 #[test]
@@ -94,6 +114,33 @@ fn test_deref_coercion() {
     // and the last & make a ref to that literal string slide
 }
 
+/// `Rc<T>` and `RefCell<T>` below are no magic either: `my_rc.rs` and `my_ref_cell.rs` rebuild them
+/// on raw pointers and `UnsafeCell` with the same API
+fn my_pointers() {
+    use crate::my_rc::{MyRc, MyWeak};
+    use crate::my_ref_cell::MyRefCell;
+
+    let mut a = MyRc::new(MyRefCell::new(vec![1]));
+    let b = MyRc::clone(&a);
+    let weak = MyRc::downgrade(&a);
+    b.borrow_mut().push(2);
+    println!("a = {:?} | same value = {} | strong = {}, weak = {}",
+             a.borrow(), MyRc::ptr_eq(&a, &b), MyRc::strong_count(&a), MyRc::weak_count(&a));
+    {
+        let _reader = a.borrow();
+        println!("borrow_mut while reading = {:?}", a.try_borrow_mut().map(|_| ()));
+    }
+
+    // shared: get_mut refuses, make_mut copies the value away from `b`
+    println!("get_mut while shared = {:?}", MyRc::get_mut(&mut a).map(|_| ()));
+    MyRc::make_mut(&mut a).get_mut().push(3);
+    println!("a = {:?} | b = {:?} | weak points to b = {}", a.borrow(), b.borrow(), weak.strong_count() == 1);
+
+    drop(b);
+    println!("upgrade after b dropped = {:?} | MyWeak::new() = {:?}", weak.upgrade(), MyWeak::<i32>::new().upgrade());
+    println!("{} | {}", MyRc::new("display"), MyRefCell::new("into_inner").into_inner());
+}
+
 /// NOTE: Rust does deref coercion when it finds types and trait implementations in three cases:
 ///     - From `&T` to `&U` when `T: Deref<Target=U>`
 ///     - From `&mut T` to `&mut U` when `T: DerefMut<Target=U>`
@@ -122,6 +169,39 @@ fn test_drop() {
     println!("CustomSmartPointer dropped before the end of main.");
 }
 
+/// `tracked::Tracked` journals the drops instead of printing them, so the order can be checked
+fn drop_order() {
+    use crate::tracked::{record, Tracked};
+
+    let ((), journal) = record(|| {
+        let c = Tracked::new("c", String::from("one"));
+        let _d = Tracked::new("d", String::from("two"));
+        let rc = Tracked::new("rc", String::from("three")).into_rc();
+        let _rc2 = Rc::clone(&rc);
+        println!("created {}, dropping it early", c.label());
+        drop(c);
+    });
+    print!("{}", journal);
+}
+
+#[test]
+fn test_drop_order_tracked() {
+    use crate::tracked::{record, Tracked};
+
+    let ((), journal) = record(|| {
+        let c = Tracked::new("c", CustomPointer { data: String::from("one") });
+        let _d = Tracked::new("d", CustomPointer { data: String::from("two") });
+        let _e = Box::new(Tracked::new("e", ()));
+        let rc = Tracked::new("rc", ()).into_rc();
+        let _rc2 = Rc::clone(&rc);
+        drop(c);
+        drop(rc); // not the last owner: nothing happens yet
+    });
+    // explicit drop first, then the locals in reverse order; the Rc value goes with `_rc2`
+    assert_eq!(journal.drop_order(), vec!["c", "rc", "e", "d"]);
+    journal.assert_no_leaks();
+}
+
 /// Reference counter smart pointer
 /// NOTE: We use the Rc<T> type when we want to allocate some data on the heap for multiple parts
 /// of our program to read and we can’t determine at compile time which part will finish using
@@ -134,32 +214,68 @@ fn test_drop() {
 mod rc_list {
     /// NOTE: please note that Rc<T> just be used in `single-threaded scenarios`
 
+    #[cfg(test)]
+    use crate::cons_list::List;
+    #[cfg(test)]
+    use crate::my_rc::MyRc;
+    #[cfg(test)]
     use std::rc::Rc;
 
-    #[derive(Debug)]
-    enum List {
-        Cons(i32, Rc<List>),
-        Nil
-    }
-
-    use List::{Cons, Nil};
-
     #[test]
     fn test_rc() {
-        // call Rc::clone() to pass reference to list a and use it to create two new list
+        // `cons` shares the old list through Rc::clone() instead of copying it
         // note: The implementation of Rc::clone doesn’t make a deep copy of all the data like most
         // types’ implementations of clone do. The call to Rc::clone only increments the reference count,
         // which doesn’t take much time. => cheaper than call a.clone() which deep copy data on heap
-        let a = Rc::new(Cons(5, Rc::new(Cons(10, Rc::new(Nil)))));
-        println!("a = {:?} | count after creating a = {}", a, Rc::strong_count(&a));
-        let b = Cons(3, Rc::clone(&a));
-        println!("b = {:?} | count after creating b = {}", b, Rc::strong_count(&a));
+        let a = List::new().cons(10).cons(5);
+        println!("a = {} | len = {}", a, a.len());
+        let b = a.cons(3);
+        println!("b = {} | len = {}", b, b.len());
         {
-            let c = Cons(4, Rc::clone(&a));
-            println!("c = {:?} | count after creating c = {}", c, Rc::strong_count(&a));
+            let c = a.cons(4);
+            println!("c = {} | len = {}", c, c.len());
         }
-        println!("a = {:?} | count after c goes out of scope = {}", a, Rc::strong_count(&a));
+        println!("a = {} after c goes out of scope", a);
+        assert_eq!(b.tail(), Some(a));
+    }
+
+    /// the counts by hand, once with std `Rc` and once with `my_rc::MyRc`
+    #[cfg(test)]
+    macro_rules! rc_counts {
+        ($name:ident, $Rc:ident) => {
+            mod $name {
+                use super::*;
+
+                #[derive(Debug)]
+                enum List {
+                    Cons(i32, $Rc<List>),
+                    Nil
+                }
+
+                use self::List::{Cons, Nil};
+
+                #[test]
+                fn test_rc_counts() {
+                    let a = $Rc::new(Cons(5, $Rc::new(Cons(10, $Rc::new(Nil)))));
+                    assert_eq!($Rc::strong_count(&a), 1);
+                    let b = Cons(3, $Rc::clone(&a));
+                    assert_eq!($Rc::strong_count(&a), 2);
+                    {
+                        let c = Cons(4, $Rc::clone(&a));
+                        assert_eq!($Rc::strong_count(&a), 3);
+                        println!("c = {:?}", c);
+                    }
+                    assert_eq!($Rc::strong_count(&a), 2);
+                    println!("b = {:?}", b);
+                }
+            }
+        };
     }
+
+    #[cfg(test)]
+    rc_counts!(with_std, Rc);
+    #[cfg(test)]
+    rc_counts!(with_my_rc, MyRc);
 }
 
 mod ref_cell {
@@ -172,72 +288,90 @@ mod ref_cell {
 
     /// NOTE: you can mutate the value inside the RefCell<T> even when the RefCell<T> is immutable.
 
-    trait Messenger {
-        fn send(&self, msg: &str);
-    }
+    /// `quota::QuotaService` is the grown-up version: configurable rules, per-user windows and
+    /// several messengers. The tracker here shares its `Messenger` trait and default rules.
+    use crate::quota::{self, Messenger, Rule};
 
     struct LimitTracker<'a, T: Messenger> {
         messenger: &'a T,
         value: usize,
         max: usize,
+        rules: Vec<Rule>,
     }
 
     impl<'a, T> LimitTracker<'a, T>
         where T: Messenger {
-        pub fn new(messenger: &T, max: usize) -> LimitTracker<T> {
+        pub fn new(messenger: &T, max: usize) -> LimitTracker<'_, T> {
+            LimitTracker::with_rules(messenger, max, Rule::defaults())
+        }
+
+        pub fn with_rules(messenger: &T, max: usize, rules: Vec<Rule>) -> LimitTracker<'_, T> {
             LimitTracker {
                 messenger,
                 value: 0,
                 max,
+                rules,
             }
         }
 
         pub fn set_value(&mut self, value: usize) {
             self.value = value;
 
-            let percentage_of_max = self.value as f64 / self.max as f64;
-
-            if percentage_of_max >= 1.0 {
-                self.messenger.send("Error: You are over your quota!");
-            } else if percentage_of_max >= 0.9 {
-                self.messenger.send("Urgent warning: You've used up over 90% of your quota!");
-            } else if percentage_of_max >= 0.75 {
-                self.messenger.send("Warning: You've used up over 75% of your quota!");
+            if let Some(rule) = quota::highest(&self.rules, self.value as u64, self.max as u64) {
+                self.messenger.send(&rule.message);
             }
         }
     }
 
+    #[cfg(test)]
     use std::cell::RefCell;
-
-    struct MockMessenger {
-        // sent_messages: Vec<String>,
-        sent_messages: RefCell<Vec<String>>,
-    }
-
-    impl MockMessenger {
-        fn new() -> MockMessenger {
-            // MockMessenger { sent_messages: vec![] }
-            MockMessenger { sent_messages: RefCell::new(vec![]) }
-        }
-    }
-
-    impl Messenger for MockMessenger {
-        fn send(&self, message: &str) {
-            // self.sent_messages.push(String::from(message));
-            self.sent_messages.borrow_mut().push(String::from(message));
-        }
+    #[cfg(test)]
+    use crate::my_ref_cell::MyRefCell;
+
+    /// the mock once with std `RefCell` and once with `my_ref_cell::MyRefCell`
+    #[cfg(test)]
+    macro_rules! mock_messenger {
+        ($name:ident, $RefCell:ident) => {
+            mod $name {
+                use super::*;
+
+                struct MockMessenger {
+                    // sent_messages: Vec<String>,
+                    sent_messages: $RefCell<Vec<String>>,
+                }
+
+                impl MockMessenger {
+                    fn new() -> MockMessenger {
+                        // MockMessenger { sent_messages: vec![] }
+                        MockMessenger { sent_messages: $RefCell::new(vec![]) }
+                    }
+                }
+
+                impl Messenger for MockMessenger {
+                    fn send(&self, message: &str) {
+                        // self.sent_messages.push(String::from(message));
+                        self.sent_messages.borrow_mut().push(String::from(message));
+                    }
+                }
+
+                #[test]
+                fn it_sends_an_over_75_percent_warning_message() {
+                    let mock_messenger = MockMessenger::new();
+                    let mut limit_tracker = LimitTracker::new(&mock_messenger, 100);
+
+                    limit_tracker.set_value(80);
+
+                    // assert_eq!(mock_messenger.sent_messages.len(), 1);
+                    assert_eq!(mock_messenger.sent_messages.borrow().len(), 1);
+                }
+            }
+        };
     }
 
-    #[test]
-    fn it_sends_an_over_75_percent_warning_message() {
-        let mock_messenger = MockMessenger::new();
-        let mut limit_tracker = LimitTracker::new(&mock_messenger, 100);
-
-        limit_tracker.set_value(80);
-
-        // assert_eq!(mock_messenger.sent_messages.len(), 1);
-        assert_eq!(mock_messenger.sent_messages.borrow().len(), 1);
-    }
+    #[cfg(test)]
+    mock_messenger!(with_std, RefCell);
+    #[cfg(test)]
+    mock_messenger!(with_my_ref_cell, MyRefCell);
 }
 
 /// Combine Rc<T> (multiple owners that have immutable access to single data) and
@@ -253,6 +387,7 @@ mod multi_mutate_list {
         Nil,
     }
 
+    #[cfg(test)]
     use self::List::{Cons, Nil};
 
     #[test]
@@ -322,66 +457,190 @@ mod reference_cycle {
         // it will overflow the stack
         // println!("a next item = {:?}", a.tail());
     }
-}
 
-mod tree_weak {
-    use std::rc::{Rc, Weak};
-    use std::cell::RefCell;
+    /// `rc_cycles` can see the leak which the test above only describes
+    use crate::rc_cycles::{Trace, Tracer};
 
-    #[derive(Debug)]
-    struct Node {
-        value: i32,
-        children: RefCell<Vec<Rc<Node>>>,
-        parent: RefCell<Weak<Node>>,
+    impl Trace for List {
+        fn trace(&self, tracer: &mut Tracer) {
+            if let Some(link) = self.tail() {
+                tracer.edge("tail", &link.borrow());
+            }
+        }
     }
 
-    #[test]
-    fn test() {
-        let leaf = Rc::new(Node {
-            value: 5,
-            parent: RefCell::new(Weak::new()),
-            children: RefCell::new(vec![])
-        });
-
-        println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
-        println!(
-            "leaf strong = {}, weak = {}",
-            Rc::strong_count(&leaf),
-            Rc::weak_count(&leaf),
-        );
+    /// Builds the cycle from `test`, lets the detector find it, then breaks it as suggested.
+    pub fn detect() {
+        use crate::rc_cycles::Registry;
 
-        {
-            let branch = Rc::new(Node {
-                value: 10,
-                parent: RefCell::new(Weak::new()),
-                children: RefCell::new(vec![Rc::clone(&leaf)])
-            });
+        let mut registry = Registry::new();
+        let a = Rc::new(Cons(5, RefCell::new(Rc::new(Nil))));
+        let b = Rc::new(Cons(10, RefCell::new(Rc::clone(&a))));
+        registry.register("a", &a);
+        registry.register("b", &b);
+        if let Some(link) = a.tail() {
+            *link.borrow_mut() = Rc::clone(&b);
+        }
 
-            *leaf.parent.borrow_mut() = Rc::downgrade(&branch);
-            println!("Pointed leaf to branch");
-            println!(
-                "branch strong = {}, weak = {}",
-                Rc::strong_count(&branch),
-                Rc::weak_count(&branch),
-            );
+        let weak_b = Rc::downgrade(&b);
+        drop(a);
+        drop(b);
+        for leak in registry.find_leaks() {
+            println!("{}", leak);
+        }
 
-            println!(
-                "leaf strong = {}, weak = {}",
-                Rc::strong_count(&leaf), // eq 2 because leaf have one strong Rc point to Nil and the other one is from branch point to clone of leaf
-                Rc::weak_count(&leaf),
-            );
+        if let Some(b) = weak_b.upgrade() {
+            if let Some(link) = b.tail() {
+                *link.borrow_mut() = Rc::new(Nil);
+            }
+        }
+        println!("alive after breaking b.tail: {:?}", registry.alive());
+    }
 
-            println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
+    #[test]
+    fn test_detect_cycle() {
+        use crate::rc_cycles::Registry;
 
+        let mut registry = Registry::new();
+        let a = Rc::new(Cons(5, RefCell::new(Rc::new(Nil))));
+        let b = Rc::new(Cons(10, RefCell::new(Rc::clone(&a))));
+        registry.register("a", &a);
+        registry.register("b", &b);
 
+        if let Some(link) = a.tail() {
+            *link.borrow_mut() = Rc::clone(&b);
         }
+        // still reachable through the local `a` and `b`
+        assert!(registry.find_leaks().is_empty());
+
+        let weak_b = Rc::downgrade(&b);
+        drop(a);
+        drop(b);
+        let leaks = registry.find_leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].to_string(), "leaked cycle [a, b], make a Weak: b.tail -> a");
+
+        // follow the advice: without the b -> a link both nodes are freed
+        if let Some(link) = weak_b.upgrade().unwrap().tail() {
+            *link.borrow_mut() = Rc::new(Nil);
+        }
+        assert!(registry.alive().is_empty());
+        assert!(registry.find_leaks().is_empty());
+    }
+}
 
-        println!("Out of the scope (a.k.a branch dropped)");
-        println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
-        println!(
-            "leaf strong = {}, weak = {}",
-            Rc::strong_count(&leaf),
-            Rc::weak_count(&leaf),
-        );
+mod tree_weak {
+    use std::rc::Rc;
+    #[cfg(test)]
+    use std::rc::Weak;
+    #[cfg(test)]
+    use std::cell::RefCell;
+    #[cfg(test)]
+    use crate::my_rc::{MyRc, MyWeak};
+    #[cfg(test)]
+    use crate::my_ref_cell::MyRefCell;
+
+    /// NOTE: `rc_tree::Tree<T>` wraps the same Rc children / Weak parent layout in an API
+    pub fn demo() {
+        use crate::rc_tree::{Tree, TreeError};
+
+        let tree = Tree::new("branch");
+        let leaf = tree.root().add_child("leaf");
+        let other = tree.root().add_child("other");
+        other.add_child("twig");
+        print!("{}", tree);
+        println!("leaf depth = {} | path = {:?}", leaf.depth(),
+                 leaf.path_to_root().iter().map(|node| *node.value()).collect::<Vec<_>>());
+
+        leaf.reparent(&other).unwrap();
+        println!("after moving leaf below other ({} nodes):\n{}", tree.len(), tree);
+        let err: Result<(), TreeError> = other.reparent(&leaf);
+        println!("moving other below leaf: {}", err.unwrap_err());
+
+        let removed = tree.find(|v| *v == "other").and_then(|node| node.remove_subtree());
+        println!("removed:\n{}", removed.map(|t| t.to_string()).unwrap_or_default());
+        println!("left, post-order: {:?} | level-order: {:?} | children of root: {}",
+                 tree.post_order().map(|node| *node.value()).collect::<Vec<_>>(),
+                 tree.level_order().map(|node| *node.value()).collect::<Vec<_>>(),
+                 tree.root().children().len());
+        drop(other);
+        // the removed subtree is gone, only our `leaf` handle keeps that node alive
+        println!("leaf strong = {}, parent = {:?}", Rc::strong_count(&leaf), leaf.parent().map(|p| *p.value()));
     }
-}
\ No newline at end of file
+
+    /// the lesson once with std `Rc`/`Weak`/`RefCell` and once with the `my_rc`/`my_ref_cell` ones
+    #[cfg(test)]
+    macro_rules! tree_lesson {
+        ($name:ident, $Rc:ident, $Weak:ident, $RefCell:ident) => {
+            mod $name {
+                use super::*;
+
+                #[derive(Debug)]
+                struct Node {
+                    value: i32,
+                    children: $RefCell<Vec<$Rc<Node>>>,
+                    parent: $RefCell<$Weak<Node>>,
+                }
+
+                #[test]
+                fn test() {
+                    let leaf = $Rc::new(Node {
+                        value: 5,
+                        parent: $RefCell::new($Weak::new()),
+                        children: $RefCell::new(vec![])
+                    });
+
+                    println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
+                    println!(
+                        "leaf strong = {}, weak = {}",
+                        $Rc::strong_count(&leaf),
+                        $Rc::weak_count(&leaf),
+                    );
+
+                    {
+                        let branch = $Rc::new(Node {
+                            value: 10,
+                            parent: $RefCell::new($Weak::new()),
+                            children: $RefCell::new(vec![$Rc::clone(&leaf)])
+                        });
+
+                        *leaf.parent.borrow_mut() = $Rc::downgrade(&branch);
+                        println!("Pointed leaf to branch");
+                        println!(
+                            "branch strong = {}, weak = {}",
+                            $Rc::strong_count(&branch),
+                            $Rc::weak_count(&branch),
+                        );
+
+                        println!(
+                            "leaf strong = {}, weak = {}",
+                            $Rc::strong_count(&leaf), // eq 2 because leaf have one strong Rc point to Nil and the other one is from branch point to clone of leaf
+                            $Rc::weak_count(&leaf),
+                        );
+
+                        println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
+
+                        assert_eq!(($Rc::strong_count(&branch), $Rc::weak_count(&branch)), (1, 1));
+                        assert_eq!(($Rc::strong_count(&leaf), $Rc::weak_count(&leaf)), (2, 0));
+                        assert_eq!(leaf.parent.borrow().upgrade().map(|parent| parent.value), Some(10));
+                    }
+
+                    println!("Out of the scope (a.k.a branch dropped)");
+                    println!("leaf parent = {:?}", leaf.parent.borrow().upgrade());
+                    println!(
+                        "leaf strong = {}, weak = {}",
+                        $Rc::strong_count(&leaf),
+                        $Rc::weak_count(&leaf),
+                    );
+                    assert!(leaf.parent.borrow().upgrade().is_none());
+                    assert_eq!(($Rc::strong_count(&leaf), $Rc::weak_count(&leaf)), (1, 0));
+                }
+            }
+        };
+    }
+
+    #[cfg(test)]
+    tree_lesson!(with_std, Rc, Weak, RefCell);
+    #[cfg(test)]
+    tree_lesson!(with_my_types, MyRc, MyWeak, MyRefCell);
+}
@@ -0,0 +1,615 @@
+/// Synchronization primitives built from `Mutex` + `Condvar`
+///
+/// `Condvar` lets a thread sleep until some state protected by a `Mutex` changes:
+/// - `wait(guard)` releases the lock and sleeps, the lock is taken back before `wait` returns
+/// - `notify_one` / `notify_all` wake up sleeping threads after the state was changed
+///
+/// Wake-ups can be spurious, so the state is always checked again in a loop
+/// (`wait_while` / `wait_timeout_while` do the loop for us).
+///
+/// Every primitive below is just a small piece of state plus one `Condvar`.
+
+use std::cell::UnsafeCell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub fn run() {
+    semaphore_demo();
+    barrier_demo();
+    latch_demo();
+    once_cell_demo();
+}
+
+/// Counting semaphore: at most `permits` holders at the same time.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    cvar: Condvar,
+}
+
+/// A permit of a `Semaphore`, given back when it is dropped.
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore { permits: Mutex::new(permits), cvar: Condvar::new() }
+    }
+
+    pub fn acquire(&self) -> SemaphoreGuard<'_> {
+        let permits = self.permits.lock().unwrap();
+        let mut permits = self.cvar.wait_while(permits, |p| *p == 0).unwrap();
+        *permits -= 1;
+        SemaphoreGuard { semaphore: self }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphoreGuard<'_>> {
+        let mut permits = self.permits.lock().unwrap();
+        if *permits == 0 {
+            return None;
+        }
+        *permits -= 1;
+        Some(SemaphoreGuard { semaphore: self })
+    }
+
+    /// `None` if no permit became available in time.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<SemaphoreGuard<'_>> {
+        let permits = self.permits.lock().unwrap();
+        let (mut permits, _) = self.cvar.wait_timeout_while(permits, timeout, |p| *p == 0).unwrap();
+        if *permits == 0 {
+            return None;
+        }
+        *permits -= 1;
+        Some(SemaphoreGuard { semaphore: self })
+    }
+
+    pub fn available_permits(&self) -> usize {
+        *self.permits.lock().unwrap()
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.cvar.notify_one();
+    }
+}
+
+struct BarrierState {
+    arrived: usize,
+    // bumped every time the barrier opens, so it can be reused for the next round
+    generation: usize,
+}
+
+/// Reusable barrier: `wait` blocks until `parties` threads are waiting, then lets all of them go.
+pub struct Barrier {
+    parties: usize,
+    state: Mutex<BarrierState>,
+    cvar: Condvar,
+}
+
+/// Exactly one thread of every round is the leader (the last one to arrive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+impl Barrier {
+    pub fn new(parties: usize) -> Barrier {
+        assert!(parties > 0, "a barrier needs at least one party");
+
+        Barrier {
+            parties,
+            state: Mutex::new(BarrierState { arrived: 0, generation: 0 }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWaitResult {
+        self.wait_until_open(None).unwrap()
+    }
+
+    /// `None` if the other parties did not arrive in time, this thread is then no longer counted
+    /// as waiting for the current round.
+    pub fn wait_for(&self, timeout: Duration) -> Option<BarrierWaitResult> {
+        self.wait_until_open(Some(timeout))
+    }
+
+    fn wait_until_open(&self, timeout: Option<Duration>) -> Option<BarrierWaitResult> {
+        let mut state = self.state.lock().unwrap();
+        let generation = state.generation;
+
+        state.arrived += 1;
+        if state.arrived == self.parties {
+            state.arrived = 0;
+            state.generation += 1;
+            self.cvar.notify_all();
+            return Some(BarrierWaitResult { leader: true });
+        }
+
+        let still_closed = |s: &mut BarrierState| s.generation == generation;
+        let mut state = match timeout {
+            None => self.cvar.wait_while(state, still_closed).unwrap(),
+            Some(timeout) => self.cvar.wait_timeout_while(state, timeout, still_closed).unwrap().0,
+        };
+
+        if state.generation == generation {
+            state.arrived -= 1;
+            return None;
+        }
+
+        Some(BarrierWaitResult { leader: false })
+    }
+}
+
+/// One-shot gate: waiting threads are released once `count_down` was called `count` times.
+pub struct CountDownLatch {
+    count: Mutex<usize>,
+    cvar: Condvar,
+}
+
+impl CountDownLatch {
+    pub fn new(count: usize) -> CountDownLatch {
+        CountDownLatch { count: Mutex::new(count), cvar: Condvar::new() }
+    }
+
+    pub fn count_down(&self) {
+        let mut count = self.count.lock().unwrap();
+        if *count > 0 {
+            *count -= 1;
+            if *count == 0 {
+                self.cvar.notify_all();
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        *self.count.lock().unwrap()
+    }
+
+    pub fn wait(&self) {
+        let count = self.count.lock().unwrap();
+        let _count = self.cvar.wait_while(count, |c| *c > 0).unwrap();
+    }
+
+    /// `true` if the count reached zero in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let count = self.count.lock().unwrap();
+        let (count, _) = self.cvar.wait_timeout_while(count, timeout, |c| *c > 0).unwrap();
+        *count == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OnceState {
+    Empty,
+    Running,
+    Ready,
+}
+
+/// Thread-safe cell written at most once, e.g. for a value which is expensive to build lazily.
+///
+/// The value lives in an `UnsafeCell` so that `&T` can outlive the lock guard:
+/// - only the thread which moved the state from `Empty` to `Running` writes the value
+/// - the value is never touched again after the state is `Ready`, so shared refs are fine
+pub struct OnceCell<T> {
+    state: Mutex<OnceState>,
+    cvar: Condvar,
+    value: UnsafeCell<Option<T>>,
+}
+
+// `&OnceCell<T>` hands out `&T` to many threads (T: Sync) and a value set on one thread can
+// be dropped on another (T: Send)
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+/// Puts the cell back to `Empty` if the initializer panics, so that a waiter can try again.
+struct ResetOnPanic<'a, T> {
+    cell: &'a OnceCell<T>,
+}
+
+impl<'a, T> Drop for ResetOnPanic<'a, T> {
+    fn drop(&mut self) {
+        *self.cell.state.lock().unwrap() = OnceState::Empty;
+        self.cell.cvar.notify_all();
+    }
+}
+
+impl<T> OnceCell<T> {
+    pub fn new() -> OnceCell<T> {
+        OnceCell { state: Mutex::new(OnceState::Empty), cvar: Condvar::new(), value: UnsafeCell::new(None) }
+    }
+
+    /// Caller must have seen the state `Ready`.
+    fn value_ref(&self) -> &T {
+        unsafe { (*self.value.get()).as_ref().unwrap() }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if *self.state.lock().unwrap() == OnceState::Ready {
+            Some(self.value_ref())
+        } else {
+            None
+        }
+    }
+
+    /// Only one caller runs `init`, the others block until the value is there.
+    pub fn get_or_init<F>(&self, init: F) -> &T
+        where F: FnOnce() -> T {
+        {
+            let state = self.state.lock().unwrap();
+            let mut state = self.cvar.wait_while(state, |s| *s == OnceState::Running).unwrap();
+            if *state == OnceState::Ready {
+                return self.value_ref();
+            }
+            *state = OnceState::Running;
+        }
+
+        let reset = ResetOnPanic { cell: self };
+        let value = init();
+        std::mem::forget(reset);
+
+        self.store(value);
+        self.value_ref()
+    }
+
+    /// `Err(value)` if the cell already holds (or is computing) a value.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if *state != OnceState::Empty {
+                return Err(value);
+            }
+            *state = OnceState::Running;
+        }
+
+        self.store(value);
+        Ok(())
+    }
+
+    /// Block until another thread sets the value.
+    pub fn wait(&self) -> &T {
+        let state = self.state.lock().unwrap();
+        let _state = self.cvar.wait_while(state, |s| *s != OnceState::Ready).unwrap();
+        self.value_ref()
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<&T> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self.cvar.wait_timeout_while(state, timeout, |s| *s != OnceState::Ready).unwrap();
+        if *state == OnceState::Ready {
+            Some(self.value_ref())
+        } else {
+            None
+        }
+    }
+
+    /// Caller must have moved the state to `Running`.
+    fn store(&self, value: T) {
+        unsafe {
+            *self.value.get() = Some(value);
+        }
+        *self.state.lock().unwrap() = OnceState::Ready;
+        self.cvar.notify_all();
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> OnceCell<T> {
+        OnceCell::new()
+    }
+}
+
+fn semaphore_demo() {
+    // at most 2 of the 5 workers download at the same time
+    let semaphore = Arc::new(Semaphore::new(2));
+    let mut handles = vec![];
+
+    for i in 0..5 {
+        let semaphore = Arc::clone(&semaphore);
+        handles.push(thread::spawn(move || {
+            let _permit = semaphore.acquire();
+            println!("worker {} downloading ({} permits left)", i, semaphore.available_permits());
+            thread::sleep(Duration::from_millis(50));
+        }));
+    }
+
+    // the impatient ones give up instead of queueing
+    thread::sleep(Duration::from_millis(10));
+    println!("try_acquire while busy: {}", semaphore.try_acquire().is_some()); // expect false
+    println!("acquire_timeout(10ms) while busy: {}", semaphore.acquire_timeout(Duration::from_millis(10)).is_some()); // expect false
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn barrier_demo() {
+    let barrier = Arc::new(Barrier::new(3));
+    let mut handles = vec![];
+
+    for i in 0..3 {
+        let barrier = Arc::clone(&barrier);
+        handles.push(thread::spawn(move || {
+            for round in 0..2 {
+                println!("worker {} finished round {}", i, round);
+                if barrier.wait().is_leader() {
+                    println!("--- everyone finished round {} ---", round);
+                }
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // the other party never shows up
+    let lonely = Barrier::new(2);
+    println!("wait_for(10ms) alone: {:?}", lonely.wait_for(Duration::from_millis(10))); // expect None
+}
+
+fn latch_demo() {
+    let latch = Arc::new(CountDownLatch::new(3));
+
+    for i in 0..3 {
+        let latch = Arc::clone(&latch);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10 * i));
+            println!("service {} is up", i);
+            latch.count_down();
+        });
+    }
+
+    if !latch.wait_timeout(Duration::from_millis(5)) {
+        println!("still waiting for {} services", latch.count());
+    }
+
+    latch.wait();
+    println!("all services are up, start serving");
+}
+
+fn once_cell_demo() {
+    let config: Arc<OnceCell<String>> = Arc::new(OnceCell::new());
+    let mut handles = vec![];
+
+    for i in 0..3 {
+        let config = Arc::clone(&config);
+        handles.push(thread::spawn(move || {
+            let value = config.get_or_init(|| {
+                println!("thread {} loads the config", i);
+                thread::sleep(Duration::from_millis(50));
+                String::from("debug=true")
+            });
+            println!("thread {} sees config `{}`", i, value);
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // one thread publishes a value, the others wait for it
+    let port: Arc<OnceCell<u16>> = Arc::new(OnceCell::new());
+    let listener = {
+        let port = Arc::clone(&port);
+        thread::spawn(move || println!("connecting to port {}", port.wait()))
+    };
+
+    println!("port before bind: {:?}", port.wait_timeout(Duration::from_millis(5))); // expect None
+    port.set(8080).unwrap();
+    println!("port after bind: {:?}", port.get()); // expect Some(8080)
+
+    listener.join().unwrap();
+}
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn test_semaphore_limits_concurrency() {
+    let semaphore = Arc::new(Semaphore::new(3));
+    let inside = Arc::new(AtomicUsize::new(0));
+    let max_inside = Arc::new(AtomicUsize::new(0));
+    let mut handles = vec![];
+
+    for _ in 0..10 {
+        let (semaphore, inside, max_inside) = (Arc::clone(&semaphore), Arc::clone(&inside), Arc::clone(&max_inside));
+        handles.push(thread::spawn(move || {
+            let _permit = semaphore.acquire();
+            let now = inside.fetch_add(1, Ordering::SeqCst) + 1;
+            max_inside.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            inside.fetch_sub(1, Ordering::SeqCst);
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(max_inside.load(Ordering::SeqCst) <= 3);
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+#[test]
+fn test_semaphore_try_and_timeout() {
+    let semaphore = Semaphore::new(1);
+
+    let permit = semaphore.try_acquire();
+    assert!(permit.is_some());
+    assert!(semaphore.try_acquire().is_none());
+    assert!(semaphore.acquire_timeout(Duration::from_millis(20)).is_none());
+
+    drop(permit);
+    assert!(semaphore.acquire_timeout(Duration::from_millis(20)).is_some());
+}
+
+#[test]
+fn test_semaphore_timeout_gets_released_permit() {
+    let semaphore = Arc::new(Semaphore::new(1));
+    let permit = semaphore.acquire();
+
+    let waiter = {
+        let semaphore = Arc::clone(&semaphore);
+        thread::spawn(move || semaphore.acquire_timeout(Duration::from_secs(5)).is_some())
+    };
+
+    thread::sleep(Duration::from_millis(20));
+    drop(permit);
+
+    assert!(waiter.join().unwrap());
+}
+
+#[test]
+fn test_barrier_is_reusable_with_one_leader_per_round() {
+    let barrier = Arc::new(Barrier::new(4));
+    let leaders = Arc::new(AtomicUsize::new(0));
+    let progress = Arc::new(AtomicUsize::new(0));
+    let mut handles = vec![];
+
+    for _ in 0..4 {
+        let (barrier, leaders, progress) = (Arc::clone(&barrier), Arc::clone(&leaders), Arc::clone(&progress));
+        handles.push(thread::spawn(move || {
+            for round in 0..5 {
+                progress.fetch_add(1, Ordering::SeqCst);
+                if barrier.wait().is_leader() {
+                    leaders.fetch_add(1, Ordering::SeqCst);
+                }
+                // nobody can start the next round before everyone finished this one
+                assert!(progress.load(Ordering::SeqCst) >= (round + 1) * 4);
+                barrier.wait();
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(leaders.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn test_barrier_timeout_withdraws_waiter() {
+    let barrier = Arc::new(Barrier::new(2));
+
+    // nobody else arrives
+    assert_eq!(barrier.wait_for(Duration::from_millis(20)), None);
+
+    // the timed out thread is not counted any more, so the next round still needs two parties
+    let other = {
+        let barrier = Arc::clone(&barrier);
+        thread::spawn(move || barrier.wait().is_leader())
+    };
+
+    let me = barrier.wait_for(Duration::from_secs(5)).unwrap().is_leader();
+    let other = other.join().unwrap();
+    assert!(me != other);
+}
+
+#[test]
+fn test_latch_releases_all_waiters() {
+    let latch = Arc::new(CountDownLatch::new(3));
+    let released = Arc::new(AtomicUsize::new(0));
+    let mut waiters = vec![];
+
+    for _ in 0..4 {
+        let (latch, released) = (Arc::clone(&latch), Arc::clone(&released));
+        waiters.push(thread::spawn(move || {
+            latch.wait();
+            released.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(released.load(Ordering::SeqCst), 0);
+        latch.count_down();
+    }
+
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+
+    assert_eq!(released.load(Ordering::SeqCst), 4);
+    assert_eq!(latch.count(), 0);
+
+    // extra count downs keep the latch open
+    latch.count_down();
+    assert!(latch.wait_timeout(Duration::from_millis(1)));
+}
+
+#[test]
+fn test_latch_timeout() {
+    let latch = CountDownLatch::new(1);
+    assert!(!latch.wait_timeout(Duration::from_millis(20)));
+    latch.count_down();
+    assert!(latch.wait_timeout(Duration::from_millis(20)));
+}
+
+#[test]
+fn test_once_cell_runs_init_once() {
+    let cell: Arc<OnceCell<usize>> = Arc::new(OnceCell::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut handles = vec![];
+
+    for i in 0..8 {
+        let (cell, calls) = (Arc::clone(&cell), Arc::clone(&calls));
+        handles.push(thread::spawn(move || {
+            *cell.get_or_init(|| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                i
+            })
+        }));
+    }
+
+    let values: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(values.iter().all(|v| *v == values[0]));
+    assert_eq!(cell.get(), Some(&values[0]));
+}
+
+#[test]
+fn test_once_cell_set_and_wait() {
+    let cell: Arc<OnceCell<String>> = Arc::new(OnceCell::new());
+
+    assert_eq!(cell.get(), None);
+    assert_eq!(cell.wait_timeout(Duration::from_millis(10)), None);
+
+    let waiter = {
+        let cell = Arc::clone(&cell);
+        thread::spawn(move || cell.wait().clone())
+    };
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(cell.set(String::from("ready")), Ok(()));
+    assert_eq!(cell.set(String::from("again")), Err(String::from("again")));
+
+    assert_eq!(waiter.join().unwrap(), "ready");
+    assert_eq!(cell.get_or_init(|| String::from("unused")), "ready");
+}
+
+#[test]
+fn test_once_cell_init_panic_lets_next_caller_retry() {
+    let cell: Arc<OnceCell<i32>> = Arc::new(OnceCell::new());
+
+    let failed = {
+        let cell = Arc::clone(&cell);
+        thread::spawn(move || {
+            cell.get_or_init(|| panic!("init failed"));
+        }).join()
+    };
+
+    assert!(failed.is_err());
+    assert_eq!(cell.get(), None);
+    assert_eq!(*cell.get_or_init(|| 7), 7);
+}
@@ -0,0 +1,247 @@
+/// Drop-order and leak instrumentation
+///
+/// `smart_pointer::CustomPointer` prints a line when it is dropped, which is fine to read once but
+/// can't be asserted on. `Tracked<T>` wraps any value and writes what happens to it into a
+/// journal instead:
+///
+/// - `Created`  `Tracked::new`
+/// - `Cloned`   `clone()`, the copy gets the label `<label>.clone`
+/// - `IntoRc`   `into_rc()`, the value moves into an `Rc`; nothing is dropped, only the owner changes
+/// - `Dropped`  `Drop::drop`, in the exact order Rust runs it
+///
+/// The journal is thread-local, so tests running in parallel don't see each other's events, and
+/// `record` gives one closure its own journal: afterwards `drop_order()` and `leaked()` can be
+/// compared with what the ownership rules predict.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Created(String),
+    Cloned { from: String, to: String },
+    IntoRc(String),
+    Dropped(String),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Created(label) => write!(f, "created {}", label),
+            Event::Cloned { from, to } => write!(f, "cloned {} -> {}", from, to),
+            Event::IntoRc(label) => write!(f, "moved {} into Rc", label),
+            Event::Dropped(label) => write!(f, "dropped {}", label),
+        }
+    }
+}
+
+thread_local! {
+    static JOURNAL: RefCell<Vec<Event>> = const { RefCell::new(Vec::new()) };
+}
+
+fn log(event: Event) {
+    // fails only while the thread shuts down, nobody can read the journal by then
+    let _ = JOURNAL.try_with(|journal| journal.borrow_mut().push(event));
+}
+
+pub struct Tracked<T> {
+    label: String,
+    value: T,
+}
+
+impl<T> Tracked<T> {
+    pub fn new(label: &str, value: T) -> Tracked<T> {
+        log(Event::Created(label.to_string()));
+        Tracked { label: label.to_string(), value }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Moves the value into an `Rc`. It is dropped once, when the last `Rc` goes away.
+    pub fn into_rc(self) -> Rc<Tracked<T>> {
+        log(Event::IntoRc(self.label.clone()));
+        Rc::new(self)
+    }
+}
+
+impl<T: Clone> Clone for Tracked<T> {
+    fn clone(&self) -> Tracked<T> {
+        let label = format!("{}.clone", self.label);
+        log(Event::Cloned { from: self.label.clone(), to: label.clone() });
+        Tracked { label, value: self.value.clone() }
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        log(Event::Dropped(self.label.clone()));
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Tracked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.label, self.value)
+    }
+}
+
+/// Events of one `record` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    pub events: Vec<Event>,
+}
+
+impl Journal {
+    /// Labels in the order they were dropped.
+    pub fn drop_order(&self) -> Vec<&str> {
+        self.events.iter()
+            .filter_map(|event| match event {
+                Event::Dropped(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Labels created (or cloned) during the recording and never dropped.
+    pub fn leaked(&self) -> Vec<&str> {
+        let mut alive: Vec<&str> = vec![];
+        for event in &self.events {
+            match event {
+                Event::Created(label) | Event::Cloned { to: label, .. } => alive.push(label),
+                Event::Dropped(label) => {
+                    if let Some(i) = alive.iter().position(|alive| alive == label) {
+                        alive.remove(i);
+                    }
+                }
+                Event::IntoRc(_) => {}
+            }
+        }
+        alive
+    }
+
+    #[cfg(test)]
+    pub fn assert_no_leaks(&self) {
+        assert!(self.leaked().is_empty(), "leaked: {:?}\n{}", self.leaked(), self);
+    }
+}
+
+impl fmt::Display for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` with an empty journal and returns what it logged. Values created in `f` and dropped
+/// by it (or at the end of it) are all in there; calls can be nested.
+pub fn record<R, F: FnOnce() -> R>(f: F) -> (R, Journal) {
+    let outer = JOURNAL.with(|journal| journal.replace(Vec::new()));
+    let result = f();
+    let events = JOURNAL.with(|journal| journal.replace(outer));
+    // the outer recording saw all of it happen too
+    JOURNAL.with(|journal| journal.borrow_mut().extend(events.iter().cloned()));
+    (result, Journal { events })
+}
+
+#[test]
+fn test_locals_drop_in_reverse_order() {
+    let ((), journal) = record(|| {
+        let _a = Tracked::new("a", 1);
+        let _b = Tracked::new("b", 2);
+        {
+            let _c = Tracked::new("c", 3);
+        }
+        let _d = Tracked::new("d", 4);
+    });
+    assert_eq!(journal.drop_order(), vec!["c", "d", "b", "a"]);
+    journal.assert_no_leaks();
+}
+
+#[test]
+fn test_fields_and_vec_drop_in_order() {
+    struct Pair(Tracked<i32>, Tracked<i32>);
+
+    let ((), journal) = record(|| {
+        let pair = Pair(Tracked::new("first", 1), Tracked::new("second", 2));
+        assert_eq!(*pair.0 + *pair.1, 3);
+        let _vec: Vec<_> = (0..2).map(|i| Tracked::new(&format!("v{}", i), i)).collect();
+    });
+    // locals in reverse, but fields and elements front to back
+    assert_eq!(journal.drop_order(), vec!["v0", "v1", "first", "second"]);
+}
+
+#[test]
+fn test_clone_and_move() {
+    fn consume(value: Tracked<String>) -> usize {
+        value.len()
+    }
+
+    let (len, journal) = record(|| {
+        let mut a = Tracked::new("a", String::from("abc"));
+        a.push('d');
+        let b = a.clone();
+        let len = consume(a); // `a` is dropped at the end of `consume`, not here
+        log(Event::Created("marker".to_string()));
+        drop(b);
+        log(Event::Dropped("marker".to_string()));
+        len
+    });
+    assert_eq!(len, 4);
+    assert_eq!(journal.to_string(),
+               "created a\ncloned a -> a.clone\ndropped a\ncreated marker\ndropped a.clone\ndropped marker\n");
+}
+
+#[test]
+fn test_rc_drops_once_with_the_last_owner() {
+    let ((), journal) = record(|| {
+        let shared = Tracked::new("shared", vec![1, 2]).into_rc();
+        let other = Rc::clone(&shared);
+        assert_eq!(shared.label(), "shared");
+        drop(shared);
+        let (_, inner) = record(|| drop(other));
+        assert_eq!(inner.drop_order(), vec!["shared"]);
+    });
+    assert_eq!(journal.events, vec![
+        Event::Created("shared".to_string()),
+        Event::IntoRc("shared".to_string()),
+        Event::Dropped("shared".to_string()),
+    ]);
+}
+
+#[test]
+fn test_leak_is_reported() {
+    use std::cell::RefCell;
+
+    struct Node {
+        _tracked: Tracked<()>,
+        next: RefCell<Option<Rc<Node>>>,
+    }
+
+    let ((), journal) = record(|| {
+        let a = Rc::new(Node { _tracked: Tracked::new("a", ()), next: RefCell::new(None) });
+        let b = Rc::new(Node { _tracked: Tracked::new("b", ()), next: RefCell::new(Some(Rc::clone(&a))) });
+        let _c = Tracked::new("c", ());
+        *a.next.borrow_mut() = Some(Rc::clone(&b));
+    });
+    assert_eq!(journal.drop_order(), vec!["c"]);
+    assert_eq!(journal.leaked(), vec!["a", "b"]);
+}
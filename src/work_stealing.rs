@@ -0,0 +1,532 @@
+/// Work-stealing scheduler
+///
+/// `concurrent.rs` spawns one OS thread per piece of work. That is fine for a handful of jobs,
+/// but a recursive algorithm quickly creates thousands of threads and spends most of its time
+/// creating and switching between them.
+///
+/// Here a fixed number of workers share the work instead. Every worker owns a deque:
+/// - new tasks are pushed to the back of the deque of the worker which created them
+/// - a worker pops from the back of its own deque (LIFO, the freshest task is hot in cache)
+/// - an idle worker steals from the front of another deque (FIFO, the oldest task is usually the biggest)
+///
+/// `join(a, b)` pushes `b` so that someone else can steal it, runs `a` itself, then helps with
+/// other tasks until `b` is finished.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub fn run() {
+    let scheduler = Scheduler::new(4);
+
+    let n = 32;
+
+    let now = Instant::now();
+    let expected = fib(n);
+    println!("fib({}) sequential     = {} in {:?}", n, expected, now.elapsed());
+
+    let now = Instant::now();
+    let result = fib_threads(n);
+    println!("fib({}) thread::spawn  = {} in {:?}", n, result, now.elapsed());
+    assert_eq!(expected, result);
+
+    let now = Instant::now();
+    let result = scheduler.install(move || fib_join(n));
+    println!("fib({}) work stealing  = {} in {:?}", n, result, now.elapsed());
+    assert_eq!(expected, result);
+
+    let data: Vec<i64> = (0..1_000_000).map(|i| (i * 7919 + 13) % 1_000_003).collect();
+
+    let now = Instant::now();
+    let mut expected = data.clone();
+    expected.sort();
+    println!("sort sequential          in {:?}", now.elapsed());
+
+    let now = Instant::now();
+    let result = quicksort_threads(data.clone());
+    println!("quicksort thread::spawn  in {:?}", now.elapsed());
+    assert_eq!(expected, result);
+
+    let now = Instant::now();
+    let result = scheduler.install(move || {
+        let mut data = data;
+        quicksort_join(&mut data);
+        data
+    });
+    println!("quicksort work stealing  in {:?}", now.elapsed());
+    assert_eq!(expected, result);
+
+    // a task spawning subtasks, the other workers steal them while this one waits for the results
+    let total = scheduler.install(|| {
+        let (tx, rx) = mpsc::channel();
+        for part in 0..10u64 {
+            let tx = tx.clone();
+            spawn(move || tx.send((part * 10 + 1..=part * 10 + 10).sum::<u64>()).unwrap());
+        }
+        drop(tx);
+        rx.iter().sum::<u64>()
+    });
+    println!("sum of 1..=100 from subtasks = {}", total);
+
+    println!("tasks stolen: {}", scheduler.steals());
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Shared {
+    deques: Vec<Mutex<VecDeque<Job>>>,
+    // tasks coming from outside of the pool, any worker can take them
+    injector: Mutex<VecDeque<Job>>,
+    idle: Mutex<()>,
+    wakeup: Condvar,
+    shutdown: AtomicBool,
+    steals: AtomicUsize,
+}
+
+impl Shared {
+    fn notify(&self) {
+        let _guard = self.idle.lock().unwrap();
+        self.wakeup.notify_one();
+    }
+}
+
+/// Handle to the worker running on the current thread.
+#[derive(Clone)]
+struct Worker {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+thread_local! {
+    static WORKER: RefCell<Option<Worker>> = const { RefCell::new(None) };
+}
+
+fn current_worker() -> Option<Worker> {
+    WORKER.with(|w| w.borrow().clone())
+}
+
+impl Worker {
+    fn push(&self, job: Job) {
+        self.shared.deques[self.index].lock().unwrap().push_back(job);
+        self.shared.notify();
+    }
+
+    /// Own deque first, then the injector, then steal from the other workers.
+    fn find_job(&self) -> Option<Job> {
+        if let Some(job) = self.shared.deques[self.index].lock().unwrap().pop_back() {
+            return Some(job);
+        }
+
+        if let Some(job) = self.shared.injector.lock().unwrap().pop_front() {
+            return Some(job);
+        }
+
+        let workers = self.shared.deques.len();
+        for offset in 1..workers {
+            let victim = (self.index + offset) % workers;
+            if let Some(job) = self.shared.deques[victim].lock().unwrap().pop_front() {
+                self.shared.steals.fetch_add(1, Ordering::Relaxed);
+                return Some(job);
+            }
+        }
+
+        None
+    }
+
+    /// Run one job if there is any, a panicking job must not take the worker down with it.
+    fn run_one(&self) -> bool {
+        match self.find_job() {
+            Some(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn main_loop(self) {
+        WORKER.with(|w| *w.borrow_mut() = Some(self.clone()));
+
+        loop {
+            if self.run_one() {
+                continue;
+            }
+
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            // the timeout covers a notification sent between `find_job` and `wait_timeout`
+            let guard = self.shared.idle.lock().unwrap();
+            let _ = self.shared.wakeup.wait_timeout(guard, Duration::from_millis(1)).unwrap();
+        }
+
+        WORKER.with(|w| *w.borrow_mut() = None);
+    }
+}
+
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn new(workers: usize) -> Scheduler {
+        assert!(workers > 0, "a scheduler needs at least one worker");
+
+        let shared = Arc::new(Shared {
+            deques: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            injector: Mutex::new(VecDeque::new()),
+            idle: Mutex::new(()),
+            wakeup: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            steals: AtomicUsize::new(0),
+        });
+
+        let handles = (0..workers)
+            .map(|index| {
+                let worker = Worker { shared: Arc::clone(&shared), index };
+                thread::spawn(move || worker.main_loop())
+            })
+            .collect();
+
+        Scheduler { shared, handles }
+    }
+
+    /// Queue a task from outside of the pool.
+    pub fn spawn<F>(&self, f: F)
+        where F: FnOnce() + Send + 'static {
+        self.shared.injector.lock().unwrap().push_back(Box::new(f));
+        self.shared.notify();
+    }
+
+    /// Run `f` on the pool and block until it returns, a panic inside `f` is re-raised here.
+    ///
+    /// From a task already running on this scheduler `f` simply runs in place: blocking that
+    /// worker until another one picks `f` up would deadlock a scheduler with a single worker.
+    pub fn install<F, R>(&self, f: F) -> R
+        where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+        if current_worker().is_some_and(|worker| Arc::ptr_eq(&worker.shared, &self.shared)) {
+            return f();
+        }
+
+        let (tx, rx) = mpsc::channel();
+
+        self.spawn(move || {
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+
+        match rx.recv().unwrap() {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Number of tasks taken from the deque of another worker so far.
+    pub fn steals(&self) -> usize {
+        self.shared.steals.load(Ordering::Relaxed)
+    }
+}
+
+/// Workers finish every queued task before the scheduler is gone.
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _guard = self.shared.idle.lock().unwrap();
+            self.shared.wakeup.notify_all();
+        }
+
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// Spawn a subtask from inside a task, it goes to the deque of the current worker.
+pub fn spawn<F>(f: F)
+    where F: FnOnce() + Send + 'static {
+    match current_worker() {
+        Some(worker) => worker.push(Box::new(f)),
+        None => panic!("work_stealing::spawn must be called from a task running on a Scheduler"),
+    }
+}
+
+/// Run `a` and `b` potentially in parallel and return both results.
+///
+/// `join` does not return before `b` has finished, even when `a` panics, so like the closures
+/// of `thread::scope` both may borrow from the caller's stack. Outside of a scheduler both
+/// closures simply run one after the other.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+    where A: FnOnce() -> RA, B: FnOnce() -> RB + Send, RB: Send {
+    let worker = match current_worker() {
+        Some(worker) => worker,
+        None => return (a(), b()),
+    };
+
+    let slot = Arc::new(Mutex::new(None));
+    let slot_b = Arc::clone(&slot);
+
+    let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+        *slot_b.lock().unwrap() = Some(panic::catch_unwind(AssertUnwindSafe(b)));
+    });
+    // SAFETY: the job only outlives what `b` borrows if `join` returns before the job ran, and
+    // the loop below only returns once the job has filled `slot`. A panic in `a` is caught
+    // and re-raised after that too.
+    let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + '_>, Job>(job) };
+    worker.push(job);
+
+    let ra = panic::catch_unwind(AssertUnwindSafe(a));
+
+    loop {
+        if let Some(rb) = slot.lock().unwrap().take() {
+            match (ra, rb) {
+                (Ok(ra), Ok(rb)) => return (ra, rb),
+                (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+            }
+        }
+
+        // usually `b` is still on top of our own deque, otherwise help the others while waiting
+        if !worker.run_one() {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Below this size splitting costs more than it gains.
+const FIB_CUTOFF: u64 = 20;
+const SORT_CUTOFF: usize = 10_000;
+
+fn fib(n: u64) -> u64 {
+    if n < 2 {
+        n
+    } else {
+        fib(n - 1) + fib(n - 2)
+    }
+}
+
+/// The `concurrent.rs` way: a new OS thread for every split.
+fn fib_threads(n: u64) -> u64 {
+    if n < FIB_CUTOFF {
+        return fib(n);
+    }
+
+    let handle = thread::spawn(move || fib_threads(n - 1));
+    let b = fib_threads(n - 2);
+
+    handle.join().unwrap() + b
+}
+
+fn fib_join(n: u64) -> u64 {
+    if n < FIB_CUTOFF {
+        return fib(n);
+    }
+
+    let (a, b) = join(move || fib_join(n - 1), move || fib_join(n - 2));
+
+    a + b
+}
+
+/// Split `v` around its middle element into less / equal / greater parts.
+fn partition(v: Vec<i64>) -> (Vec<i64>, Vec<i64>, Vec<i64>) {
+    let pivot = v[v.len() / 2];
+    let mut less = vec![];
+    let mut equal = vec![];
+    let mut greater = vec![];
+
+    for x in v {
+        if x < pivot {
+            less.push(x);
+        } else if x > pivot {
+            greater.push(x);
+        } else {
+            equal.push(x);
+        }
+    }
+
+    (less, equal, greater)
+}
+
+fn concat(mut less: Vec<i64>, equal: Vec<i64>, greater: Vec<i64>) -> Vec<i64> {
+    less.extend(equal);
+    less.extend(greater);
+    less
+}
+
+fn quicksort_threads(mut v: Vec<i64>) -> Vec<i64> {
+    if v.len() <= SORT_CUTOFF {
+        v.sort();
+        return v;
+    }
+
+    let (less, equal, greater) = partition(v);
+    let handle = thread::spawn(move || quicksort_threads(less));
+    let greater = quicksort_threads(greater);
+
+    concat(handle.join().unwrap(), equal, greater)
+}
+
+/// Split `v` around its middle element in place: returns `(lt, gt)` with `v[..lt]` less than
+/// the pivot, `v[lt..gt]` equal to it and `v[gt..]` greater.
+fn partition_in_place(v: &mut [i64]) -> (usize, usize) {
+    let pivot = v[v.len() / 2];
+    let (mut lt, mut i, mut gt) = (0, 0, v.len());
+
+    while i < gt {
+        if v[i] < pivot {
+            v.swap(lt, i);
+            lt += 1;
+            i += 1;
+        } else if v[i] > pivot {
+            gt -= 1;
+            v.swap(i, gt);
+        } else {
+            i += 1;
+        }
+    }
+
+    (lt, gt)
+}
+
+/// `join` can borrow, so both halves are sorted where they are, no copies.
+fn quicksort_join(v: &mut [i64]) {
+    if v.len() <= SORT_CUTOFF {
+        v.sort();
+        return;
+    }
+
+    let (lt, gt) = partition_in_place(v);
+    let (less, rest) = v.split_at_mut(lt);
+    let greater = &mut rest[gt - lt..];
+    join(|| quicksort_join(less), || quicksort_join(greater));
+}
+
+#[test]
+fn test_join_outside_scheduler_runs_sequentially() {
+    let (a, b) = join(|| 1 + 1, || "two");
+    assert_eq!((a, b), (2, "two"));
+}
+
+#[test]
+fn test_fib_join_matches_sequential() {
+    let scheduler = Scheduler::new(4);
+
+    for n in [0, 1, 2, 10, 25].iter().cloned() {
+        assert_eq!(fib(n), scheduler.install(move || fib_join(n)));
+    }
+}
+
+#[test]
+fn test_quicksort_join_matches_sort() {
+    let scheduler = Scheduler::new(3);
+    let data: Vec<i64> = (0..200_000).map(|i| (i * 7919 + 13) % 1009).collect();
+
+    let mut expected = data.clone();
+    expected.sort();
+
+    let sorted = scheduler.install(move || {
+        let mut data = data;
+        quicksort_join(&mut data);
+        data
+    });
+    assert_eq!(expected, sorted);
+}
+
+#[test]
+fn test_join_borrows_from_the_stack() {
+    let scheduler = Scheduler::new(2);
+
+    let total = scheduler.install(|| {
+        let data: Vec<u64> = (1..=1000).collect();
+        let mut counts = [0; 2];
+        let (left, right) = data.split_at(500);
+        let (first, second) = counts.split_at_mut(1);
+
+        let (a, b) = join(
+            || { first[0] = left.len(); left.iter().sum::<u64>() },
+            || { second[0] = right.len(); right.iter().sum::<u64>() },
+        );
+        assert_eq!(counts, [500, 500]);
+        a + b
+    });
+
+    assert_eq!(total, 500_500);
+}
+
+#[test]
+fn test_install_from_a_task_runs_in_place() {
+    let scheduler = Arc::new(Scheduler::new(1));
+    let inner = Arc::clone(&scheduler);
+
+    // with one worker, queueing the inner closure and waiting for it would never finish
+    assert_eq!(scheduler.install(move || inner.install(|| 6) * 7), 42);
+}
+
+#[test]
+fn test_idle_worker_steals_subtasks() {
+    let scheduler = Scheduler::new(2);
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let counter = Arc::clone(&done);
+    scheduler.install(move || {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..10 {
+            let tx = tx.clone();
+            let counter = Arc::clone(&counter);
+            spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tx.send(()).unwrap();
+            });
+        }
+
+        // this worker blocks without helping, only the other worker can run the subtasks
+        for _ in 0..10 {
+            rx.recv().unwrap();
+        }
+    });
+
+    assert_eq!(done.load(Ordering::SeqCst), 10);
+    assert!(scheduler.steals() >= 10);
+}
+
+#[test]
+fn test_spawned_tasks_finish_before_drop() {
+    let done = Arc::new(AtomicUsize::new(0));
+
+    {
+        let scheduler = Scheduler::new(2);
+        for _ in 0..100 {
+            let done = Arc::clone(&done);
+            scheduler.spawn(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    assert_eq!(done.load(Ordering::SeqCst), 100);
+}
+
+#[test]
+#[should_panic(expected = "boom")]
+fn test_panic_in_joined_task_reaches_caller() {
+    let scheduler = Scheduler::new(2);
+
+    scheduler.install(|| {
+        let (_, b): ((), ()) = join(|| (), || panic!("boom"));
+        b
+    });
+}
+
+#[test]
+fn test_worker_survives_panicking_task() {
+    let scheduler = Scheduler::new(1);
+
+    scheduler.spawn(|| panic!("task failed"));
+
+    assert_eq!(scheduler.install(|| 42), 42);
+}
@@ -0,0 +1,504 @@
+/// Workout planner
+///
+/// `closure::generate_workout` picks push-ups and sit-ups or a run from `intensity`, and takes a
+/// break when a hard-coded `random_number` is 3. This plans real days:
+///
+/// - `Rng` is a seeded xorshift64*, the same seed always gives the same plan
+/// - `Rules` come from a small config file (`fixtures/workout.txt` by default): exercises with
+///   their amount at intensity 10, the start intensity, the step, rest chance and longest streak
+/// - a training day alternates strength (two exercises, like push-ups and sit-ups) and cardio
+///   (one, like the run); a rest day comes by chance or at the latest after `max_streak` days
+/// - every planned training day is `step` harder than the one before
+/// - the `History` file keeps each day as planned / done / skipped; the next plan continues
+///   from it: `step` up after a day done, down after a day skipped (which also ends the
+///   streak), the strength/cardio order carries on, and days still planned are planned again
+///
+/// ```text
+/// cargo run -- workout plan [--days 7] [--seed 42] [--config <file>] [--history <file>]
+/// cargo run -- workout done|skip [--history <file>]     marks the next planned day
+/// ```
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::parse_error::ParseError;
+
+/// xorshift64*, small and good enough to shuffle exercises.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be 0, xorshift would stay there
+        Rng { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform enough in `0..n` for small n.
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_u64() % n as u64) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Rest,
+    Strength,
+    Cardio,
+}
+
+impl Kind {
+    fn parse(word: &str) -> Option<Kind> {
+        match word {
+            "rest" => Some(Kind::Rest),
+            "strength" => Some(Kind::Strength),
+            "cardio" => Some(Kind::Cardio),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Kind::Rest => "rest",
+            Kind::Strength => "strength",
+            Kind::Cardio => "cardio",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exercise {
+    pub name: String,
+    pub kind: Kind,
+    pub unit: String,
+    /// Amount at intensity 10, it scales linearly.
+    pub per_ten: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    pub exercises: Vec<Exercise>,
+    pub start: u32,
+    pub step: u32,
+    pub rest_chance: u32,
+    pub max_streak: u32,
+}
+
+impl Rules {
+    /// One entry per line, `#` starts a comment, see `fixtures/workout.txt`.
+    pub fn parse(text: &str) -> Result<Rules, ParseError> {
+        let mut rules = Rules { exercises: vec![], start: 10, step: 2, rest_chance: 15, max_streak: 4 };
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ParseError { line: i + 1, message };
+            let number = |word: Option<&str>, what: &str| -> Result<u32, ParseError> {
+                let word = word.ok_or_else(|| error(format!("missing {}", what)))?;
+                word.parse().map_err(|_| error(format!("{} is not a number: {:?}", what, word)))
+            };
+
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => {}
+                Some("exercise") => {
+                    let name = words.next().ok_or_else(|| error("missing name".to_string()))?;
+                    let kind = match words.next().map(|word| (word, Kind::parse(word))) {
+                        Some((_, Some(kind))) if kind != Kind::Rest => kind,
+                        Some((word, _)) => return Err(error(format!("kind must be strength or cardio, not {:?}", word))),
+                        None => return Err(error("missing kind".to_string())),
+                    };
+                    let unit = words.next().ok_or_else(|| error("missing unit".to_string()))?;
+                    let per_ten = number(words.next(), "amount")?;
+                    rules.exercises.push(Exercise { name: name.to_string(), kind, unit: unit.to_string(), per_ten });
+                }
+                Some("start") => rules.start = number(words.next(), "start")?,
+                Some("step") => rules.step = number(words.next(), "step")?,
+                Some("rest_chance") => rules.rest_chance = number(words.next(), "rest_chance")?.min(100),
+                Some("max_streak") => rules.max_streak = number(words.next(), "max_streak")?,
+                Some(other) => return Err(error(format!("unknown entry {:?}", other))),
+            }
+        }
+
+        for kind in [Kind::Strength, Kind::Cardio].iter() {
+            if !rules.exercises.iter().any(|exercise| exercise.kind == *kind) {
+                return Err(ParseError { line: 0, message: format!("no {} exercise", kind) });
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Set {
+    pub exercise: String,
+    pub amount: u32,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Day {
+    pub day: u32,
+    pub kind: Kind,
+    pub intensity: u32,
+    pub sets: Vec<Set>,
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kind == Kind::Rest {
+            return write!(f, "day {}: Take a break today! Remember to stay hydrated!", self.day);
+        }
+        let sets: Vec<String> = self.sets.iter()
+            .map(|set| format!("{} {} {}", set.amount, set.unit, set.exercise))
+            .collect();
+        write!(f, "day {}: {} at intensity {}: {}", self.day, self.kind, self.intensity, sets.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Planned,
+    Done,
+    Skipped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Planned => "planned",
+            Status::Done => "done",
+            Status::Skipped => "skipped",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub day: u32,
+    pub kind: Kind,
+    pub intensity: u32,
+    pub status: Status,
+}
+
+/// One `Entry` per line: `<day> <kind> <intensity> <planned|done|skipped>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+/// Where the next plan starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Start {
+    day: u32,
+    intensity: u32,
+    streak: u32,
+    last: Option<Kind>,
+}
+
+impl History {
+    pub fn parse(text: &str) -> Result<History, ParseError> {
+        let mut history = History::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseError { line: i + 1, message: message.to_string() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            if words.len() != 4 {
+                return Err(error("expected <day> <kind> <intensity> <status>"));
+            }
+            let status = match words[3] {
+                "planned" => Status::Planned,
+                "done" => Status::Done,
+                "skipped" => Status::Skipped,
+                _ => return Err(error("status must be planned, done or skipped")),
+            };
+            history.entries.push(Entry {
+                day: words[0].parse().map_err(|_| error("day is not a number"))?,
+                kind: Kind::parse(words[1]).ok_or_else(|| error("kind must be rest, strength or cardio"))?,
+                intensity: words[2].parse().map_err(|_| error("intensity is not a number"))?,
+                status,
+            });
+        }
+        Ok(history)
+    }
+
+    /// Marks the first planned day, returns it.
+    pub fn mark(&mut self, status: Status) -> Option<Entry> {
+        let entry = self.entries.iter_mut().find(|entry| entry.status == Status::Planned)?;
+        entry.status = status;
+        Some(*entry)
+    }
+
+    /// Replaces the days still planned with `days`.
+    pub fn replan(&mut self, days: &[Day]) {
+        self.entries.retain(|entry| entry.status != Status::Planned);
+        self.entries.extend(days.iter().map(|day| Entry {
+            day: day.day,
+            kind: day.kind,
+            intensity: day.intensity,
+            status: Status::Planned,
+        }));
+    }
+
+    fn start(&self, rules: &Rules) -> Start {
+        let mut start = Start { day: 1, intensity: rules.start, streak: 0, last: None };
+        for entry in self.entries.iter().filter(|entry| entry.status != Status::Planned) {
+            start.day = entry.day.saturating_add(1);
+            if entry.kind == Kind::Rest {
+                start.streak = 0;
+                continue;
+            }
+            // a skipped day rested the body all the same
+            start.streak = if entry.status == Status::Done { start.streak.saturating_add(1) } else { 0 };
+            start.last = Some(entry.kind);
+            start.intensity = match entry.status {
+                Status::Done => entry.intensity.saturating_add(rules.step),
+                _ => entry.intensity.saturating_sub(rules.step).max(1),
+            };
+        }
+        start
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{} {} {} {}", entry.day, entry.kind, entry.intensity, entry.status)?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks `count` different exercises of `kind`, in the order they were drawn.
+fn pick<'r>(rules: &'r Rules, kind: Kind, count: usize, rng: &mut Rng) -> Vec<&'r Exercise> {
+    let mut candidates: Vec<&Exercise> = rules.exercises.iter().filter(|e| e.kind == kind).collect();
+    let mut picked = vec![];
+    while picked.len() < count && !candidates.is_empty() {
+        let i = rng.below(candidates.len() as u32) as usize;
+        picked.push(candidates.remove(i));
+    }
+    picked
+}
+
+/// The next `days` days after what `history` already has.
+pub fn plan(rules: &Rules, history: &History, days: u32, rng: &mut Rng) -> Vec<Day> {
+    let Start { day: first, mut intensity, mut streak, mut last } = history.start(rules);
+
+    // numbers come from files and flags, they stop at u32::MAX instead of overflowing
+    (first..first.saturating_add(days)).map(|day| {
+        let rest = streak >= rules.max_streak || rng.below(100) < rules.rest_chance;
+        if rest {
+            streak = 0;
+            return Day { day, kind: Kind::Rest, intensity, sets: vec![] };
+        }
+
+        let kind = match last {
+            Some(Kind::Strength) => Kind::Cardio,
+            _ => Kind::Strength,
+        };
+        let count = if kind == Kind::Strength { 2 } else { 1 };
+        let sets = pick(rules, kind, count, rng).into_iter()
+            .map(|exercise| Set {
+                exercise: exercise.name.clone(),
+                amount: u32::try_from(exercise.per_ten as u64 * intensity as u64 / 10).unwrap_or(u32::MAX).max(1),
+                unit: exercise.unit.clone(),
+            })
+            .collect();
+        let planned = Day { day, kind, intensity, sets };
+
+        streak += 1;
+        last = Some(kind);
+        intensity = intensity.saturating_add(rules.step);
+        planned
+    }).collect()
+}
+
+/// `workout plan|done|skip [--days N] [--seed N] [--config <file>] [--history <file>]`
+pub fn run(args: &[String]) {
+    let mut command = "plan";
+    let mut days = 7;
+    let mut seed = None;
+    let mut config = None;
+    let mut history_path = "workout-history.txt".to_string();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next() {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!("{} needs a value", name)),
+        };
+        let parsed = match arg.as_str() {
+            "--days" => value("--days").and_then(|v| v.parse().map(|d| days = d).map_err(|_| "--days is not a number".to_string())),
+            "--seed" => value("--seed").and_then(|v| v.parse().map(|s| seed = Some(s)).map_err(|_| "--seed is not a number".to_string())),
+            "--config" => value("--config").map(|v| config = Some(v)),
+            "--history" => value("--history").map(|v| history_path = v),
+            "plan" | "done" | "skip" => {
+                command = arg;
+                Ok(())
+            }
+            other => Err(format!("unknown argument {:?}", other)),
+        };
+        if let Err(e) = parsed {
+            return eprintln!("workout: {}", e);
+        }
+    }
+
+    let mut history = match std::fs::read_to_string(&history_path) {
+        Ok(text) => match History::parse(&text) {
+            Ok(history) => history,
+            Err(e) => return eprintln!("workout: {}: {}", history_path, e),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => History::default(),
+        Err(e) => return eprintln!("workout: can not read {}: {}", history_path, e),
+    };
+
+    if command == "plan" {
+        let text = match &config {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => return eprintln!("workout: can not read {}: {}", path, e),
+            },
+            None => include_str!("../fixtures/workout.txt").to_string(),
+        };
+        let rules = match Rules::parse(&text) {
+            Ok(rules) => rules,
+            Err(e) => return eprintln!("workout: {}: {}", config.as_deref().unwrap_or("fixtures/workout.txt"), e),
+        };
+        let seed = seed.unwrap_or_else(|| {
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+        });
+        let planned = plan(&rules, &history, days, &mut Rng::new(seed));
+        println!("plan (seed {}):", seed);
+        for day in &planned {
+            println!("  {}", day);
+        }
+        history.replan(&planned);
+    } else {
+        let status = if command == "done" { Status::Done } else { Status::Skipped };
+        match history.mark(status) {
+            Some(entry) => println!("day {} ({} at intensity {}) {}", entry.day, entry.kind, entry.intensity, status),
+            None => return eprintln!("workout: nothing planned, run `workout plan` first"),
+        }
+    }
+
+    if let Err(e) = std::fs::write(&history_path, history.to_string()) {
+        eprintln!("workout: can not save {}: {}", history_path, e);
+    }
+}
+
+#[cfg(test)]
+fn default_rules() -> Rules {
+    Rules::parse(include_str!("../fixtures/workout.txt")).unwrap()
+}
+
+#[test]
+fn test_rng_is_reproducible() {
+    let draw = |seed| {
+        let mut rng = Rng::new(seed);
+        (0..5).map(|_| rng.below(100)).collect::<Vec<_>>()
+    };
+    assert_eq!(draw(42), draw(42));
+    assert_ne!(draw(42), draw(43));
+    // seed 0 must not get stuck at 0
+    assert!(draw(0).iter().any(|&n| n != 0));
+    assert!(draw(7).iter().all(|&n| n < 100));
+}
+
+#[test]
+fn test_plan_is_deterministic() {
+    let rules = default_rules();
+    let first = plan(&rules, &History::default(), 7, &mut Rng::new(42));
+    assert_eq!(first, plan(&rules, &History::default(), 7, &mut Rng::new(42)));
+
+    let lines: Vec<String> = first.iter().map(|day| day.to_string()).collect();
+    assert_eq!(lines, vec![
+        "day 1: strength at intensity 10: 10 reps push-ups, 15 reps squats",
+        "day 2: cardio at intensity 12: 12 minutes run",
+        "day 3: strength at intensity 14: 14 reps sit-ups, 14 reps push-ups",
+        "day 4: cardio at intensity 16: 32 minutes bike",
+        "day 5: Take a break today! Remember to stay hydrated!",
+        "day 6: strength at intensity 18: 18 reps sit-ups, 18 reps push-ups",
+        "day 7: cardio at intensity 20: 40 minutes bike",
+    ]);
+}
+
+#[test]
+fn test_streak_and_rest_chance() {
+    let mut rules = default_rules();
+    rules.rest_chance = 0;
+    rules.max_streak = 3;
+    let kinds: Vec<Kind> = plan(&rules, &History::default(), 8, &mut Rng::new(1)).iter().map(|d| d.kind).collect();
+    assert_eq!(kinds, vec![Kind::Strength, Kind::Cardio, Kind::Strength, Kind::Rest,
+                           Kind::Cardio, Kind::Strength, Kind::Cardio, Kind::Rest]);
+
+    rules.rest_chance = 100;
+    assert!(plan(&rules, &History::default(), 5, &mut Rng::new(1)).iter().all(|d| d.kind == Kind::Rest));
+}
+
+#[test]
+fn test_next_plan_adapts_to_history() {
+    let mut rules = default_rules();
+    rules.rest_chance = 0;
+    let mut history = History::default();
+    let planned = plan(&rules, &history, 3, &mut Rng::new(42));
+    history.replan(&planned);
+    assert_eq!(history.mark(Status::Done).map(|e| e.day), Some(1));
+    assert_eq!(history.mark(Status::Skipped).map(|e| e.day), Some(2));
+
+    // day 3 was only planned: planned again, after the skipped cardio day at 12
+    let next = plan(&rules, &history, 2, &mut Rng::new(5));
+    assert_eq!((next[0].day, next[0].kind, next[0].intensity), (3, Kind::Strength, 10));
+    history.replan(&next);
+    assert_eq!(history.to_string(), "1 strength 10 done\n2 cardio 12 skipped\n3 strength 10 planned\n4 cardio 12 planned\n");
+    assert_eq!(History::parse(&history.to_string()).unwrap(), history);
+
+    history.mark(Status::Done);
+    history.mark(Status::Done);
+    // 4 training days in a row with day 5 and 6, the 7th must be a rest
+    let next = plan(&rules, &history, 3, &mut Rng::new(5));
+    assert_eq!(next.iter().map(|d| (d.day, d.kind, d.intensity)).collect::<Vec<_>>(),
+               vec![(5, Kind::Strength, 14), (6, Kind::Cardio, 16), (7, Kind::Rest, 18)]);
+}
+
+#[test]
+fn test_huge_numbers_saturate() {
+    let mut rules = Rules::parse("exercise push-ups strength reps 500000000\nexercise run cardio minutes 4294967295\n\
+                                  start 4000000000\nstep 4294967295\nrest_chance 0\nmax_streak 4294967295").unwrap();
+    let days = plan(&rules, &History::default(), 3, &mut Rng::new(7));
+    assert_eq!(days.iter().map(|d| d.intensity).collect::<Vec<_>>(), vec![4_000_000_000, u32::MAX, u32::MAX]);
+    assert_eq!(days[0].sets[0].amount, u32::MAX);
+    assert!(days.iter().flat_map(|d| d.sets.iter()).all(|set| set.amount == u32::MAX));
+
+    // the last day there is, done at the highest intensity: the plan just ends there
+    rules.step = 1;
+    let history = History::parse("4294967294 strength 4294967295 done\n4294967295 cardio 4294967295 done\n").unwrap();
+    assert!(plan(&rules, &history, u32::MAX, &mut Rng::new(7)).is_empty());
+    let history = History::parse("4294967290 strength 4294967295 done\n").unwrap();
+    let days = plan(&rules, &history, u32::MAX, &mut Rng::new(7));
+    assert_eq!(days.iter().map(|d| d.day).collect::<Vec<_>>(), vec![4_294_967_291, 4_294_967_292, 4_294_967_293, 4_294_967_294]);
+    assert!(days.iter().all(|d| d.intensity == u32::MAX));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(Rules::parse("exercise run cardio minutes ten").unwrap_err().to_string(),
+               "line 1: amount is not a number: \"ten\"");
+    assert_eq!(Rules::parse("exercise nap rest minutes 1").unwrap_err().message,
+               "kind must be strength or cardio, not \"rest\"");
+    assert_eq!(Rules::parse("exercise run cardio minutes 10").unwrap_err().message, "no strength exercise");
+    assert_eq!(Rules::parse("\nrounds 3").unwrap_err(), ParseError { line: 2, message: "unknown entry \"rounds\"".to_string() });
+    assert_eq!(History::parse("1 strength 10").unwrap_err().message, "expected <day> <kind> <intensity> <status>");
+    assert_eq!(History::parse("1 yoga 10 done").unwrap_err().message, "kind must be rest, strength or cardio");
+}
@@ -1,3 +1,7 @@
+// the notes below are `///` like in the lessons, and `Guess::new` keeps the book's comparison
+#![allow(unused_doc_comments, clippy::empty_line_after_doc_comments, clippy::doc_lazy_continuation,
+         clippy::manual_range_contains)]
+
 /// NOTE: When you run multiple tests, by default they run in parallel using threads.
 /// if you don't want tests run in parallel use following command
 /// > cargo test -- --test-threads=1